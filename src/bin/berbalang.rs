@@ -1,7 +1,6 @@
 use berbalib::configure::{Config, Job};
 use berbalib::examples::{hello_world, linear_gp};
-use berbalib::{limit_threads, logger, roper, set_starting_timestamp, set_timeout};
-
+use berbalib::{cluster, limit_threads, logger, roper, set_starting_timestamp, set_timeout};
 
 fn main() {
     coredump::register_panic_handler().expect("Failed to register panic handler.");
@@ -10,7 +9,7 @@ fn main() {
         .nth(1)
         .unwrap_or_else(|| "./config.toml".to_string());
     let population_name = std::env::args().nth(2);
-    let mut config = Config::from_path(&config_file, population_name)
+    let mut config = Config::from_path(&config_file, population_name.clone())
         .unwrap_or_else(|e| panic!("Failed to generate Config from {:?}: {:?}", &config_file, e));
     logger::init(&config.observer.population_name);
     set_starting_timestamp();
@@ -29,6 +28,17 @@ fn main() {
         limit_threads(1, &mut config);
     }
 
+    if let Some(island) = cluster::island_from_env() {
+        cluster::join(&config, island)
+            .unwrap_or_else(|e| panic!("Island {} failed to join the cluster: {:?}", island, e));
+    } else if config.cluster.coordinator.is_some() {
+        let args = std::iter::once(config_file)
+            .chain(population_name)
+            .collect::<Vec<String>>();
+        cluster::coordinate(&config, &args).expect("Failed to coordinate the cluster");
+        return;
+    }
+
     match config.job {
         Job::LinearGp => {
             linear_gp::run(config);
@@ -37,7 +47,7 @@ fn main() {
             hello_world::run(config);
         }
         Job::Roper => {
-            roper::run(config);
+            roper::run(config).unwrap_or_else(|e| panic!("Failed to start the roper job: {:?}", e));
         }
     }
 
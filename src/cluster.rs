@@ -0,0 +1,578 @@
+//! Islands can be run as separate processes, to spare a single process the
+//! allocator and mmap contention of many Unicorn emulators. When the config
+//! names a `cluster.coordinator` address, `berbalang` starts as the
+//! coordinator: it listens on that address and spawns an island process for
+//! each of the `num_islands` islands, telling each which island it is through
+//! the `BERBALANG_ISLAND` environment variable.
+//!
+//! The islands and the coordinator exchange JSON lines. The coordinator
+//! relays migrants between islands, keeps the global epoch counter,
+//! broadcasts the order to stop once any island has found a champion or run
+//! its course, and gathers the statistics logged by every island into
+//! `cluster_<name>_statistics.csv` files.
+use std::collections::VecDeque;
+use std::fs::OpenOptions;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::process::{Child, Command};
+use std::str::FromStr;
+use std::sync::atomic;
+use std::sync::mpsc::channel;
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread::{sleep, spawn};
+use std::time::{Duration, Instant};
+
+use hashbrown::HashMap;
+use serde::{Deserialize, Serialize};
+
+use crate::configure::Config;
+use crate::error::Error;
+
+/// The environment variable through which the coordinator tells each
+/// island process which island it runs.
+pub const ISLAND_VAR: &str = "BERBALANG_ISLAND";
+
+/// How long the coordinator waits for every island to join the cluster.
+const JOIN_TIMEOUT: Duration = Duration::from_secs(300);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Address {
+    Unix(String),
+    Tcp(String),
+}
+
+impl FromStr for Address {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(path) = s.strip_prefix("unix:") {
+            Ok(Self::Unix(path.to_string()))
+        } else if let Some(addr) = s.strip_prefix("tcp:") {
+            Ok(Self::Tcp(addr.to_string()))
+        } else {
+            Err(Error::Parsing(format!(
+                "Expected an address of the form unix:<path> or tcp:<host>:<port>, found {:?}",
+                s
+            )))
+        }
+    }
+}
+
+enum Stream {
+    Unix(UnixStream),
+    Tcp(TcpStream),
+}
+
+impl Stream {
+    fn connect(address: &Address) -> io::Result<Self> {
+        match address {
+            Address::Unix(path) => UnixStream::connect(path).map(Self::Unix),
+            Address::Tcp(addr) => TcpStream::connect(addr).map(Self::Tcp),
+        }
+    }
+
+    fn try_clone(&self) -> io::Result<Self> {
+        match self {
+            Self::Unix(s) => s.try_clone().map(Self::Unix),
+            Self::Tcp(s) => s.try_clone().map(Self::Tcp),
+        }
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        match self {
+            Self::Unix(s) => s.set_nonblocking(nonblocking),
+            Self::Tcp(s) => s.set_nonblocking(nonblocking),
+        }
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Self::Unix(s) => s.set_read_timeout(timeout),
+            Self::Tcp(s) => s.set_read_timeout(timeout),
+        }
+    }
+
+    fn send(&mut self, message: &Message) -> Result<(), Error> {
+        let mut line = serde_json::to_string(message)?;
+        line.push('\n');
+        self.write_all(line.as_bytes())?;
+        self.flush()?;
+        Ok(())
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::Unix(s) => s.read(buf),
+            Self::Tcp(s) => s.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Unix(s) => s.write(buf),
+            Self::Tcp(s) => s.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Unix(s) => s.flush(),
+            Self::Tcp(s) => s.flush(),
+        }
+    }
+}
+
+enum Listener {
+    Unix(UnixListener),
+    Tcp(TcpListener),
+}
+
+impl Listener {
+    fn bind(address: &Address) -> io::Result<Self> {
+        match address {
+            Address::Unix(path) => {
+                // clear away the socket left by an earlier run
+                if Path::new(path).exists() {
+                    std::fs::remove_file(path)?;
+                }
+                UnixListener::bind(path).map(Self::Unix)
+            }
+            Address::Tcp(addr) => TcpListener::bind(addr).map(Self::Tcp),
+        }
+    }
+
+    fn accept(&self) -> io::Result<Stream> {
+        match self {
+            Self::Unix(l) => l.accept().map(|(s, _)| Stream::Unix(s)),
+            Self::Tcp(l) => l.accept().map(|(s, _)| Stream::Tcp(s)),
+        }
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        match self {
+            Self::Unix(l) => l.set_nonblocking(nonblocking),
+            Self::Tcp(l) => l.set_nonblocking(nonblocking),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Message {
+    /// The first message an island sends, naming itself.
+    Hello {
+        island: usize,
+    },
+    /// A creature, serialized as JSON, bound for the island `to`.
+    Migrant {
+        from: usize,
+        to: usize,
+        creature: String,
+    },
+    /// Sent by an island in place of incrementing its own epoch counter.
+    IncrementEpoch,
+    /// The new value of the global epoch counter.
+    Epoch {
+        epoch: usize,
+    },
+    Stop {
+        island: usize,
+        champion: bool,
+    },
+    /// A row of one of an island's statistics files.
+    Record {
+        island: usize,
+        name: String,
+        header: String,
+        row: String,
+    },
+}
+
+/// An island process's connection to the coordinator.
+pub struct Link {
+    island: usize,
+    writer: Mutex<Stream>,
+    migrants: Mutex<VecDeque<String>>,
+}
+
+static LINK: RwLock<Option<Arc<Link>>> = RwLock::new(None);
+
+impl Link {
+    pub fn island(&self) -> usize {
+        self.island
+    }
+
+    pub fn send(&self, message: &Message) {
+        let mut writer = self.writer.lock().expect("poisoned cluster link");
+        if let Err(e) = writer.send(message) {
+            log::error!("Failed to send {:?} to the coordinator: {:?}", message, e);
+        }
+    }
+
+    /// The next migrant to have arrived from another process, as JSON.
+    pub fn take_migrant(&self) -> Option<String> {
+        self.migrants
+            .lock()
+            .expect("poisoned migrant queue")
+            .pop_front()
+    }
+}
+
+/// The link to the coordinator, if this process is one island of a cluster.
+pub fn link() -> Option<Arc<Link>> {
+    LINK.read().expect("poisoned cluster link").clone()
+}
+
+/// The island this process runs, if it was spawned by a coordinator.
+pub fn island_from_env() -> Option<usize> {
+    std::env::var(ISLAND_VAR).ok()?.parse().ok()
+}
+
+fn coordinator_address(config: &Config) -> Result<Address, Error> {
+    config
+        .cluster
+        .coordinator
+        .as_ref()
+        .ok_or_else(|| Error::MissingKey("cluster.coordinator".to_string()))?
+        .parse()
+}
+
+/// Connects this process, as the given island, to the coordinator, and
+/// starts listening for migrants and orders.
+pub fn join(config: &Config, island: usize) -> Result<(), Error> {
+    let link = connect(&coordinator_address(config)?, island)?;
+    *LINK.write().expect("poisoned cluster link") = Some(link);
+    Ok(())
+}
+
+/// Connects to the coordinator at the given address, as the given island,
+/// and spawns a thread that queues the migrants it relays and obeys its
+/// orders.
+fn connect(address: &Address, island: usize) -> Result<Arc<Link>, Error> {
+    let mut stream = Stream::connect(address)?;
+    stream.send(&Message::Hello { island })?;
+    let reader = BufReader::new(stream.try_clone()?);
+    let link = Arc::new(Link {
+        island,
+        writer: Mutex::new(stream),
+        migrants: Mutex::new(VecDeque::new()),
+    });
+
+    let listening = link.clone();
+    spawn(move || {
+        let link = listening;
+        for line in reader.lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(e) => {
+                    log::error!("Error reading from the coordinator: {:?}", e);
+                    break;
+                }
+            };
+            match serde_json::from_str(&line) {
+                Ok(Message::Migrant { creature, .. }) => link
+                    .migrants
+                    .lock()
+                    .expect("poisoned migrant queue")
+                    .push_back(creature),
+                Ok(Message::Epoch { epoch }) => {
+                    crate::EPOCH_COUNTER.store(epoch, atomic::Ordering::Relaxed)
+                }
+                Ok(Message::Stop { island, champion }) => crate::stop_everything(island, champion),
+                Ok(message) => log::warn!("Unexpected message from the coordinator: {:?}", message),
+                Err(e) => log::error!("Failed to parse message from the coordinator: {:?}", e),
+            }
+        }
+        log::warn!("Island {} lost its connection to the coordinator", island);
+        crate::stop_everything(island, false);
+    });
+    Ok(link)
+}
+
+/// Appends a row of an island's statistics to the cluster-wide file of the
+/// same name, prefixed with the island's id.
+fn append_record(
+    directory: &str,
+    island: usize,
+    name: &str,
+    header: &str,
+    row: &str,
+) -> Result<(), Error> {
+    let filename = format!("{}/cluster_{}_statistics.csv", directory, name);
+    let msg = if Path::new(&filename).exists() {
+        format!("{},{}\n", island, row)
+    } else {
+        format!("island,{}\n{},{}\n", header, island, row)
+    };
+    OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(&filename)?
+        .write_all(msg.as_bytes())?;
+    Ok(())
+}
+
+fn broadcast(writers: &mut HashMap<usize, Stream>, message: &Message) {
+    for (island, writer) in writers.iter_mut() {
+        if let Err(e) = writer.send(message) {
+            log::debug!("Failed to send {:?} to island {}: {:?}", message, island, e);
+        }
+    }
+}
+
+/// Runs the coordinator: spawns an island process for each island, passing
+/// it `args`, and then relays messages among them until every island has
+/// disconnected.
+pub fn coordinate(config: &Config, args: &[String]) -> Result<(), Error> {
+    let listener = Listener::bind(&coordinator_address(config)?)?;
+    let num_islands = config.num_islands.max(1);
+
+    // The cluster's statistics are kept beside the islands' directories.
+    let mut local = config.clone();
+    local.set_data_directory();
+    let directory = Path::new(&local.data_directory())
+        .parent()
+        .map(|p| p.to_string_lossy().to_string())
+        .unwrap_or_else(|| ".".to_string());
+
+    let exe = std::env::current_exe()?;
+    let mut children = vec![];
+    for i in 0..num_islands {
+        let spawned = Command::new(&exe)
+            .args(args)
+            .env(ISLAND_VAR, i.to_string())
+            .spawn();
+        match spawned {
+            Ok(child) => children.push(child),
+            Err(e) => {
+                kill_all(&mut children);
+                return Err(e.into());
+            }
+        }
+    }
+
+    if let Err(e) = relay(&listener, num_islands, &directory, &mut children) {
+        log::error!("The coordinator failed, stopping every island: {:?}", e);
+        kill_all(&mut children);
+        return Err(e);
+    }
+
+    for mut child in children.into_iter() {
+        child.wait()?;
+    }
+    Ok(())
+}
+
+fn kill_all(children: &mut [Child]) {
+    for child in children.iter_mut() {
+        // An island that has already exited can't be killed, which is fine.
+        let _ = child.kill();
+        let _ = child.wait();
+    }
+}
+
+/// Accepts the next island to connect. Gives up if any island that has not
+/// yet joined exits first, or if the deadline passes. `children` are the
+/// island processes, indexed by island.
+fn accept_island(
+    listener: &Listener,
+    children: &mut [Child],
+    joined: &HashMap<usize, Stream>,
+    deadline: Instant,
+) -> Result<Stream, Error> {
+    listener.set_nonblocking(true)?;
+    loop {
+        match listener.accept() {
+            Ok(stream) => {
+                stream.set_nonblocking(false)?;
+                return Ok(stream);
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+            Err(e) => return Err(e.into()),
+        }
+        for (island, child) in children.iter_mut().enumerate() {
+            if joined.contains_key(&island) {
+                continue;
+            }
+            if let Some(status) = child.try_wait()? {
+                return Err(Error::Misc(format!(
+                    "Island {} exited with {} before joining the cluster",
+                    island, status
+                )));
+            }
+        }
+        if Instant::now() > deadline {
+            return Err(Error::Misc(format!(
+                "Only {} islands joined the cluster within {:?}",
+                joined.len(),
+                JOIN_TIMEOUT
+            )));
+        }
+        sleep(Duration::from_millis(50));
+    }
+}
+
+/// Accepts the given number of islands, and relays messages among them until
+/// every one has disconnected. Statistics are gathered into `directory`.
+/// `children` are the island processes, if the coordinator spawned them,
+/// which are watched in case they die before joining.
+fn relay(
+    listener: &Listener,
+    num_islands: usize,
+    directory: &str,
+    children: &mut [Child],
+) -> Result<(), Error> {
+    // Each island's messages are passed along to the main loop, followed
+    // by `None` once it disconnects.
+    let (tx, rx) = channel::<(usize, Option<Message>)>();
+    let mut writers: HashMap<usize, Stream> = HashMap::new();
+    let deadline = Instant::now() + JOIN_TIMEOUT;
+    for _ in 0..num_islands {
+        let stream = accept_island(listener, children, &writers, deadline)?;
+        // An island that connects but never greets us mustn't hang the cluster.
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        stream.set_read_timeout(Some(timeout.max(Duration::from_millis(1))))?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut greeting = String::new();
+        reader.read_line(&mut greeting)?;
+        stream.set_read_timeout(None)?;
+        let island = match serde_json::from_str(&greeting)? {
+            Message::Hello { island } => island,
+            message => {
+                return Err(Error::Misc(format!(
+                    "Expected a greeting from an island, but received {:?}",
+                    message
+                )))
+            }
+        };
+        log::info!("Island {} has joined the cluster", island);
+        writers.insert(island, stream);
+        let tx = tx.clone();
+        spawn(move || {
+            for line in reader.lines() {
+                let message = match line.map(|l| serde_json::from_str(&l)) {
+                    Ok(Ok(message)) => message,
+                    Ok(Err(e)) => {
+                        log::error!("Failed to parse message from island {}: {:?}", island, e);
+                        continue;
+                    }
+                    Err(_) => break,
+                };
+                if tx.send((island, Some(message))).is_err() {
+                    return;
+                }
+            }
+            let _ = tx.send((island, None));
+        });
+    }
+    drop(tx);
+
+    let mut connected = num_islands;
+    let mut epoch = 0;
+    let mut stopped = false;
+    while connected > 0 {
+        let (sender, message) = rx.recv()?;
+        match message {
+            None => {
+                log::info!("Island {} has left the cluster", sender);
+                connected -= 1;
+            }
+            Some(Message::Migrant { from, to, creature }) => match writers.get_mut(&to) {
+                Some(writer) => {
+                    log::debug!("Relaying a migrant from island {} to island {}", from, to);
+                    if let Err(e) = writer.send(&Message::Migrant { from, to, creature }) {
+                        log::debug!("Failed to relay migrant to island {}: {:?}", to, e);
+                    }
+                }
+                None => log::warn!("Migrant bound for unknown island {}", to),
+            },
+            Some(Message::IncrementEpoch) => {
+                epoch += 1;
+                crate::EPOCH_COUNTER.store(epoch, atomic::Ordering::Relaxed);
+                broadcast(&mut writers, &Message::Epoch { epoch });
+            }
+            Some(Message::Stop { island, champion }) => {
+                if !stopped {
+                    stopped = true;
+                    crate::stop_everything(island, champion);
+                    broadcast(&mut writers, &Message::Stop { island, champion });
+                }
+            }
+            Some(Message::Record {
+                island,
+                name,
+                header,
+                row,
+            }) => {
+                if let Err(e) = append_record(directory, island, &name, &header, &row) {
+                    log::error!("Failed to record {} statistics: {:?}", name, e);
+                }
+            }
+            Some(message) => log::warn!("Unexpected message from island {}: {:?}", sender, message),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_address() {
+        assert_eq!(
+            "unix:/tmp/berbalang.sock".parse::<Address>().unwrap(),
+            Address::Unix("/tmp/berbalang.sock".to_string())
+        );
+        assert_eq!(
+            "tcp:127.0.0.1:7878".parse::<Address>().unwrap(),
+            Address::Tcp("127.0.0.1:7878".to_string())
+        );
+        assert!("127.0.0.1:7878".parse::<Address>().is_err());
+    }
+
+    #[test]
+    fn test_relay_migrant() {
+        let path = std::env::temp_dir().join(format!("berbalang_{}.sock", std::process::id()));
+        let address = Address::Unix(path.to_string_lossy().to_string());
+        let listener = Listener::bind(&address).unwrap();
+        let directory = std::env::temp_dir().to_string_lossy().to_string();
+        spawn(move || relay(&listener, 2, &directory, &mut []));
+
+        let island_0 = connect(&address, 0).unwrap();
+        let island_1 = connect(&address, 1).unwrap();
+        island_0.send(&Message::Migrant {
+            from: 0,
+            to: 1,
+            creature: "\"voyager\"".to_string(),
+        });
+
+        let mut migrant = None;
+        for _ in 0..500 {
+            migrant = island_1.take_migrant();
+            if migrant.is_some() {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert_eq!(migrant.as_deref(), Some("\"voyager\""));
+        assert!(island_0.take_migrant().is_none());
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn test_island_dies_before_joining() {
+        let path = std::env::temp_dir().join(format!("berbalang_dead_{}.sock", std::process::id()));
+        let address = Address::Unix(path.to_string_lossy().to_string());
+        let listener = Listener::bind(&address).unwrap();
+        let directory = std::env::temp_dir().to_string_lossy().to_string();
+        let mut children = vec![Command::new("true").spawn().unwrap()];
+        assert!(relay(&listener, 1, &directory, &mut children).is_err());
+        let _ = std::fs::remove_file(path);
+    }
+}
@@ -1,13 +1,20 @@
 use std::cmp::Ordering;
+use std::fmt;
 use std::fmt::Debug;
 use std::path::Path;
+use std::sync::Arc;
 
 use chrono::prelude::*;
 use hashbrown::{HashMap, HashSet};
 use serde::{Deserialize, Serialize};
 
+use crate::emulator::loader::get_static_memory_image;
+use crate::emulator::memory_pattern::{parse_memory_pattern_file, MemoryPattern};
+use crate::emulator::reachability::TargetDistances;
 use crate::emulator::register_pattern::{parse_register_pattern_file, RegisterPattern};
+use crate::emulator::stages::{parse_stage_file, Stage};
 use crate::error::Error;
+use crate::fitness::Comparison;
 
 #[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct DataConfig {
@@ -39,8 +46,34 @@ fn default_one() -> f64 {
     1.0
 }
 
-fn default_crossover_algorithm() -> String {
-    "alternating".to_string()
+/// How the chromosomes of mates are recombined. In the config, the names are
+/// written in snake case, e.g. `crossover_algorithm = "two_point"`.
+#[derive(Clone, Debug, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CrossoverAlgorithm {
+    /// A prefix of one parent joined to a suffix of another.
+    OnePoint,
+    /// A segment of one parent spliced into the middle of another.
+    TwoPoint,
+    /// Each locus taken from a randomly chosen parent.
+    Uniform,
+    /// Runs of exponentially distributed length, taken from the parents in
+    /// turn.
+    Alternating,
+    /// One-point crossover, where the second parent is cut at the locus
+    /// homologous to the first parent's cut: the nearest locus holding the
+    /// same gadget address, or else the gadget executed at the same
+    /// position in the chain.
+    Homologous,
+    /// One-point crossover that only cuts before words that were executed
+    /// as gadgets, i.e. at return boundaries, in the parents' profiles.
+    GadgetBoundary,
+}
+
+impl Default for CrossoverAlgorithm {
+    fn default() -> Self {
+        Self::Alternating
+    }
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize, Default)]
@@ -54,8 +87,8 @@ pub struct Config {
     #[serde(default)]
     pub island_id: usize,
     pub crossover_period: f64,
-    #[serde(default = "default_crossover_algorithm")]
-    pub crossover_algorithm: String,
+    #[serde(default)]
+    pub crossover_algorithm: CrossoverAlgorithm,
     pub crossover_rate: f64,
     #[serde(default)]
     pub data: DataConfig,
@@ -88,6 +121,22 @@ pub struct Config {
     pub random_seed: u64,
     #[serde(default)]
     pub push_vm: PushVm,
+    /// If present, a `novelty` factor is added to each creature's fitness.
+    pub novelty: Option<NoveltyConfig>,
+    #[serde(default)]
+    pub map_elites: MapElitesConfig,
+    #[serde(default)]
+    pub migration: MigrationConfig,
+    #[serde(default)]
+    pub cluster: ClusterConfig,
+    #[serde(default)]
+    pub metropolis: MetropolisConfig,
+    #[serde(default)]
+    pub alps: AlpsConfig,
+    /// If present, each creature carries its own mutation parameters, and
+    /// mutation operators are chosen by the credit they have earned.
+    #[serde(default)]
+    pub self_adaptation: Option<SelfAdaptationConfig>,
 }
 
 fn default_tournament_size() -> usize {
@@ -107,15 +156,104 @@ pub struct PushVm {
     pub literal_rate: f64,
 }
 
+/// One fitness function, or several. In a list, an entry of the form
+/// `"prefix:function"` has its factors renamed to `prefix_factor`, so that
+/// functions emitting the same factors can be used together:
+///
+/// ```toml
+/// function = ["register_pattern", "mem:memory_pattern", "code_coverage"]
+/// ```
+///
+/// Whether one function or several, each creature records each thing
+/// (its digrams, its memory writes, a register state, an address visited)
+/// in the frequency sketches at most once. A thing that recurs within a
+/// single profile, such as a register state seen at several boundaries,
+/// counts once towards its frequency, not once per occurrence.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(untagged)]
+pub enum FitnessFunctionList {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl Default for FitnessFunctionList {
+    fn default() -> Self {
+        Self::One(String::new())
+    }
+}
+
+impl From<&str> for FitnessFunctionList {
+    fn from(name: &str) -> Self {
+        Self::One(name.to_string())
+    }
+}
+
+impl fmt::Display for FitnessFunctionList {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::One(name) => write!(f, "{}", name),
+            Self::Many(names) => write!(f, "[{}]", names.join(", ")),
+        }
+    }
+}
+
+/// A fitness function named in the config, and the prefix, if any, to be
+/// given to its factors.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FitnessComponent {
+    pub prefix: Option<String>,
+    pub name: String,
+}
+
+impl FitnessFunctionList {
+    pub fn components(&self) -> Vec<FitnessComponent> {
+        let names = match self {
+            Self::One(name) => std::slice::from_ref(name),
+            Self::Many(names) => names.as_slice(),
+        };
+        names
+            .iter()
+            .map(|entry| match entry.find(':') {
+                Some(i) => FitnessComponent {
+                    prefix: Some(entry[..i].trim().to_string()),
+                    name: entry[i + 1..].trim().to_string(),
+                },
+                None => FitnessComponent {
+                    prefix: None,
+                    name: entry.trim().to_string(),
+                },
+            })
+            .collect()
+    }
+}
+
 #[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct FitnessConfig {
     pub target: f64,
     pub eval_by_case: bool,
+    /// Whether the fitness of a genome may change over time, as it does when
+    /// it depends on the sketches or the novelty archive. If not, cached
+    /// fitness scores are reused.
     pub dynamic: bool,
+    /// The number of evaluations to cache, by chromosome. Zero disables
+    /// the cache.
+    #[serde(default)]
+    pub cache_size: usize,
     #[serde(default)]
     priority: String,
-    pub function: String,
+    /// The fitness function, or a list of fitness functions whose factors
+    /// are merged.
+    pub function: FitnessFunctionList,
     pub weighting: String,
+    /// How creatures' fitness maps are compared in selection.
+    #[serde(default)]
+    pub comparison: Comparison,
+    /// The objectives used by Pareto and lexicographic comparison, in order
+    /// of precedence, and as the cases of lexicase selection. If empty, all
+    /// objectives are compared, and the cases are the factors of the
+    /// weighting expression.
+    #[serde(default)]
+    pub objectives: Vec<String>,
 }
 
 impl FitnessConfig {
@@ -126,6 +264,17 @@ impl FitnessConfig {
             &self.priority
         }
     }
+
+    /// Checks that the weighting and priority expressions refer only to
+    /// the given factors and to the variables and functions of the
+    /// expression language.
+    pub fn validate_expressions(&self, factors: &[&str]) -> Result<(), Error> {
+        crate::util::expression::validate(&self.weighting, factors)?;
+        if !self.priority.is_empty() {
+            crate::util::expression::validate(&self.priority, factors)?;
+        }
+        Ok(())
+    }
 }
 
 #[derive(Clone, Debug, Default, Serialize, Deserialize)]
@@ -136,6 +285,391 @@ pub struct TournamentConfig {
     pub migration_rate: f64,
     pub num_offspring: usize,
     pub num_parents: usize,
+    /// The number of tournaments whose combatants are developed together,
+    /// to keep the emulators busy.
+    #[serde(default = "default_pipeline_depth")]
+    pub pipeline_depth: usize,
+    /// If present, the geographic radius is adjusted in response to the
+    /// genetic diversity of the combatants.
+    pub adaptive_radius: Option<AdaptiveRadiusConfig>,
+}
+
+fn default_pipeline_depth() -> usize {
+    1
+}
+
+fn default_diversity_tolerance() -> f64 {
+    0.05
+}
+
+fn default_diversity_window() -> usize {
+    64
+}
+
+fn default_radius_step() -> usize {
+    1
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AdaptiveRadiusConfig {
+    pub min_radius: usize,
+    pub max_radius: usize,
+    /// The desired ratio of distinct digrams to all digrams in the
+    /// combatants' genomes.
+    pub target_diversity: f64,
+    /// How far diversity may stray from the target before the radius is
+    /// adjusted.
+    #[serde(default = "default_diversity_tolerance")]
+    pub tolerance: f64,
+    /// The number of tournaments over which diversity is measured.
+    #[serde(default = "default_diversity_window")]
+    pub window: usize,
+    /// The amount by which the radius is shrunk or widened.
+    #[serde(default = "default_radius_step")]
+    pub step: usize,
+}
+
+/// The shape of the graph along which creatures migrate between islands.
+/// Each island sends its emigrants only to its neighbours.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum Topology {
+    /// Each island sends to the next, and the last to the first.
+    Ring,
+    /// The islands are laid out on a grid whose edges wrap around, and each
+    /// sends to the islands above, below, left and right of it. The grid is
+    /// the most nearly square one whose cells number exactly `num_islands`,
+    /// so a prime number of islands makes a single row: a ring in which each
+    /// island sends in both directions.
+    TorusGrid,
+    /// Each island sends to every other.
+    FullyConnected,
+    /// Each island sends to, and receives from, `degree` others, drawn at
+    /// random from the run's seed.
+    RandomRegular,
+    /// Island 0 sends to every other, and they send only to island 0.
+    Star,
+}
+
+impl Default for Topology {
+    fn default() -> Self {
+        Self::FullyConnected
+    }
+}
+
+/// Which creature an island sends abroad. Emigrants are copies, and the
+/// original stays at home.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum MigrationPolicy {
+    /// The fittest candidate, by the `priority` fitness expression.
+    Best,
+    Random,
+    /// The candidate whose genome is made of the rarest digrams.
+    MostNovel,
+}
+
+impl Default for MigrationPolicy {
+    fn default() -> Self {
+        Self::Random
+    }
+}
+
+/// Which resident an immigrant replaces.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum ReplacementPolicy {
+    /// The least fit candidate, by the `priority` fitness expression.
+    Worst,
+    Random,
+}
+
+impl Default for ReplacementPolicy {
+    fn default() -> Self {
+        Self::Worst
+    }
+}
+
+fn default_migration_degree() -> usize {
+    2
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MigrationConfig {
+    #[serde(default)]
+    pub topology: Topology,
+    /// The number of neighbours of each island in a `RandomRegular` topology.
+    #[serde(default = "default_migration_degree")]
+    pub degree: usize,
+    #[serde(default)]
+    pub policy: MigrationPolicy,
+    #[serde(default)]
+    pub replacement: ReplacementPolicy,
+}
+
+impl Default for MigrationConfig {
+    fn default() -> Self {
+        Self {
+            topology: Topology::default(),
+            degree: default_migration_degree(),
+            policy: MigrationPolicy::default(),
+            replacement: ReplacementPolicy::default(),
+        }
+    }
+}
+
+fn default_learning_rate() -> f64 {
+    0.2
+}
+
+fn default_min_rate() -> f64 {
+    0.001
+}
+
+fn default_max_rate() -> f64 {
+    1.0
+}
+
+fn default_min_exponent() -> f64 {
+    0.5
+}
+
+fn default_max_exponent() -> f64 {
+    20.0
+}
+
+fn default_credit_decay() -> f64 {
+    0.99
+}
+
+fn default_min_operator_weight() -> f64 {
+    0.05
+}
+
+/// Settings for self-adaptive mutation. See the `evolution::adaptation`
+/// module.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SelfAdaptationConfig {
+    /// The standard deviation of the log-normal perturbation of each
+    /// offspring's mutation rate and exponent.
+    #[serde(default = "default_learning_rate")]
+    pub learning_rate: f64,
+    #[serde(default = "default_min_rate")]
+    pub min_rate: f64,
+    #[serde(default = "default_max_rate")]
+    pub max_rate: f64,
+    #[serde(default = "default_min_exponent")]
+    pub min_exponent: f64,
+    #[serde(default = "default_max_exponent")]
+    pub max_exponent: f64,
+    /// The factor by which each operator's record of successes and trials
+    /// is discounted whenever an offspring is credited.
+    #[serde(default = "default_credit_decay")]
+    pub credit_decay: f64,
+    /// No operator's weight falls below this.
+    #[serde(default = "default_min_operator_weight")]
+    pub min_operator_weight: f64,
+}
+
+/// How the temperature of a Metropolis chain falls as the run goes on,
+/// where `k` is the number of steps taken and `T0` a chain's initial
+/// temperature.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum CoolingSchedule {
+    /// The temperature stays at `T0`, as in plain Metropolis-Hastings.
+    Constant,
+    /// `T0 * cooling_rate^k`
+    Exponential,
+    /// `T0 - cooling_rate * k`
+    Linear,
+    /// `T0 / (1 + cooling_rate * ln(1 + k))`
+    Logarithmic,
+}
+
+impl Default for CoolingSchedule {
+    fn default() -> Self {
+        Self::Exponential
+    }
+}
+
+fn default_initial_temperature() -> f64 {
+    1.0
+}
+
+fn default_min_temperature() -> f64 {
+    1e-3
+}
+
+fn default_cooling_rate() -> f64 {
+    0.999
+}
+
+fn default_replicas() -> usize {
+    1
+}
+
+fn default_temperature_ladder() -> f64 {
+    2.0
+}
+
+fn default_swap_interval() -> usize {
+    10
+}
+
+/// Settings for the Metropolis engine. With more than one replica, the
+/// engine runs parallel tempering: the replicas' chains run at a ladder of
+/// temperatures, and neighbouring chains periodically propose to swap
+/// their specimens.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MetropolisConfig {
+    #[serde(default = "default_initial_temperature")]
+    pub initial_temperature: f64,
+    /// No chain is ever cooled below this temperature.
+    #[serde(default = "default_min_temperature")]
+    pub min_temperature: f64,
+    #[serde(default)]
+    pub cooling: CoolingSchedule,
+    #[serde(default = "default_cooling_rate")]
+    pub cooling_rate: f64,
+    /// The number of chains run on each island.
+    #[serde(default = "default_replicas")]
+    pub replicas: usize,
+    /// The ratio of the initial temperatures of neighbouring replicas. The
+    /// coldest replica starts at `initial_temperature`.
+    #[serde(default = "default_temperature_ladder")]
+    pub temperature_ladder: f64,
+    /// The number of steps between rounds of swap proposals.
+    #[serde(default = "default_swap_interval")]
+    pub swap_interval: usize,
+}
+
+impl Default for MetropolisConfig {
+    fn default() -> Self {
+        Self {
+            initial_temperature: default_initial_temperature(),
+            min_temperature: default_min_temperature(),
+            cooling: CoolingSchedule::default(),
+            cooling_rate: default_cooling_rate(),
+            replicas: default_replicas(),
+            temperature_ladder: default_temperature_ladder(),
+            swap_interval: default_swap_interval(),
+        }
+    }
+}
+
+impl MetropolisConfig {
+    /// The temperature, after `step` steps, of a chain that started at
+    /// `initial`.
+    pub fn temperature(&self, initial: f64, step: usize) -> f64 {
+        let k = step as f64;
+        let t = match self.cooling {
+            CoolingSchedule::Constant => initial,
+            CoolingSchedule::Exponential => initial * self.cooling_rate.powf(k),
+            CoolingSchedule::Linear => initial - self.cooling_rate * k,
+            CoolingSchedule::Logarithmic => initial / (1.0 + self.cooling_rate * k.ln_1p()),
+        };
+        t.max(self.min_temperature)
+    }
+
+    /// The initial temperature of each replica, from coldest to hottest.
+    pub fn ladder(&self) -> Vec<f64> {
+        (0..self.replicas.max(1))
+            .map(|i| self.initial_temperature * self.temperature_ladder.powi(i as i32))
+            .collect()
+    }
+}
+
+/// How the maximum ages of the layers of an ALPS population grow, as
+/// multiples of the `age_gap`.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum AgingScheme {
+    /// 1, 2, 3, 4, 5, ...
+    Linear,
+    /// 1, 2, 4, 9, 16, ...
+    Polynomial,
+    /// 1, 2, 3, 5, 8, ...
+    Fibonacci,
+    /// 1, 2, 4, 8, 16, ...
+    Exponential,
+}
+
+impl Default for AgingScheme {
+    fn default() -> Self {
+        Self::Polynomial
+    }
+}
+
+fn default_num_layers() -> usize {
+    5
+}
+
+fn default_age_gap() -> usize {
+    10
+}
+
+fn default_injection_interval() -> usize {
+    0x100
+}
+
+/// Settings for the age-layered population structure, used when
+/// `selection = "Alps"`. A creature's age is its `generation`: the length
+/// of its longest line of descent from a random creature.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AlpsConfig {
+    /// The island's `pop_size` is divided evenly among the layers.
+    #[serde(default = "default_num_layers")]
+    pub num_layers: usize,
+    #[serde(default = "default_age_gap")]
+    pub age_gap: usize,
+    #[serde(default)]
+    pub aging_scheme: AgingScheme,
+    /// The number of tournaments between replacements of the bottom layer
+    /// with fresh random creatures.
+    #[serde(default = "default_injection_interval")]
+    pub injection_interval: usize,
+}
+
+impl Default for AlpsConfig {
+    fn default() -> Self {
+        Self {
+            num_layers: default_num_layers(),
+            age_gap: default_age_gap(),
+            aging_scheme: AgingScheme::default(),
+            injection_interval: default_injection_interval(),
+        }
+    }
+}
+
+impl AlpsConfig {
+    /// The oldest a creature may be and remain in the given layer. The top
+    /// layer has no limit.
+    pub fn age_limit(&self, layer: usize) -> Option<usize> {
+        if layer + 1 >= self.num_layers {
+            return None;
+        }
+        let multiple = match self.aging_scheme {
+            AgingScheme::Linear => layer + 1,
+            AgingScheme::Polynomial => (layer * layer).max(layer + 1),
+            AgingScheme::Fibonacci => {
+                let (mut a, mut b) = (1, 2);
+                for _ in 0..layer {
+                    let c = a + b;
+                    a = b;
+                    b = c;
+                }
+                a
+            }
+            AgingScheme::Exponential => 1 << layer,
+        };
+        Some(self.age_gap * multiple)
+    }
+}
+
+/// Settings for running each island in a process of its own. See the
+/// `cluster` module.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct ClusterConfig {
+    /// The address on which the coordinator listens, as `unix:<path>` or
+    /// `tcp:<host>:<port>`. If absent, the islands run as threads of a
+    /// single process.
+    pub coordinator: Option<String>,
 }
 
 fn default_weight_decay() -> f64 {
@@ -148,6 +682,113 @@ pub struct RouletteConfig {
     pub weight_decay: f64,
 }
 
+/// An aspect of a creature's behaviour, as recorded in its profile.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub enum BehaviourFeature {
+    /// The values of the output registers at the end of each run.
+    Registers,
+    /// The bytes written to memory, and where.
+    MemoryWrites,
+    /// The basic blocks visited.
+    Blocks,
+}
+
+fn default_behaviour() -> Vec<BehaviourFeature> {
+    vec![BehaviourFeature::Registers]
+}
+
+fn default_novelty_k() -> usize {
+    15
+}
+
+fn default_archive_threshold() -> f64 {
+    1.0
+}
+
+fn default_archive_size() -> usize {
+    0x1000
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NoveltyConfig {
+    /// The features that make up the behaviour descriptor. The distance
+    /// between two behaviours is the sum of the distances between their
+    /// features, each of which lies between 0 and 1.
+    #[serde(default = "default_behaviour")]
+    pub behaviour: Vec<BehaviourFeature>,
+    /// The number of nearest neighbours over which sparseness is averaged.
+    #[serde(default = "default_novelty_k")]
+    pub k: usize,
+    /// Behaviours at least this sparse are added to the archive.
+    #[serde(default = "default_archive_threshold")]
+    pub archive_threshold: f64,
+    /// Once the archive is full, the oldest behaviours are evicted.
+    #[serde(default = "default_archive_size")]
+    pub archive_size: usize,
+}
+
+impl Default for NoveltyConfig {
+    fn default() -> Self {
+        Self {
+            behaviour: default_behaviour(),
+            k: default_novelty_k(),
+            archive_threshold: default_archive_threshold(),
+            archive_size: default_archive_size(),
+        }
+    }
+}
+
+/// A behavioural dimension of the MAP-Elites grid, measured from the profile.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub enum EliteFeature {
+    /// The number of distinct gadgets executed, averaged over the runs.
+    GadgetsExecuted,
+    /// The number of bytes written to memory, averaged over the runs.
+    BytesWritten,
+    /// The number of registers in the register pattern holding their target
+    /// values, averaged over the runs.
+    RegistersControlled,
+    /// The proportion of runs that crashed.
+    Crashed,
+    /// The number of gadget transitions (returns, or dispatches), averaged over the runs.
+    RetCount,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EliteDimension {
+    pub feature: EliteFeature,
+    /// The number of bins into which the range `min..max` is divided. Values
+    /// outside of the range are put in the first or last bin.
+    pub bins: usize,
+    #[serde(default)]
+    pub min: f64,
+    pub max: f64,
+}
+
+impl EliteDimension {
+    pub fn bin(&self, value: f64) -> usize {
+        if self.bins == 0 || self.max <= self.min {
+            return 0;
+        }
+        let width = (self.max - self.min) / self.bins as f64;
+        let bin = ((value - self.min) / width).floor();
+        if bin < 0.0 {
+            0
+        } else {
+            (bin as usize).min(self.bins - 1)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct MapElitesConfig {
+    pub dimensions: Vec<EliteDimension>,
+    /// The number of offspring produced and evaluated per iteration. Defaults
+    /// to the population size, which is also the number of random creatures
+    /// used to seed the grid.
+    pub batch_size: Option<usize>,
+}
+
 fn random_population_name() -> String {
     // we're letting this random value be unseeded for now, since
     // the name impacts nothing and we don't want to clobber same-seeded runs
@@ -196,6 +837,23 @@ impl Config {
             config.observer.population_name
         );
         config.assert_invariants();
+        config.roper.parse_bad_bytes()?;
+        // The MAP-Elites dimensions are measured from emulation profiles.
+        if matches!(config.selection, Selection::MapElites) && !matches!(config.job, Job::Roper) {
+            return Err(Error::Parsing(format!(
+                "MAP-Elites selection is only available to the roper job, not {:?}",
+                config.job
+            )));
+        }
+        if let Job::Roper = config.job {
+            // The fitness functions check their patterns against the config.
+            config.roper.parse_register_patterns();
+            config.roper.parse_memory_patterns();
+            crate::roper::fitness_functions::configured_fitness_function(&config)?
+                .validate(&config)?;
+            let factors = crate::roper::fitness_functions::fitness_factors(&config)?;
+            config.fitness.validate_expressions(&factors)?;
+        }
         config.set_data_directory();
         // copy the config file to the data directory for posterity
         // bit ugly, here: copying it to the parent of the directory, just above the island subdirs
@@ -270,10 +928,174 @@ fn default_mode() -> unicorn::Mode {
     unicorn::Mode::MODE_32
 }
 
+/// The style of chain being evolved. This decides where the composable
+/// boundaries between gadgets are found: at `ret` instructions, for
+/// return-oriented programming, or at indirect `jmp` or `call` instructions
+/// that hand control back to a dispatcher gadget, for jump- and call-oriented
+/// programming.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq, Hash)]
+pub enum ChainMode {
+    Rop,
+    Jop,
+    Cop,
+}
+
+impl Default for ChainMode {
+    fn default() -> Self {
+        Self::Rop
+    }
+}
+
+/// Parameters for the dispatcher-gadget model used by JOP and COP chains.
+/// The payload is treated as a dispatch table: a sequence of gadget addresses
+/// that the dispatcher walks through, using `table_register` as its cursor.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, Eq, PartialEq)]
+pub struct JopConfig {
+    /// The address of the dispatcher gadget. If set, execution begins here,
+    /// otherwise it begins at the first address in the dispatch table.
+    pub dispatcher: Option<u64>,
+    /// The register the dispatcher uses to index the dispatch table.
+    pub table_register: Option<String>,
+    /// Added to the address of the dispatch table before it is loaded into
+    /// `table_register`, to accommodate dispatchers that advance the cursor
+    /// before, rather than after, jumping.
+    #[serde(default)]
+    pub table_offset: i64,
+}
+
+/// The buffer into which the payload is delivered.
+#[derive(Clone, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub enum PayloadBuffer {
+    /// An offset from the lowest address of the emulator's stack.
+    Stack(u64),
+    /// A fixed address, such as a heap or .bss buffer.
+    Address(u64),
+    /// The address of a named symbol in the target binary. This is
+    /// resolved to an `Address` when the binary is loaded.
+    Symbol(String),
+}
+
+impl Default for PayloadBuffer {
+    fn default() -> Self {
+        Self::Stack(0x100)
+    }
+}
+
+fn default_padding_byte() -> u8 {
+    0x41
+}
+
+/// Models the overflow through which the payload is delivered, so that
+/// evolved chains are evaluated in the state the process would be in at the
+/// moment of hijack. The bytes written to the buffer are `padding` copies of
+/// `padding_byte`, followed by the saved frame pointer (if given), followed
+/// by the chain itself. The defaults reproduce the original behaviour: the
+/// chain is written 0x100 bytes into the stack and its first word is popped
+/// into the program counter.
+#[derive(Clone, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub struct DeliveryConfig {
+    #[serde(default)]
+    pub buffer: PayloadBuffer,
+    /// The number of bytes between the start of the buffer and the saved
+    /// frame pointer (or the return address, if there is no saved frame
+    /// pointer).
+    #[serde(default)]
+    pub padding: usize,
+    #[serde(default = "default_padding_byte")]
+    pub padding_byte: u8,
+    /// The value to overwrite the saved frame pointer with.
+    pub saved_frame_pointer: Option<u64>,
+    /// The address of the vulnerable function's epilogue (e.g. `leave; ret`).
+    /// If set, emulation begins here, with the frame pointer aimed at the
+    /// saved frame pointer slot, rather than at the first gadget. Only used
+    /// for ROP chains.
+    pub epilogue: Option<u64>,
+    /// The frame pointer register. Defaults to the conventional one for the
+    /// architecture.
+    pub frame_register: Option<String>,
+}
+
+impl Default for DeliveryConfig {
+    fn default() -> Self {
+        Self {
+            buffer: PayloadBuffer::default(),
+            padding: 0,
+            padding_byte: default_padding_byte(),
+            saved_frame_pointer: None,
+            epilogue: None,
+            frame_register: None,
+        }
+    }
+}
+
+/// How to handle payloads containing the bytes listed in `bad_bytes`.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub enum BadBytePolicy {
+    /// Rewrite each bad byte to its substitute in the `bad_bytes` table at
+    /// pack time. The genome is left untouched.
+    Substitute,
+    /// Leave the payload as it is, but add a `bad_bytes` factor to the
+    /// fitness, counting the bad bytes in the packed payload.
+    Penalize,
+    /// Like `Penalize`, but also have mutation replace offending addresses
+    /// with equivalent gadgets at addresses free of bad bytes.
+    Repair,
+}
+
+impl Default for BadBytePolicy {
+    fn default() -> Self {
+        Self::Substitute
+    }
+}
+
+/// What becomes of a gene that has outlived its `gene_ttl`.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub enum GeneExpiry {
+    Delete,
+    /// Replace the gene with a fresh address from the soup.
+    Replace,
+}
+
+impl Default for GeneExpiry {
+    fn default() -> Self {
+        Self::Delete
+    }
+}
+
+/// The task of writing a string somewhere in memory, and then pointing a
+/// register at it, as for the arguments to `execve`. Scored by the
+/// `string_pointer` fitness function, which requires `record_memory_writes`.
+#[derive(Clone, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub struct StringPointerConfig {
+    /// The bytes to be written. Remember the terminating `\u0000`, if one
+    /// is wanted.
+    pub string: String,
+    /// The register that should point to the string.
+    pub register: String,
+}
+
+/// The task of steering execution to a particular address, such as a win
+/// function or a one-gadget. Scored by the `reach_target` fitness function,
+/// which stops the emulator as soon as the target is reached, and compares
+/// the register state at that moment with the register pattern, if any.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, Eq, PartialEq)]
+pub struct ReachTargetConfig {
+    pub address: Option<u64>,
+    /// A symbol to be resolved to the target address, when the binary is
+    /// loaded.
+    pub symbol: Option<String>,
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize, Eq, PartialEq)]
 pub struct RoperConfig {
     #[serde(default)]
     pub use_push: bool,
+    #[serde(default)]
+    pub chain_mode: ChainMode,
+    #[serde(default)]
+    pub jop: JopConfig,
+    #[serde(default)]
+    pub delivery: DeliveryConfig,
     pub gadget_file: Option<String>,
     #[serde(default)]
     pub output_registers: Vec<String>,
@@ -284,6 +1106,11 @@ pub struct RoperConfig {
     pub register_pattern_file: Option<String>,
     #[serde(skip)]
     pub parsed_register_patterns: Vec<RegisterPattern>,
+    /// A file listing stages to be reached in order, for the `staged` fitness function.
+    #[serde(default)]
+    pub stage_file: Option<String>,
+    #[serde(skip)]
+    pub parsed_stages: Vec<Stage>,
     #[serde(default = "Default::default")]
     pub soup: Option<Vec<u64>>,
     pub soup_size: Option<usize>,
@@ -309,13 +1136,40 @@ pub struct RoperConfig {
     pub binary_path: String,
     #[serde(default)]
     pub ld_paths: Option<Vec<String>>,
+    /// Maps each forbidden byte, as a hex string, to its substitute.
     #[serde(default)]
     pub bad_bytes: Option<HashMap<String, u8>>,
+    #[serde(skip)]
+    pub parsed_bad_bytes: HashMap<u8, u8>,
+    /// The keys of `parsed_bad_bytes`, in order.
+    #[serde(skip)]
+    pub parsed_forbidden_bytes: Vec<u8>,
+    #[serde(default)]
+    pub bad_byte_policy: BadBytePolicy,
+    /// A single memory pattern, to be written in every case.
     pub memory_pattern: Option<Vec<u8>>,
+    /// A file of memory patterns, one for each case. Takes precedence over
+    /// `memory_pattern`.
+    #[serde(default)]
+    pub memory_pattern_file: Option<String>,
+    #[serde(skip)]
+    pub parsed_memory_patterns: Vec<MemoryPattern>,
+    #[serde(default)]
+    pub string_pointer: Option<StringPointerConfig>,
+    #[serde(default)]
+    pub reach_target: Option<ReachTargetConfig>,
+    #[serde(skip)]
+    pub target_distances: Option<Arc<TargetDistances>>,
     #[serde(default)]
     pub break_on_calls: bool,
     #[serde(default)]
     pub monitor_stack_writes: bool,
+    /// If present, a gene that has been inherited more than this many times
+    /// since it last executed expires.
+    #[serde(default)]
+    pub gene_ttl: Option<usize>,
+    #[serde(default)]
+    pub gene_expiry: GeneExpiry,
 }
 
 impl RoperConfig {
@@ -332,6 +1186,112 @@ impl RoperConfig {
         &self.parsed_register_patterns
     }
 
+    pub fn parse_memory_patterns(&mut self) {
+        if let Some(ref pat_file) = self.memory_pattern_file {
+            let ps =
+                parse_memory_pattern_file(pat_file).expect("Failed to parse memory pattern file");
+            self.parsed_memory_patterns = ps;
+        } else if let Some(ref pattern) = self.memory_pattern {
+            self.parsed_memory_patterns = vec![MemoryPattern::from(pattern.clone())];
+        }
+    }
+
+    pub fn memory_patterns(&self) -> &[MemoryPattern] {
+        &self.parsed_memory_patterns
+    }
+
+    pub fn parse_stages(&mut self) {
+        if let Some(ref stage_file) = self.stage_file {
+            let stages = parse_stage_file(stage_file).expect("Failed to parse stage file");
+            self.parsed_stages = stages;
+        }
+    }
+
+    pub fn stages(&self) -> &[Stage] {
+        &self.parsed_stages
+    }
+
+    /// The address at which the emulator should stop, once the target
+    /// symbol, if any, has been resolved.
+    pub fn target_address(&self) -> Option<u64> {
+        self.reach_target.as_ref().and_then(|t| t.address)
+    }
+
+    /// Computes the control flow distance from each block to the target,
+    /// for the `reach_target` fitness function. Requires the lifted program,
+    /// and the target's address, resolved from its symbol if need be.
+    pub fn compute_target_distances(&mut self) -> Result<(), Error> {
+        if self.reach_target.is_none() {
+            return Ok(());
+        }
+        let target = self.target_address().ok_or_else(|| {
+            Error::MissingKey(
+                "roper.reach_target.address, or a roper.reach_target.symbol that resolves to one"
+                    .into(),
+            )
+        })?;
+        let memory = get_static_memory_image();
+        let program = memory.il_program.as_ref().ok_or_else(|| {
+            Error::Misc(format!(
+                "The program in {} could not be lifted, so the distances to the \
+                 reach_target address 0x{:x} can't be measured",
+                self.binary_path, target
+            ))
+        })?;
+        self.target_distances = Some(Arc::new(TargetDistances::from_program(program, target)));
+        Ok(())
+    }
+
+    pub fn target_distances(&self) -> Option<&TargetDistances> {
+        self.target_distances.as_deref()
+    }
+
+    /// Whether the hatchery should snapshot the machine state at each boundary.
+    pub fn record_boundaries(&self) -> bool {
+        !self.parsed_stages.is_empty()
+    }
+
+    /// Whether the boundary snapshots should include the memory written so far.
+    pub fn snapshot_memory(&self) -> bool {
+        self.parsed_stages.iter().any(Stage::needs_memory)
+    }
+
+    /// Parses the hex keys of the `bad_bytes` table.
+    pub fn parse_bad_bytes(&mut self) -> Result<(), Error> {
+        let mut table = HashMap::new();
+        for (k, v) in self.bad_bytes.iter().flatten() {
+            let byte = u8::from_str_radix(k.trim_start_matches("0x"), 16)
+                .map_err(|_| Error::Parsing(format!("Invalid bad byte: {}", k)))?;
+            table.insert(byte, *v);
+        }
+        let mut forbidden = table.keys().copied().collect::<Vec<u8>>();
+        forbidden.sort_unstable();
+        self.parsed_bad_bytes = table;
+        self.parsed_forbidden_bytes = forbidden;
+        Ok(())
+    }
+
+    /// The table of bad bytes and their substitutes, keyed by byte.
+    pub fn bad_byte_table(&self) -> &HashMap<u8, u8> {
+        &self.parsed_bad_bytes
+    }
+
+    /// The bytes that must not appear in a packed payload.
+    pub fn forbidden_bytes(&self) -> &[u8] {
+        &self.parsed_forbidden_bytes
+    }
+
+    /// The byte filter to apply at pack time, which is only used under the
+    /// `Substitute` policy.
+    pub fn byte_filter(&self) -> Option<HashMap<u8, u8>> {
+        match self.bad_byte_policy {
+            BadBytePolicy::Substitute if self.bad_bytes.is_some() => {
+                Some(self.bad_byte_table().clone())
+            }
+            _ => None,
+        }
+    }
+
     pub fn registers_to_check(&self) -> Vec<String> {
         let mut set = HashSet::new();
         for r in self
@@ -341,7 +1301,14 @@ impl RoperConfig {
         {
             set.insert(r.clone());
         }
-        for rp in self.parsed_register_patterns.iter() {
+        if let Some(ref sp) = self.string_pointer {
+            set.insert(sp.register.clone());
+        }
+        for rp in self.parsed_register_patterns.iter().chain(
+            self.parsed_stages
+                .iter()
+                .filter_map(Stage::register_pattern),
+        ) {
             for r in rp.0.keys() {
                 set.insert(r.clone());
             }
@@ -370,17 +1337,27 @@ impl Default for RoperConfig {
     fn default() -> Self {
         Self {
             use_push: false,
+            chain_mode: ChainMode::Rop,
+            jop: JopConfig::default(),
+            delivery: DeliveryConfig::default(),
             gadget_file: None,
             output_registers: vec![],
             input_registers: vec![],
             randomize_registers: false,
             register_pattern_file: None,
             parsed_register_patterns: vec![],
+            stage_file: None,
+            parsed_stages: vec![],
             soup: None,
             soup_size: None,
             arch: unicorn::Arch::X86,
             mode: unicorn::Mode::MODE_64,
             memory_pattern: None,
+            memory_pattern_file: None,
+            parsed_memory_patterns: vec![],
+            string_pointer: None,
+            reach_target: None,
+            target_distances: None,
             num_workers: 8,
             num_emulators: 8,
             wait_limit: 500,
@@ -392,8 +1369,13 @@ impl Default for RoperConfig {
             binary_path: "/bin/sh".to_string(),
             ld_paths: None,
             bad_bytes: None,
+            parsed_bad_bytes: HashMap::new(),
+            parsed_forbidden_bytes: vec![],
+            bad_byte_policy: BadBytePolicy::Substitute,
             break_on_calls: false,
             monitor_stack_writes: false,
+            gene_ttl: None,
+            gene_expiry: GeneExpiry::default(),
         }
     }
 }
@@ -425,6 +1407,8 @@ pub enum Selection {
     Roulette,
     Metropolis,
     Lexicase,
+    MapElites,
+    Alps,
 }
 
 impl Default for Selection {
@@ -439,3 +1423,37 @@ pub enum Problem {
     RegisterSpecification(RegisterPattern),
     MemoryPattern(Vec<u8>),
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_elite_dimension_bin() {
+        let dim = EliteDimension {
+            feature: EliteFeature::GadgetsExecuted,
+            bins: 4,
+            min: 0.0,
+            max: 8.0,
+        };
+        assert_eq!(dim.bin(0.0), 0);
+        assert_eq!(dim.bin(1.9), 0);
+        assert_eq!(dim.bin(2.0), 1);
+        assert_eq!(dim.bin(5.0), 2);
+        assert_eq!(dim.bin(7.9), 3);
+        // values outside the range are clamped to the first or last bin
+        assert_eq!(dim.bin(8.0), 3);
+        assert_eq!(dim.bin(100.0), 3);
+        assert_eq!(dim.bin(-1.0), 0);
+
+        // degenerate dimensions put everything in one bin
+        let no_bins = EliteDimension { bins: 0, ..dim };
+        assert_eq!(no_bins.bin(5.0), 0);
+        let empty_range = EliteDimension {
+            min: 8.0,
+            max: 8.0,
+            ..dim
+        };
+        assert_eq!(empty_range.bin(5.0), 0);
+    }
+}
@@ -12,14 +12,16 @@ use rayon::prelude::*;
 use threadpool::ThreadPool;
 use unicorn::{Context, Cpu, Mode};
 
+use crate::configure::ChainMode;
 pub use crate::configure::RoperConfig;
 use crate::disassembler::Disassembler;
 use crate::emulator::hatchery::hooking::emu_prep_fn;
 use crate::emulator::loader;
 use crate::emulator::loader::Seg;
-use crate::emulator::pack::Pack;
+use crate::emulator::pack::{count_bad_bytes, Pack};
 use crate::emulator::profiler::{Profile, Profiler};
 use crate::emulator::register_pattern::Register;
+use crate::emulator::stages::progress_through_stages;
 use crate::error::Error;
 
 //use std::sync::atomic::{AtomicUsize, Ordering};
@@ -153,10 +155,15 @@ impl<C: Cpu<'static>> EmuPool<C> {
     }
 }
 
-type InboundTx<T, C> = SyncSender<(T, Option<HashMap<Register<C>, u64>>)>;
-type InboundRx<T, C> = Receiver<(T, Option<HashMap<Register<C>, u64>>)>;
-type OutboundTx = SyncSender<Profile>;
-type OutboundRx = Receiver<Profile>;
+/// A payload to execute, with its optional register inputs.
+pub type Job<C> = (Vec<u64>, Option<HashMap<Register<C>, u64>>);
+
+// Jobs are tagged with their position in the batch, since the results
+// return in the order in which they finish.
+type InboundTx<T, C> = SyncSender<(usize, T, Option<HashMap<Register<C>, u64>>)>;
+type InboundRx<T, C> = Receiver<(usize, T, Option<HashMap<Register<C>, u64>>)>;
+type OutboundTx = SyncSender<(usize, Profile)>;
+type OutboundRx = Receiver<(usize, Profile)>;
 type InboundChannel<T, C> = (InboundTx<T, C>, InboundRx<T, C>);
 type OutboundChannel = (OutboundTx, OutboundRx);
 
@@ -240,18 +247,13 @@ impl<C: 'static + Cpu<'static> + Send> Hatchery<C> {
         let parameters = config.clone();
         let mem = memory.clone();
         let disas = disassembler.clone();
-        let bad_bytes: Arc<Option<HashMap<u8, u8>>> =
-            Arc::new(config.bad_bytes.as_ref().map(|table| {
-                table
-                    .iter()
-                    // FIXME do this in a less dirty, shotgunny way
-                    .map(|(k, v)| (u8::from_str_radix(k, 16).unwrap(), *v))
-                    .collect::<HashMap<u8, u8>>()
-            }));
+        let bad_bytes: Arc<Option<HashMap<u8, u8>>> = Arc::new(config.byte_filter());
+        let forbidden_bytes: Arc<Vec<u8>> = Arc::new(config.forbidden_bytes().to_vec());
         let handle = spawn(move || {
-            for (payload, args) in our_rx.iter() {
+            for (tag, payload, args) in our_rx.iter() {
                 let config = parameters.clone();
                 let bad_bytes = bad_bytes.clone();
+                let forbidden_bytes = forbidden_bytes.clone();
                 let our_tx = our_tx.clone();
                 let output_registers = output_registers.clone();
                 let thread_pool = t_pool.lock().expect("Failed to unlock thread_pool mutex");
@@ -269,6 +271,8 @@ impl<C: 'static + Cpu<'static> + Send> Hatchery<C> {
                     let mut emu: Reusable<'_, C> = emulator_pool.pull();
                     // Initialize the profiler
                     let mut profiler = Profiler::new(&output_registers, &initial_register_state);
+                    profiler.record_boundaries = config.record_boundaries();
+                    profiler.snapshot_memory = config.snapshot_memory();
                     // load the inputs
                     for (reg, val) in initial_register_state.iter() {
                         emu.reg_write(*reg, *val).expect("Failed to load registers");
@@ -286,10 +290,15 @@ impl<C: 'static + Cpu<'static> + Send> Hatchery<C> {
                     }
 
                     let code = payload.pack(word_size, endian, (*bad_bytes).as_ref());
+                    profiler.bad_byte_count = count_bad_bytes(&code, &forbidden_bytes);
                     let initial_pc = emu_prep_fn(&mut (*emu), &config, &code, &profiler).expect("Failure in the emulator preparation function.");
 
                     if config.record_basic_blocks {
-                        let _hook = hooking::install_code_logging_hook(&mut (*emu), &profiler, &payload.as_code_addrs(word_size, endian), config.break_on_calls).expect("Failed to install code_logging_hook");
+                        let _hook = hooking::install_code_logging_hook(&mut (*emu), &profiler, &payload.as_code_addrs(word_size, endian), config.break_on_calls, config.chain_mode).expect("Failed to install code_logging_hook");
+                    }
+
+                    if let Some(target) = config.target_address() {
+                        let _hook = hooking::install_target_hook(&mut (*emu), &profiler, target).expect("Failed to install target_hook");
                     }
 
                     // WONTFIX: It turns out that Unicorn never implemented a fetch hook. It's an unused enum in the C code. Balls.
@@ -347,10 +356,17 @@ impl<C: 'static + Cpu<'static> + Send> Hatchery<C> {
                             });
                         });
                     }
-                    let profile = profiler.into();
+                    let mut profile: Profile = profiler.into();
+                    if config.record_boundaries() {
+                        profile.stage_progress = profile
+                            .boundaries
+                            .iter()
+                            .map(|b| progress_through_stages(config.stages(), b))
+                            .collect();
+                    }
                     // Now send the code back, along with its profile information.
                     // (The genotype, along with its phenotype.)
-                    our_tx.send(profile).map_err(Error::from).expect("TX Failure in pipeline");
+                    our_tx.send((tag, profile)).map_err(Error::from).expect("TX Failure in pipeline");
                 });
             }
         });
@@ -371,8 +387,30 @@ impl<C: 'static + Cpu<'static> + Send> Hatchery<C> {
         payload: Vec<u64>,
         args: Option<HashMap<Register<C>, u64>>,
     ) -> Result<Profile, Error> {
-        self.tx.send((payload, args))?;
-        self.rx.recv().map_err(Error::from)
+        self.tx.send((0, payload, args))?;
+        self.rx
+            .recv()
+            .map(|(_, profile)| profile)
+            .map_err(Error::from)
+    }
+
+    /// Submits every job at once, so that as many emulators as there are
+    /// workers can be kept busy, and returns their profiles in the order
+    /// in which the jobs were given.
+    pub fn execute_batch(&self, jobs: Vec<Job<C>>) -> Result<Vec<Profile>, Error> {
+        let n = jobs.len();
+        for (tag, (payload, args)) in jobs.into_iter().enumerate() {
+            self.tx.send((tag, payload, args))?;
+        }
+        let mut profiles: Vec<Option<Profile>> = vec![None; n];
+        for _ in 0..n {
+            let (tag, profile) = self.rx.recv()?;
+            profiles[tag] = Some(profile);
+        }
+        Ok(profiles
+            .into_iter()
+            .map(|p| p.expect("Missing profile in batch"))
+            .collect())
     }
 }
 // TODO: try to reduce the number of mutexes needed in this setup. it seems like a code smell.
@@ -423,10 +461,13 @@ pub mod hooking {
     use hashbrown::HashSet;
     use unicorn::{CodeHookType, MemHookType, MemType, Protection};
 
+    use crate::configure::PayloadBuffer;
     use crate::emulator::hatchery::tools::find_stack;
     use crate::emulator::loader::get_static_memory_image;
-    use crate::emulator::profiler::{read_registers_in_hook, Block, MemLogEntry};
-    use crate::util::architecture::{endian, read_integer, word_size_in_bytes, Perms};
+    use crate::emulator::profiler::{read_registers_in_hook, Block, BoundarySnapshot, MemLogEntry};
+    use crate::util::architecture::{
+        endian, frame_pointer_register, read_integer, word_size_in_bytes, write_integer, Perms,
+    };
 
     use super::*;
 
@@ -468,6 +509,29 @@ pub mod hooking {
         false
     }
 
+    /// An indirect branch is a `jmp` (in JOP mode) or `call` (in COP mode) whose
+    /// target is taken from a register or from memory, rather than encoded as an
+    /// immediate. These are the points at which control passes back to the
+    /// dispatcher, or on to the next gadget in the dispatch table.
+    fn is_indirect_branch(
+        arch: unicorn::Arch,
+        _mode: unicorn::Mode,
+        inst: &Insn<'_>,
+        chain_mode: ChainMode,
+    ) -> bool {
+        let mnemonic = match (arch, chain_mode) {
+            (unicorn::Arch::X86, ChainMode::Jop) => "jmp",
+            (unicorn::Arch::X86, ChainMode::Cop) => "call",
+            _ => return false, // TODO: implement other arches later if needed
+        };
+        if inst.mnemonic() != Some(mnemonic) {
+            return false;
+        }
+        inst.op_str()
+            .map(|op| !op.starts_with("0x") && !op.chars().all(|c| c.is_ascii_digit()))
+            .unwrap_or(false)
+    }
+
     /// We want the emulator to halt on a syscall.
     /// NOTE: this only really works on x86 architectures. TODO: generalize somehow
     // pub fn install_syscall_hook<C: 'static + Cpu<'static>>(
@@ -560,25 +624,111 @@ pub mod hooking {
 
     pub fn emu_prep_fn<C: 'static + Cpu<'static>>(
         emu: &mut C,
-        _config: &RoperConfig,
+        config: &RoperConfig,
+        code: &[u8],
+        profiler: &Profiler<C>,
+    ) -> Result<u64, Error> {
+        match config.chain_mode {
+            ChainMode::Rop => rop_prep_fn(emu, config, code, profiler),
+            ChainMode::Jop | ChainMode::Cop => dispatch_table_prep_fn(emu, config, code, profiler),
+        }
+    }
+
+    /// Write the payload into the configured buffer, behind its padding and
+    /// saved frame pointer, and return the address at which the chain itself begins.
+    fn deliver_payload<C: 'static + Cpu<'static>>(
+        emu: &mut C,
+        config: &RoperConfig,
+        code: &[u8],
+    ) -> Result<u64, Error> {
+        let delivery = &config.delivery;
+        let word_size = word_size_in_bytes(emu.arch(), emu.mode());
+        let endian = endian(emu.arch(), emu.mode());
+        let buffer = match delivery.buffer {
+            PayloadBuffer::Stack(offset) => {
+                let stack = tools::find_stack(emu).expect("Can't find stack");
+                stack.begin + offset
+            }
+            PayloadBuffer::Address(addr) => addr,
+            PayloadBuffer::Symbol(ref name) => {
+                return Err(Error::Misc(format!(
+                    "Payload buffer symbol {} has not been resolved",
+                    name
+                )))
+            }
+        };
+        let region = emu
+            .mem_regions()?
+            .into_iter()
+            .find(|r| r.begin <= buffer && buffer < r.end && r.writeable())
+            .ok_or_else(|| {
+                Error::Misc(format!(
+                    "Payload buffer at 0x{:x} is not in writeable memory",
+                    buffer
+                ))
+            })?;
+
+        let mut bytes = vec![delivery.padding_byte; delivery.padding];
+        if let Some(frame_pointer) = delivery.saved_frame_pointer {
+            let mut word = vec![0_u8; word_size];
+            write_integer(endian, word_size, frame_pointer, &mut word);
+            bytes.extend_from_slice(&word);
+        }
+        let chain_start = buffer + bytes.len() as u64;
+        bytes.extend_from_slice(code);
+
+        let room = (region.end - buffer) as usize;
+        let end = room.min(bytes.len());
+        emu.mem_write(buffer, &bytes[0..end])?;
+        Ok(chain_start)
+    }
+
+    fn frame_pointer<C: 'static + Cpu<'static>>(
+        emu: &C,
+        config: &RoperConfig,
+    ) -> Result<Register<C>, Error> {
+        let name = config
+            .delivery
+            .frame_register
+            .as_deref()
+            .or_else(|| frame_pointer_register(emu.arch(), emu.mode()))
+            .ok_or_else(|| Error::MissingKey("delivery.frame_register".into()))?;
+        name.parse()
+            .ok()
+            .ok_or_else(|| Error::Parsing(format!("Invalid register: {}", name)))
+    }
+
+    fn rop_prep_fn<C: 'static + Cpu<'static>>(
+        emu: &mut C,
+        config: &RoperConfig,
         code: &[u8],
         _profiler: &Profiler<C>,
     ) -> Result<u64, Error> {
-        // now write the payload
-        let stack = tools::find_stack(emu).expect("Can't find stack");
-        let pad = 0x100;
-        let sp = stack.begin + pad;
-        let room = (stack.end - (stack.begin + pad)) as usize;
-        let end = room.min(code.len());
-        let payload = &code[0..end];
-        emu.mem_write(sp, payload)?;
-        // set the stack pointer to the middle of the stack
+        let chain_start = deliver_payload(emu, config, code)?;
+        let word_size = word_size_in_bytes(emu.arch(), emu.mode()) as u64;
+
+        if let Some(epilogue) = config.delivery.epilogue {
+            // Reproduce the state at the end of the vulnerable function: the frame
+            // pointer addresses the saved frame pointer slot, which sits just below
+            // the return address, and the stack pointer is somewhere beneath it.
+            let fp = frame_pointer(emu, config)?;
+            emu.reg_write(fp, chain_start - word_size)?;
+            let buffer_start = chain_start
+                - config.delivery.padding as u64
+                - config.delivery.saved_frame_pointer.map_or(0, |_| word_size);
+            emu.write_stack_pointer(buffer_start)?;
+            return Ok(epilogue);
+        }
+
+        if let Some(frame_pointer_value) = config.delivery.saved_frame_pointer {
+            let fp = frame_pointer(emu, config)?;
+            emu.reg_write(fp, frame_pointer_value)?;
+        }
         // now "pop" the stack into the program counter
-        let word_size = word_size_in_bytes(emu.arch(), emu.mode());
-        let a_bytes = emu.mem_read_as_vec(sp, word_size)?;
+        let a_bytes = emu.mem_read_as_vec(chain_start, word_size as usize)?;
         let endian = endian(emu.arch(), emu.mode());
-        if let Some(address) = read_integer(&a_bytes, endian, word_size) {
-            emu.write_stack_pointer(sp + word_size as u64)?;
+        if let Some(address) = read_integer(&a_bytes, endian, word_size as usize) {
+            emu.write_stack_pointer(chain_start + word_size)?;
 
             Ok(address)
         } else {
@@ -586,11 +736,51 @@ pub mod hooking {
         }
     }
 
+    /// Write the payload as a dispatch table, for JOP or COP chains, and point
+    /// the dispatcher's cursor register at it. Execution begins at the dispatcher
+    /// gadget, if one has been configured, or else at the first entry in the table.
+    fn dispatch_table_prep_fn<C: 'static + Cpu<'static>>(
+        emu: &mut C,
+        config: &RoperConfig,
+        code: &[u8],
+        _profiler: &Profiler<C>,
+    ) -> Result<u64, Error> {
+        let table = deliver_payload(emu, config, code)?;
+        // Calls made by COP gadgets still need somewhere to push their return
+        // addresses, so leave the stack pointer near the top of the stack.
+        let stack = tools::find_stack(emu).expect("Can't find stack");
+        let word_size = word_size_in_bytes(emu.arch(), emu.mode());
+        emu.write_stack_pointer(stack.end - 2 * word_size as u64)?;
+
+        let (cursor, start) = if let Some(dispatcher) = config.jop.dispatcher {
+            (table, dispatcher)
+        } else {
+            let a_bytes = emu.mem_read_as_vec(table, word_size)?;
+            let endian = endian(emu.arch(), emu.mode());
+            let first = read_integer(&a_bytes, endian, word_size).ok_or_else(|| {
+                Error::Misc("Failed to read first entry of dispatch table".into())
+            })?;
+            (table + word_size as u64, first)
+        };
+        if let Some(ref reg_name) = config.jop.table_register {
+            let reg: Register<C> = reg_name
+                .parse()
+                .ok()
+                .ok_or_else(|| Error::Parsing(format!("Invalid register: {}", reg_name)))?;
+            emu.reg_write(
+                reg,
+                (cursor as i64).wrapping_add(config.jop.table_offset) as u64,
+            )?;
+        }
+        Ok(start)
+    }
+
     pub fn install_code_logging_hook<C: 'static + Cpu<'static>>(
         emu: &mut C,
         profiler: &Profiler<C>,
         gadget_addrs: &[u64],
         break_on_calls: bool,
+        chain_mode: ChainMode,
     ) -> Result<unicorn::uc_hook, unicorn::Error> {
         let memory = get_static_memory_image();
         // let stack_region: MemRegion = find_stack(emu).expect("Could not find stack");
@@ -604,12 +794,19 @@ pub mod hooking {
         let block_log = profiler.trace_log.clone();
         let gadget_log = profiler.gadget_log.clone();
         let ret_count = profiler.ret_count.clone();
+        let dispatch_count = profiler.dispatch_count.clone();
+        // Set when an indirect branch is taken, so that the next block entered can be
+        // checked against the gadget addresses in the dispatch table.
+        let pending_dispatch = Arc::new(atomic::AtomicBool::new(false));
         let call_stack_depth = profiler.call_stack_depth.clone();
         let register_state = profiler.registers_at_last_ret.clone();
         let registers_to_read = Arc::new(profiler.registers_to_read.clone());
         let committed_write_log = profiler.committed_write_log.clone();
         let committed_trace_log = profiler.committed_trace_log.clone();
         let write_log = profiler.write_log.clone();
+        let record_boundaries = profiler.record_boundaries;
+        let snapshot_memory = profiler.snapshot_memory;
+        let boundary_log = profiler.boundary_log.clone();
         let sp: i32 = emu.stack_pointer().into();
 
         macro_rules! commit_logs {
@@ -631,6 +828,42 @@ pub mod hooking {
             };
         }
 
+        // Snapshot the machine state for goals, like staged goals, that depend
+        // on intermediate states. At a syscall, the registers haven't been
+        // committed, so they're read afresh, along with any uncommitted writes.
+        macro_rules! snapshot {
+            ($engine: expr, syscall = $syscall: expr) => {
+                if record_boundaries {
+                    let registers = if $syscall {
+                        let current = Arc::new(Mutex::new(HashMap::new()));
+                        read_registers_in_hook::<C>(
+                            current.clone(),
+                            &registers_to_read,
+                            &($engine),
+                        );
+                        let registers = current.lock().unwrap().clone();
+                        registers
+                    } else {
+                        register_state.lock().unwrap().clone()
+                    };
+                    let memory = if snapshot_memory {
+                        let mut memory = committed_write_log.lock().unwrap().clone();
+                        if $syscall {
+                            memory.absorb_segqueue(&write_log);
+                        }
+                        Some(memory)
+                    } else {
+                        None
+                    };
+                    boundary_log.lock().unwrap().push(BoundarySnapshot {
+                        registers,
+                        memory,
+                        syscall: $syscall,
+                    });
+                }
+            };
+        }
+
         let bb_callback = move |engine: &unicorn::Unicorn<'_>, entry: u64, size: u32| {
             let size = size as usize;
             // let code = engine
@@ -638,6 +871,17 @@ pub mod hooking {
             //     .unwrap_or_default();
             let memory = get_static_memory_image();
 
+            if chain_mode != ChainMode::Rop
+                && pending_dispatch.swap(false, atomic::Ordering::Relaxed)
+                && gadget_addrs.contains(&entry)
+            {
+                // The dispatcher has handed control to the next gadget in the table,
+                // which makes this a composable joint in a JOP or COP chain.
+                dispatch_count.fetch_add(1, atomic::Ordering::Relaxed);
+                commit_logs!(engine, registers_to_read => register_state, write_log => committed_write_log, block_log => committed_trace_log);
+                snapshot!(engine, syscall = false);
+            }
+
             let block = Block { entry, size };
             block_log.push(block);
             if gadget_addrs.contains(&entry) {
@@ -648,7 +892,7 @@ pub mod hooking {
                 // Once we have reached a `ret` instruction, we have reached a point at which our
                 // gadget chain is composable with additional gadgets. This is where we want to
                 // commit our various trace logs.
-                if is_ret(arch, mode, &inst) {
+                if chain_mode == ChainMode::Rop && is_ret(arch, mode, &inst) {
                     // it would actually be interesting to see if the stack pointer is ever pointing to the heap
                     // could we evolve a stack pivot?
                     // TODO: we can actually check, on each ret, to see if the stack pointer
@@ -683,6 +927,7 @@ pub mod hooking {
                             } else {
                                 ret_count.fetch_add(1, atomic::Ordering::Relaxed);
                                 commit_logs!(engine, registers_to_read => register_state, write_log => committed_write_log, block_log => committed_trace_log);
+                                snapshot!(engine, syscall = false);
                             }
                             // Quietly stop the emulator if there's an attempt to return to 0
                             if addr == 0 {
@@ -695,13 +940,18 @@ pub mod hooking {
                 } else if is_syscall(arch, mode, &inst) {
                     // Committing the logs at a syscall is one way to get trapped in a non-composable local optima.
                     // commit_logs!(engine, registers_to_read => register_state, write_log => committed_write_log, block_log => committed_trace_log);
+                    snapshot!(engine, syscall = true);
                     engine.emu_stop().expect("Failed to stop emulator");
                 } else {
                     // if not a RETURN
                     // EXPERIMENTAL: halt execution at calls. see what happens.
                     if let Some(insts) = memory.disassemble(entry, size, Some(1)) {
                         for inst in insts.iter() {
-                            if is_call(arch, mode, &inst) {
+                            if chain_mode != ChainMode::Rop
+                                && is_indirect_branch(arch, mode, &inst, chain_mode)
+                            {
+                                pending_dispatch.store(true, atomic::Ordering::Relaxed);
+                            } else if is_call(arch, mode, &inst) {
                                 call_stack_depth.fetch_add(1, atomic::Ordering::Relaxed);
                                 if break_on_calls {
                                     engine.emu_stop().expect("Failed to stop emulator");
@@ -717,6 +967,40 @@ pub mod hooking {
         emu.add_code_hook(CodeHookType::CODE, 1, 0, bb_callback) //code_hook_all(emu, CodeHookType::CODE, bb_callback)?;
     }
 
+    /// Stops the emulator when execution reaches the target address, and
+    /// commits the registers and logs as they stand at that moment, so that
+    /// the register state can be checked against the register pattern.
+    pub fn install_target_hook<C: 'static + Cpu<'static>>(
+        emu: &mut C,
+        profiler: &Profiler<C>,
+        target: u64,
+    ) -> Result<unicorn::uc_hook, unicorn::Error> {
+        let register_state = profiler.registers_at_last_ret.clone();
+        let registers_to_read = profiler.registers_to_read.clone();
+        let trace_log = profiler.trace_log.clone();
+        let committed_trace_log = profiler.committed_trace_log.clone();
+        let write_log = profiler.write_log.clone();
+        let committed_write_log = profiler.committed_write_log.clone();
+        let target_reached = profiler.target_reached.clone();
+
+        let callback = move |engine: &unicorn::Unicorn<'_>, _address: u64, _size: u32| {
+            read_registers_in_hook::<C>(register_state.clone(), &registers_to_read, engine);
+            committed_write_log
+                .lock()
+                .unwrap()
+                .absorb_segqueue(&write_log);
+            if let Ok(mut log) = committed_trace_log.lock() {
+                while let Ok(b) = trace_log.pop() {
+                    log.push(b)
+                }
+            }
+            target_reached.store(true, atomic::Ordering::Relaxed);
+            engine.emu_stop().expect("Failed to stop emulator");
+        };
+
+        emu.add_code_hook(CodeHookType::CODE, target, target, callback)
+    }
+
     pub fn install_mem_write_hook<C: 'static + Cpu<'static>>(
         emu: &mut C,
         profiler: &Profiler<C>,
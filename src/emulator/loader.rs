@@ -579,6 +579,23 @@ pub fn load(
     }
 }
 
+/// Look up the address of a named symbol in an ELF binary, searching both the
+/// static and the dynamic symbol tables.
+pub fn resolve_symbol(path: &str, name: &str) -> Result<u64, Error> {
+    let code_buffer = std::fs::read(path)?;
+    let elf = Elf::parse(&code_buffer)?;
+    let static_syms = elf.syms.iter().map(|sym| (sym, &elf.strtab));
+    let dynamic_syms = elf.dynsyms.iter().map(|sym| (sym, &elf.dynstrtab));
+    static_syms
+        .chain(dynamic_syms)
+        .find(|(sym, strtab)| match strtab.get(sym.st_name) {
+            Some(Ok(n)) => n == name,
+            _ => false,
+        })
+        .map(|(sym, _)| sym.st_value)
+        .ok_or_else(|| Error::MissingKey(format!("No symbol named {} in {}", name, path)))
+}
+
 pub fn load_from_path(config: &RoperConfig, init: bool) -> Result<Vec<Seg>, Error> {
     let path = &config.binary_path;
     let stack_size = config.emulator_stack_size;
@@ -597,7 +614,7 @@ pub mod falcon_loader {
     use fnv::FnvHasher;
     use unicorn::{Arch, Mode};
 
-    use crate::configure::Config;
+    use crate::configure::{Config, PayloadBuffer};
     use crate::util;
     use crate::util::dump::ron_undump;
 
@@ -687,6 +704,20 @@ pub mod falcon_loader {
             config.roper.arch = arch;
             config.roper.mode = mode;
 
+            if let PayloadBuffer::Symbol(name) = config.roper.delivery.buffer.clone() {
+                let addr = resolve_symbol(path, &name)?;
+                log::info!("Payload buffer {} resolved to 0x{:x}", name, addr);
+                config.roper.delivery.buffer = PayloadBuffer::Address(addr);
+            }
+
+            if let Some(ref mut target) = config.roper.reach_target {
+                if let (None, Some(name)) = (target.address, target.symbol.clone()) {
+                    let addr = resolve_symbol(path, &name)?;
+                    log::info!("Target {} resolved to 0x{:x}", name, addr);
+                    target.address = Some(addr);
+                }
+            }
+
             // Do the lifting, then serialize and save the lifted program
             // but check to see if a previously lifted version already exists.
             let mem_hash = {
@@ -700,28 +731,31 @@ pub mod falcon_loader {
 
             // FIXME: temporarily disabled. Problems deserializing RON encoded Program structs.
             // experiment with different formats.
-            let program: Option<il::Program> = if config.roper.use_push {
-                if false && cached_path.exists() {
-                    Some(
-                        ron_undump::<il::Program, &Path>(cached_path).unwrap_or_else(|e| {
-                            panic!(
-                                "Failed to deserialized cached il::Program at {:?}: {:?}",
-                                cached_path, e
-                            )
-                        }),
-                    )
+            // The program is needed by Push, and for the control flow
+            // distances used by the `reach_target` fitness function.
+            let program: Option<il::Program> =
+                if config.roper.use_push || config.roper.reach_target.is_some() {
+                    if false && cached_path.exists() {
+                        Some(
+                            ron_undump::<il::Program, &Path>(cached_path).unwrap_or_else(|e| {
+                                panic!(
+                                    "Failed to deserialized cached il::Program at {:?}: {:?}",
+                                    cached_path, e
+                                )
+                            }),
+                        )
+                    } else {
+                        log::info!("Lifting the intermediate representation of the program...");
+                        let program = linker
+                            .program()
+                            .expect("Failed to lift il::Program from ElfLinker");
+                        log::info!("Finished lifting program.");
+                        // ron_dump(&program, cached_path).expect("Failed to dump il::Program");
+                        Some(program)
+                    }
                 } else {
-                    log::info!("Lifting the intermediate representation of the program...");
-                    let program = linker
-                        .program()
-                        .expect("Failed to lift il::Program from ElfLinker");
-                    log::info!("Finished lifting program.");
-                    // ron_dump(&program, cached_path).expect("Failed to dump il::Program");
-                    Some(program)
-                }
-            } else {
-                None
-            };
+                    None
+                };
 
             if init {
                 // TODO: let lift_program be optional, and only activated when using Push
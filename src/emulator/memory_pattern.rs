@@ -0,0 +1,172 @@
+use serde::Deserialize;
+
+use crate::emulator::profiler::SparseData;
+use crate::error::Error;
+
+/// Parse the memory pattern file. Like the register pattern file, this
+/// consists of TOML chunks, separated by lines of `---`, one for each case.
+/// For example:
+///
+/// ```toml
+/// string = "/bin/sh\u0000"
+/// ---
+/// bytes = [0x41, 0x42, 0x43, 0x44]
+/// region = [0x804f000, 0x8050000]
+/// ---
+/// string = "ABCD"
+/// address = 0x804f000
+/// ```
+pub fn parse_memory_pattern_file(path: &str) -> Result<Vec<MemoryPattern>, Error> {
+    let data = std::fs::read_to_string(path)?;
+    let res = parse_memory_patterns(&data);
+    log::info!("Parsed memory patterns: {:#x?}", res);
+    res
+}
+
+pub fn parse_memory_patterns(data: &str) -> Result<Vec<MemoryPattern>, Error> {
+    data.split("\n---")
+        .filter(|chunk| !chunk.trim().is_empty())
+        .map(|chunk| {
+            let mp_conf: MemoryPatternConfig = toml::from_str(chunk)?;
+            mp_conf.into_pattern()
+        })
+        .collect::<Result<Vec<MemoryPattern>, Error>>()
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct MemoryPatternConfig {
+    #[serde(default)]
+    pub bytes: Vec<u8>,
+    pub string: Option<String>,
+    /// The pattern must be written at exactly this address.
+    pub address: Option<u64>,
+    /// The pattern must be written somewhere in this half-open range.
+    pub region: Option<[u64; 2]>,
+}
+
+impl MemoryPatternConfig {
+    fn into_pattern(self) -> Result<MemoryPattern, Error> {
+        let MemoryPatternConfig {
+            mut bytes,
+            string,
+            address,
+            region,
+        } = self;
+        if let Some(string) = string {
+            bytes.extend_from_slice(string.as_bytes());
+        }
+        if bytes.is_empty() {
+            return Err(Error::Parsing(
+                "A memory pattern needs either `bytes` or a `string`".into(),
+            ));
+        }
+        let region = match (address, region) {
+            (Some(_), Some(_)) => {
+                return Err(Error::Parsing(
+                    "A memory pattern may have an `address` or a `region`, but not both".into(),
+                ))
+            }
+            (Some(addr), None) => Some((addr, addr + 1)),
+            (None, Some([start, end])) => Some((start, end)),
+            (None, None) => None,
+        };
+        Ok(MemoryPattern { bytes, region })
+    }
+}
+
+/// A sequence of bytes that ought to be written to memory, optionally
+/// confined to a region (a single address being a region of one byte).
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct MemoryPattern {
+    pub bytes: Vec<u8>,
+    /// Matches must begin in this half-open range of addresses.
+    pub region: Option<(u64, u64)>,
+}
+
+impl From<Vec<u8>> for MemoryPattern {
+    fn from(bytes: Vec<u8>) -> Self {
+        Self {
+            bytes,
+            region: None,
+        }
+    }
+}
+
+impl MemoryPattern {
+    fn range(&self) -> std::ops::Range<u64> {
+        self.region
+            .map(|(start, end)| start..end)
+            .unwrap_or(0..std::u64::MAX)
+    }
+
+    /// The number of bytes missing from the longest prefix of the pattern
+    /// that was written. Zero means that the pattern was written in full.
+    pub fn prefix_error(&self, memory: &SparseData) -> usize {
+        let found = memory
+            .longest_prefix_within(&self.bytes, self.range())
+            .map_or(0, |(_, len)| len);
+        self.bytes.len() - found
+    }
+
+    /// The number of bytes missing from the longest run of the pattern's
+    /// bytes, from anywhere in the pattern, that was written. This rewards
+    /// partial progress that doesn't begin with the pattern's first byte.
+    pub fn substring_error(&self, memory: &SparseData) -> usize {
+        let found = memory
+            .longest_substring_within(&self.bytes, self.range())
+            .map_or(0, |(_, len)| len);
+        self.bytes.len() - found
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::emulator::profiler::MemLogEntry;
+
+    use super::*;
+
+    fn written_at(address: u64, bytes: &[u8]) -> SparseData {
+        bytes
+            .iter()
+            .enumerate()
+            .map(|(i, b)| MemLogEntry {
+                program_counter: 0,
+                address: address + i as u64,
+                num_bytes_written: 1,
+                value: *b as u64,
+            })
+            .collect::<Vec<MemLogEntry>>()
+            .into()
+    }
+
+    #[test]
+    fn test_parse_memory_patterns() {
+        let data = "string = \"AB\"\n---\nbytes = [1, 2, 3]\naddress = 4096\n";
+        let patterns = parse_memory_patterns(data).expect("failed to parse");
+        assert_eq!(patterns.len(), 2);
+        assert_eq!(patterns[0].bytes, b"AB".to_vec());
+        assert_eq!(patterns[0].region, None);
+        assert_eq!(patterns[1].bytes, vec![1, 2, 3]);
+        assert_eq!(patterns[1].region, Some((0x1000, 0x1001)));
+
+        assert!(parse_memory_patterns("address = 4096\n").is_err());
+    }
+
+    #[test]
+    fn test_memory_pattern_errors() {
+        let memory = written_at(0x1000, b"xxBCDEFG");
+        let pattern = MemoryPattern::from(b"ABCDEFGH".to_vec());
+        assert_eq!(pattern.prefix_error(&memory), 8);
+        assert_eq!(pattern.substring_error(&memory), 2);
+
+        let memory = written_at(0x1000, b"ABCDxxxx");
+        assert_eq!(pattern.prefix_error(&memory), 4);
+
+        let confined = MemoryPattern {
+            bytes: b"ABCD".to_vec(),
+            region: Some((0x2000, 0x3000)),
+        };
+        assert_eq!(confined.prefix_error(&memory), 4);
+        assert_eq!(confined.prefix_error(&written_at(0x2ff0, b"ABCD")), 0);
+    }
+}
@@ -1,5 +1,8 @@
 pub mod hatchery;
 pub mod loader;
+pub mod memory_pattern;
 pub mod pack;
 pub mod profiler;
+pub mod reachability;
 pub mod register_pattern;
+pub mod stages;
@@ -2,7 +2,7 @@ use byteorder::{BigEndian, ByteOrder, LittleEndian};
 use hashbrown::HashMap;
 
 use crate::emulator::loader;
-use crate::util::architecture::{Endian, Perms};
+use crate::util::architecture::{write_integer, Endian, Perms};
 
 pub trait Pack {
     fn pack(
@@ -75,3 +75,18 @@ impl Pack for Vec<u64> {
             .collect::<Vec<_>>()
     }
 }
+
+/// Counts the bytes in `bytes` that appear among the `bad_bytes`.
+pub fn count_bad_bytes(bytes: &[u8], bad_bytes: &[u8]) -> usize {
+    bytes.iter().filter(|b| bad_bytes.contains(b)).count()
+}
+
+/// Returns true if the packed representation of `word` contains any of the `bad_bytes`.
+pub fn word_has_bad_bytes(word: u64, word_size: usize, endian: Endian, bad_bytes: &[u8]) -> bool {
+    if bad_bytes.is_empty() {
+        return false;
+    }
+    let mut bytes = vec![0_u8; word_size];
+    write_integer(endian, word_size, word, &mut bytes);
+    count_bad_bytes(&bytes, bad_bytes) > 0
+}
@@ -1,7 +1,8 @@
 use std::cmp::{Ord, PartialOrd};
 use std::collections::BTreeMap;
 use std::fmt;
-use std::sync::atomic::AtomicUsize;
+use std::ops::RangeBounds;
+use std::sync::atomic::{AtomicBool, AtomicUsize};
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
@@ -14,9 +15,11 @@ use subslice::SubsliceExt;
 pub use unicorn::unicorn_const::Error as UCError;
 use unicorn::Cpu;
 
+use crate::configure::ChainMode;
 use crate::emulator::loader;
 use crate::emulator::loader::{get_static_memory_image, try_to_get_static_memory_image, Seg};
 use crate::emulator::register_pattern::{Register, RegisterState};
+use crate::emulator::stages::StageProgress;
 use crate::util::architecture::{write_integer, Endian};
 
 #[derive(Clone, PartialEq, Eq, Ord, PartialOrd, Serialize, Deserialize, Hash)]
@@ -54,12 +57,33 @@ impl fmt::Debug for Block {
     }
 }
 
+/// A snapshot of the machine state, taken at a composable boundary (or at a
+/// system call), while the emulator is running.
+pub struct BoundarySnapshot<C: Cpu<'static>> {
+    pub registers: HashMap<Register<C>, u64>,
+    pub memory: Option<SparseDataHelper>,
+    pub syscall: bool,
+}
+
+/// The state of the machine at a composable boundary, as recorded in the `Profile`.
+#[derive(Debug, Clone)]
+pub struct Boundary {
+    pub registers: RegisterState,
+    /// The memory written up to this point, if memory snapshots were requested.
+    pub memory: Option<SparseData>,
+    /// True if this snapshot was taken at a system call, rather than at a
+    /// gadget boundary.
+    pub syscall: bool,
+}
+
 pub struct Profiler<C: Cpu<'static>> {
     /// The Arc<RwLock<_>> fields need to be writeable for the unicorn callbacks.
     pub trace_log: Arc<SegQueue<Block>>,
     pub committed_trace_log: Arc<Mutex<Vec<Block>>>,
 
     pub ret_count: Arc<AtomicUsize>,
+    /// Counts the gadget transitions mediated by a dispatcher, in JOP and COP mode.
+    pub dispatch_count: Arc<AtomicUsize>,
     pub call_stack_depth: Arc<AtomicUsize>,
     pub gadget_log: Arc<SegQueue<u64>>,
     //Arc<RwLock<Vec<u64>>>,
@@ -70,15 +94,25 @@ pub struct Profiler<C: Cpu<'static>> {
     //Arc<RwLock<Vec<MemLogEntry>>>,
     pub cpu_error: Option<unicorn::Error>,
     pub emulation_time: Duration,
+    /// The number of forbidden bytes found in the packed payload.
+    pub bad_byte_count: usize,
+    /// Whether to snapshot the machine state at each boundary, and whether
+    /// those snapshots should include the memory written so far.
+    pub record_boundaries: bool,
+    pub snapshot_memory: bool,
+    pub boundary_log: Arc<Mutex<Vec<BoundarySnapshot<C>>>>,
     pub registers_at_last_ret: Arc<Mutex<HashMap<Register<C>, u64>>>,
     pub registers_to_read: Vec<Register<C>>,
     pub input: HashMap<Register<C>, u64>,
+    /// Set by the target hook, if execution reaches the target address.
+    pub target_reached: Arc<AtomicBool>,
 }
 
 impl<C: Cpu<'static>> Default for Profiler<C> {
     fn default() -> Self {
         Self {
             ret_count: Arc::new(AtomicUsize::new(0)),
+            dispatch_count: Arc::new(AtomicUsize::new(0)),
             call_stack_depth: Arc::new(AtomicUsize::new(0)),
             write_log: Arc::new(SegQueue::new()), //Arc::new(RwLock::new(Vec::default())),
             input: HashMap::default(),
@@ -86,11 +120,16 @@ impl<C: Cpu<'static>> Default for Profiler<C> {
             cpu_error: None,
             registers_to_read: Vec::new(),
             emulation_time: Duration::default(),
+            bad_byte_count: 0,
+            record_boundaries: false,
+            snapshot_memory: false,
+            boundary_log: Arc::new(Mutex::new(Vec::new())),
             trace_log: Arc::new(SegQueue::new()),
             gadget_log: Arc::new(SegQueue::new()), //Arc::new(RwLock::new(Vec::new())),
             written_memory: vec![],
             committed_write_log: Default::default(),
             committed_trace_log: Default::default(),
+            target_reached: Arc::new(AtomicBool::new(false)),
         }
     }
 }
@@ -132,6 +171,21 @@ pub struct Profile {
     pub memory_writes: Vec<SparseData>,
     pub executable: bool,
     pub ret_counts: Vec<usize>,
+    #[serde(default)]
+    pub dispatch_counts: Vec<usize>,
+    #[serde(default)]
+    pub bad_byte_counts: Vec<usize>,
+    /// The machine state at each boundary, for each run. Only recorded when
+    /// a goal depends on intermediate states, such as a staged goal.
+    #[serde(skip)]
+    pub boundaries: Vec<Vec<Boundary>>,
+    /// How far each run got through the stages, with the boundary at which
+    /// each stage was satisfied. Only recorded when there are stages.
+    #[serde(default)]
+    pub stage_progress: Vec<StageProgress>,
+    /// Whether each run reached the `reach_target` address.
+    #[serde(default)]
+    pub targets_reached: Vec<bool>,
 }
 
 fn fetch_code_executed(path: &Vec<Block>, extra_segs: Option<&[Seg]>) -> Vec<u8> {
@@ -160,6 +214,10 @@ impl<C: 'static + Cpu<'static>> From<Profiler<C>> for Profile {
         let mut gadgets_executed = Vec::new();
         let mut memory_writes = Vec::new();
         let mut ret_counts = Vec::new();
+        let mut dispatch_counts = Vec::new();
+        let mut bad_byte_counts = Vec::new();
+        let mut boundaries = Vec::new();
+        let mut targets_reached = Vec::new();
         let mut code_paths_executed = Vec::new();
 
         let Profiler {
@@ -168,14 +226,20 @@ impl<C: 'static + Cpu<'static>> From<Profiler<C>> for Profile {
             write_log,
             cpu_error,
             emulation_time,
+            bad_byte_count,
+            record_boundaries: _,
+            snapshot_memory: _,
+            boundary_log,
             registers_at_last_ret: registers,
             gadget_log,
             written_memory,
             ret_count,
+            dispatch_count,
             committed_write_log,
             committed_trace_log,
             registers_to_read,
             input,
+            target_reached,
         } = p;
         let path = Arc::try_unwrap(committed_trace_log)
             .ok()
@@ -207,6 +271,22 @@ impl<C: 'static + Cpu<'static>> From<Profiler<C>> for Profile {
         // memory_writes.push(segqueue_to_vec(write_log).into());
 
         ret_counts.push(ret_count.load(std::sync::atomic::Ordering::Relaxed));
+        dispatch_counts.push(dispatch_count.load(std::sync::atomic::Ordering::Relaxed));
+        bad_byte_counts.push(bad_byte_count);
+        targets_reached.push(target_reached.load(std::sync::atomic::Ordering::Relaxed));
+        // The register states are spidered through the memory as it stands at
+        // the end of execution, which is an approximation.
+        let snapshots = std::mem::take(&mut *boundary_log.lock().unwrap());
+        boundaries.push(
+            snapshots
+                .into_iter()
+                .map(|snapshot| Boundary {
+                    registers: RegisterState::new::<C>(&snapshot.registers, Some(&written_memory)),
+                    memory: snapshot.memory.map(SparseData::from),
+                    syscall: snapshot.syscall,
+                })
+                .collect::<Vec<Boundary>>(),
+        );
 
         if cfg!(debug_assertions) {
             log::debug!(
@@ -241,6 +321,11 @@ impl<C: 'static + Cpu<'static>> From<Profiler<C>> for Profile {
             memory_writes,
             executable: true,
             ret_counts,
+            dispatch_counts,
+            bad_byte_counts,
+            boundaries,
+            stage_progress: vec![],
+            targets_reached,
         }
     }
 }
@@ -268,6 +353,11 @@ impl Profile {
             memory_writes,
             executable,
             ret_counts,
+            dispatch_counts,
+            bad_byte_counts,
+            boundaries,
+            stage_progress,
+            targets_reached,
         } = other;
 
         self.paths.extend(paths.into_iter());
@@ -278,9 +368,32 @@ impl Profile {
         self.gadgets_executed.extend(gadgets_executed.into_iter());
         self.memory_writes.extend(memory_writes.into_iter());
         self.ret_counts.extend(ret_counts.into_iter());
+        self.dispatch_counts.extend(dispatch_counts.into_iter());
+        self.bad_byte_counts.extend(bad_byte_counts.into_iter());
+        self.boundaries.extend(boundaries.into_iter());
+        self.stage_progress.extend(stage_progress.into_iter());
+        self.targets_reached.extend(targets_reached.into_iter());
         self.executable &= executable;
     }
 
+    /// Returns the number of composable gadget transitions observed in the
+    /// `index`th run: `ret` boundaries for ROP chains, and transitions through
+    /// the dispatcher for JOP and COP chains.
+    pub fn gadget_transitions(&self, index: usize, mode: ChainMode) -> usize {
+        let counts = match mode {
+            ChainMode::Rop => &self.ret_counts,
+            ChainMode::Jop | ChainMode::Cop => &self.dispatch_counts,
+        };
+        counts.get(index).cloned().unwrap_or(0)
+    }
+
+    pub fn total_gadget_transitions(&self, mode: ChainMode) -> usize {
+        match mode {
+            ChainMode::Rop => self.ret_counts.iter().sum(),
+            ChainMode::Jop | ChainMode::Cop => self.dispatch_counts.iter().sum(),
+        }
+    }
+
     pub fn avg_emulation_micros(&self) -> f64 {
         self.emulation_times.iter().sum::<Duration>().as_micros() as f64
             / self.emulation_times.len() as f64
@@ -513,6 +626,86 @@ impl SparseData {
     pub fn len(&self) -> usize {
         self.0.values().map(|buf| buf.len()).sum()
     }
+
+    /// Returns the address and length of the longest prefix of `seq` to be found
+    /// among the written bytes, or `None` if not even the first byte was written.
+    pub fn longest_prefix(&self, seq: &[u8]) -> Option<(u64, usize)> {
+        self.longest_prefix_within(seq, ..)
+    }
+
+    /// Like `longest_prefix`, but only considers matches beginning at an
+    /// address in `range`.
+    pub fn longest_prefix_within<R: RangeBounds<u64>>(
+        &self,
+        seq: &[u8],
+        range: R,
+    ) -> Option<(u64, usize)> {
+        let mut best: Option<(u64, usize)> = None;
+        for (addr, buf) in self.0.iter() {
+            for start in 0..buf.len() {
+                if !range.contains(&(addr + start as u64)) {
+                    continue;
+                }
+                let len = common_prefix_len(&buf[start..], seq);
+                if len > best.map_or(0, |(_, l)| l) {
+                    best = Some((addr + start as u64, len));
+                    if len == seq.len() {
+                        return best;
+                    }
+                }
+            }
+        }
+        best
+    }
+
+    /// Returns the address and length of the longest contiguous run of bytes
+    /// from anywhere in `seq` to be found among the written bytes, beginning
+    /// at an address in `range`.
+    pub fn longest_substring_within<R: RangeBounds<u64>>(
+        &self,
+        seq: &[u8],
+        range: R,
+    ) -> Option<(u64, usize)> {
+        let mut best: Option<(u64, usize)> = None;
+        for (addr, buf) in self.0.iter() {
+            for start in 0..buf.len() {
+                if !range.contains(&(addr + start as u64)) {
+                    continue;
+                }
+                for offset in 0..seq.len() {
+                    let len = common_prefix_len(&buf[start..], &seq[offset..]);
+                    if len > best.map_or(0, |(_, l)| l) {
+                        best = Some((addr + start as u64, len));
+                        if len == seq.len() {
+                            return best;
+                        }
+                    }
+                }
+            }
+        }
+        best
+    }
+
+    /// Iterates over the written bytes, with their addresses, in address order.
+    pub fn bytes(&self) -> impl Iterator<Item = (u64, u8)> + '_ {
+        self.0.iter().flat_map(|(addr, buf)| {
+            buf.iter()
+                .enumerate()
+                .map(move |(i, b)| (addr + i as u64, *b))
+        })
+    }
+
+    /// Returns the byte written to `addr`, if any.
+    pub fn byte_at(&self, addr: u64) -> Option<u8> {
+        self.0
+            .range(..=addr)
+            .next_back()
+            .and_then(|(start, buf)| buf.get((addr - start) as usize).copied())
+    }
+}
+
+fn common_prefix_len(a: &[u8], b: &[u8]) -> usize {
+    a.iter().zip(b.iter()).take_while(|(x, y)| x == y).count()
 }
 
 impl fmt::Debug for SparseData {
@@ -0,0 +1,191 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+
+use falcon::il;
+use hashbrown::HashMap;
+
+use crate::emulator::profiler::Block;
+
+/// The number of control flow edges between each basic block of the program
+/// and the block containing the target address, found by a breadth-first
+/// search backwards from the target. Call edges are followed into their
+/// callees, and calls fall through to their return sites, as in falcon's
+/// control flow graphs.
+#[derive(Clone, PartialEq, Eq)]
+pub struct TargetDistances {
+    pub target: u64,
+    /// Maps the first address of each block to its last address and its
+    /// distance from the target. Blocks from which the target is
+    /// unreachable are omitted.
+    blocks: BTreeMap<u64, (u64, usize)>,
+    max_distance: usize,
+}
+
+impl fmt::Debug for TargetDistances {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "TargetDistances {{ target: {:#x}, reachable blocks: {}, max distance: {} }}",
+            self.target,
+            self.blocks.len(),
+            self.max_distance
+        )
+    }
+}
+
+impl TargetDistances {
+    /// Computes the distances over a graph of blocks, each given as its
+    /// first and last address, and edges given as pairs of indices into
+    /// `blocks`.
+    pub fn from_graph(blocks: &[(u64, u64)], edges: &[(usize, usize)], target: u64) -> Self {
+        let mut predecessors: HashMap<usize, Vec<usize>> = HashMap::new();
+        for (from, to) in edges {
+            predecessors.entry(*to).or_default().push(*from);
+        }
+        let mut distances: HashMap<usize, usize> = HashMap::new();
+        let mut queue = VecDeque::new();
+        for (i, (start, end)) in blocks.iter().enumerate() {
+            if *start <= target && target <= *end {
+                distances.insert(i, 0);
+                queue.push_back(i);
+            }
+        }
+        while let Some(node) = queue.pop_front() {
+            let distance = distances[&node];
+            for pred in predecessors.get(&node).into_iter().flatten() {
+                if !distances.contains_key(pred) {
+                    distances.insert(*pred, distance + 1);
+                    queue.push_back(*pred);
+                }
+            }
+        }
+        let max_distance = distances.values().cloned().max().unwrap_or(0);
+        let blocks = distances
+            .into_iter()
+            .map(|(i, d)| (blocks[i].0, (blocks[i].1, d)))
+            .collect();
+        Self {
+            target,
+            blocks,
+            max_distance,
+        }
+    }
+
+    /// Builds the interprocedural control flow graph of the lifted program
+    /// and computes the distances over it.
+    pub fn from_program(program: &il::Program, target: u64) -> Self {
+        let mut blocks: Vec<(u64, u64)> = vec![];
+        let mut edges: Vec<(usize, usize)> = vec![];
+        // Blocks without addressed instructions are still nodes in the
+        // graph, so that paths through them are kept.
+        let mut node_of: HashMap<(usize, usize), usize> = HashMap::new();
+        let mut entry_of: HashMap<u64, usize> = HashMap::new();
+        let mut calls: Vec<(usize, u64)> = vec![];
+
+        for (f_idx, function) in program.functions().into_iter().enumerate() {
+            let cfg = function.control_flow_graph();
+            for block in cfg.blocks() {
+                let addresses = block
+                    .instructions()
+                    .iter()
+                    .filter_map(il::Instruction::address)
+                    .collect::<Vec<u64>>();
+                let span = match (addresses.iter().min(), addresses.iter().max()) {
+                    (Some(first), Some(last)) => (*first, *last),
+                    // An empty range never contains the target.
+                    _ => (1, 0),
+                };
+                let node = blocks.len();
+                blocks.push(span);
+                node_of.insert((f_idx, block.index()), node);
+                for instruction in block.instructions() {
+                    if let il::Operation::Branch {
+                        target: il::Expression::Constant(c),
+                    } = instruction.operation()
+                    {
+                        if let Some(callee) = c.value_u64() {
+                            calls.push((node, callee));
+                        }
+                    }
+                }
+            }
+            if let Some(entry) = cfg.entry() {
+                entry_of.insert(function.address(), node_of[&(f_idx, entry)]);
+            }
+            for edge in cfg.edges() {
+                edges.push((
+                    node_of[&(f_idx, edge.head())],
+                    node_of[&(f_idx, edge.tail())],
+                ));
+            }
+        }
+        for (node, callee) in calls {
+            if let Some(entry) = entry_of.get(&callee) {
+                edges.push((node, *entry));
+            }
+        }
+        let distances = Self::from_graph(&blocks, &edges, target);
+        log::info!("Computed control flow distances: {:?}", distances);
+        distances
+    }
+
+    /// The distance from the block containing `addr` to the target, if the
+    /// target is reachable from it.
+    pub fn distance(&self, addr: u64) -> Option<usize> {
+        self.blocks
+            .range(..=addr)
+            .next_back()
+            .filter(|(_, (last, _))| addr <= *last)
+            .map(|(_, (_, distance))| *distance)
+    }
+
+    /// The least distance from any of the blocks on the path to the target,
+    /// or one more than the greatest distance in the graph, if the target
+    /// is reachable from none of them.
+    pub fn min_distance(&self, path: &[Block]) -> usize {
+        path.iter()
+            .filter_map(|block| {
+                if block.entry <= self.target && self.target < block.entry + block.size as u64 {
+                    Some(0)
+                } else {
+                    self.distance(block.entry)
+                }
+            })
+            .min()
+            .unwrap_or(self.max_distance + 1)
+    }
+
+    pub fn max_distance(&self) -> usize {
+        self.max_distance
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_target_distances() {
+        // 0 -> 1 -> 2 (target), and 3, which is cut off
+        let blocks = [(0x10, 0x1f), (0x20, 0x2f), (0x30, 0x3f), (0x40, 0x4f)];
+        let edges = [(0, 1), (1, 2), (2, 3)];
+        let distances = TargetDistances::from_graph(&blocks, &edges, 0x34);
+        assert_eq!(distances.distance(0x30), Some(0));
+        assert_eq!(distances.distance(0x18), Some(2));
+        assert_eq!(distances.distance(0x44), None);
+        assert_eq!(distances.distance(0x50), None);
+
+        let path = vec![
+            Block {
+                entry: 0x44,
+                size: 4,
+            },
+            Block {
+                entry: 0x22,
+                size: 4,
+            },
+        ];
+        assert_eq!(distances.min_distance(&path), 1);
+        assert_eq!(distances.min_distance(&path[..1]), 3);
+    }
+}
@@ -9,10 +9,10 @@ use unicorn::Cpu;
 
 use crate::emulator::loader;
 use crate::emulator::loader::{get_static_memory_image, Seg};
+use crate::emulator::profiler::SparseData;
 use crate::error::Error;
 use crate::util;
-use crate::util::architecture::{write_integer, Endian};
-use crate::util::bitwise;
+use crate::util::architecture::{read_integer, write_integer, Endian, Perms};
 use crate::util::bitwise::nybble;
 use std::ops::Sub;
 
@@ -43,6 +43,34 @@ pub type Register<C> = <C as Cpu<'static>>::Reg;
 // - it contains the head or tail of the target (so that sliding it along may find the target)
 // - it points to writeable address. then look at hamming distance.
 
+/// The form of value a register is expected to hold.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub enum ValueKind {
+    /// Any one of the words in `vals`.
+    Exact,
+    /// A word in the half-open range `vals[0]..vals[1]`.
+    Range,
+    /// A word `w` such that `w & vals[0] == vals[1]`.
+    Mask,
+    /// A pointer into readable memory.
+    Readable,
+    /// A pointer into writeable memory.
+    Writeable,
+    /// Anything but the words in `vals`.
+    NotEqual,
+    /// Anything at all.
+    Any,
+    /// A pointer to this sequence of bytes. Used for strings too long to
+    /// pack into a single word.
+    Bytes(Vec<u8>),
+}
+
+impl Default for ValueKind {
+    fn default() -> Self {
+        Self::Exact
+    }
+}
+
 /// For example, if EAX <- 0xdeadbeef, then EAX holds
 /// `RegisterValue { val: 0xdeadbeef, deref: 0 }`.
 /// But if EAX <- 0x12345678 <- 0xdeadbeef, then we have
@@ -52,11 +80,21 @@ pub type Register<C> = <C as Cpu<'static>>::Reg;
 pub struct RegisterValue {
     pub vals: Vec<u64>, // Alternatives
     pub deref: usize,
+    #[serde(default)]
+    pub kind: ValueKind,
 }
 
+/// Penalty for holding a value that a `NotEqual` pattern rules out. The
+/// penalty is all or nothing, rather than graded by closeness to the
+/// forbidden value, since every other word satisfies the pattern exactly:
+/// a graded penalty would count against values that are already correct.
+/// It is what `weighted_ham` charges for a wrong most significant bit, the
+/// costliest single-bit miss for the other kinds of value.
+const NOT_EQUAL_PENALTY: f64 = 64.0;
+
 impl RegisterValue {
     fn reduce_references(&mut self) {
-        if self.deref == 0 {
+        if self.deref == 0 || self.kind != ValueKind::Exact {
             return;
         }
         let memory = get_static_memory_image();
@@ -69,6 +107,48 @@ impl RegisterValue {
             self.deref -= 1;
         }
     }
+
+    /// The position in the dereference chain at which the value is to be found.
+    /// A `Bytes` value is matched against the pointer to its first byte, one step
+    /// earlier in the chain.
+    fn chain_position(&self) -> usize {
+        match self.kind {
+            ValueKind::Bytes(_) => self.deref.saturating_sub(1),
+            _ => self.deref,
+        }
+    }
+
+    /// How far the word `w` is from satisfying this value, once it has been
+    /// found in the dereference chain. `memory` holds the writes made during
+    /// execution, which are consulted, before the static memory image, when
+    /// matching `Bytes`.
+    fn word_distance(&self, w: u64, memory: Option<&SparseData>) -> f64 {
+        match self.kind {
+            ValueKind::Exact => least_word_distance(w, &self.vals),
+            ValueKind::Range => {
+                let (lo, hi) = (self.vals[0], self.vals[1]);
+                if w < lo {
+                    weighted_ham(w, lo)
+                } else if w >= hi {
+                    weighted_ham(w, hi - 1)
+                } else {
+                    0.0
+                }
+            }
+            ValueKind::Mask => weighted_ham(w & self.vals[0], self.vals[1]),
+            ValueKind::Readable => pointer_distance(w, Perms::READ),
+            ValueKind::Writeable => pointer_distance(w, Perms::WRITE),
+            ValueKind::NotEqual => {
+                if self.vals.contains(&w) {
+                    NOT_EQUAL_PENALTY
+                } else {
+                    0.0
+                }
+            }
+            ValueKind::Any => 0.0,
+            ValueKind::Bytes(ref bytes) => bytes_distance(w, bytes, memory),
+        }
+    }
 }
 
 impl From<u64> for RegisterValue {
@@ -76,61 +156,140 @@ impl From<u64> for RegisterValue {
         Self {
             vals: vec![val],
             deref: 0,
+            kind: ValueKind::Exact,
+        }
+    }
+}
+
+fn word_format() -> (Endian, usize) {
+    if let Some(memory) = loader::try_to_get_static_memory_image() {
+        (memory.endian, memory.word_size)
+    } else {
+        (Endian::Little, 8)
+    }
+}
+
+fn parse_number(s: &str) -> Result<u64, Error> {
+    let s = s.trim();
+    if s.starts_with("0x") {
+        Ok(u64::from_str_radix(s.trim_start_matches("0x"), 16)?)
+    } else {
+        Ok(u64::from_str_radix(s, 10)?)
+    }
+}
+
+/// Parse the body of a quoted string, up to its closing quote, handling the
+/// escapes `\0`, `\n`, `\r`, `\t`, `\\`, `\'` and `\xHH`. For the sake of
+/// older pattern files, the closing quote may be omitted.
+fn parse_string_literal(s: &str) -> Result<Vec<u8>, Error> {
+    let mut bytes = Vec::new();
+    let mut chars = s.chars();
+    loop {
+        let ch = match chars.next() {
+            None => return Ok(bytes),
+            Some(ch) => ch,
+        };
+        if ch == '\'' {
+            if chars.as_str().trim().is_empty() {
+                return Ok(bytes);
+            }
+            return Err(Error::Parsing(format!(
+                "Trailing characters after string: '{}",
+                s
+            )));
+        }
+        if ch != '\\' {
+            let mut buf = [0_u8; 4];
+            bytes.extend_from_slice(ch.encode_utf8(&mut buf).as_bytes());
+            continue;
+        }
+        match chars.next() {
+            Some('0') => bytes.push(0),
+            Some('n') => bytes.push(b'\n'),
+            Some('r') => bytes.push(b'\r'),
+            Some('t') => bytes.push(b'\t'),
+            Some('\\') => bytes.push(b'\\'),
+            Some('\'') => bytes.push(b'\''),
+            Some('x') => {
+                let hex = chars.by_ref().take(2).collect::<String>();
+                bytes.push(u8::from_str_radix(&hex, 16)?);
+            }
+            other => {
+                return Err(Error::Parsing(format!(
+                    "Invalid escape sequence in string: \\{}",
+                    other.map(|c| c.to_string()).unwrap_or_default()
+                )))
+            }
         }
     }
 }
 
 /// Grammar:
+/// ```text
+/// RegisterValue -> & RegisterValue | Atom
+/// Atom -> numeric_literal
+///       | numeric_literal .. numeric_literal   (half-open range)
+///       | numeric_literal & numeric_literal    (mask & expected bits)
+///       | ! numeric_literal                    (anything but)
+///       | _                                    (anything)
+///       | readable | writeable                 (pointer to such memory)
+///       | ' string '
 /// ```
-/// RegisterValue -> numeric_literal | & RegisterValue
-/// ```
+/// Strings that fit in a word are packed into a word, using the endianness
+/// of the loaded binary. Longer strings must be dereferenced at least once,
+/// and are matched byte by byte against the memory they point to.
 impl FromStr for RegisterValue {
     type Err = Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        fn parse_rv<I: Iterator<Item = char>>(
-            mut chars: I,
-            deref: usize,
-        ) -> Result<RegisterValue, Error> {
-            let ch = chars.next();
-            match ch {
-                None => Err(Error::Parsing("Invalid register value".into())),
-                Some('&') => parse_rv(chars, deref + 1),
-                Some(' ') => parse_rv(chars, deref),
-                // You could handle short strings here, too.
-                // Match on an opening single quote, and pack `word_size` bytes into an
-                // integer. TODO
-                Some('\'') => {
-                    let s = chars.collect::<String>();
-                    // FIXME: don't hardcode the endian
-                    if let Some(w) = bitwise::try_str_as_word(s, Endian::Little) {
-                        Ok(RegisterValue {
-                            vals: vec![w],
-                            deref,
-                        })
-                    } else {
-                        Err(Error::Parsing(
-                            "can only encode strings of fewer than 8 characters".into(),
-                        ))
-                    }
-                }
-                Some(x) => {
-                    let rest = chars.collect::<String>();
-                    let numeral = format!("{}{}", x, rest);
-                    let val = if numeral.starts_with("0x") {
-                        let numeral = numeral.trim_start_matches("0x");
-                        u64::from_str_radix(&numeral, 16)?
-                    } else {
-                        u64::from_str_radix(&numeral, 10)?
-                    };
-                    Ok(RegisterValue {
-                        vals: vec![val],
-                        deref,
-                    })
-                }
+        let s = s.trim();
+        let deref = s
+            .chars()
+            .take_while(|c| *c == '&' || c.is_whitespace())
+            .filter(|c| *c == '&')
+            .count();
+        let body = s.trim_start_matches(|c: char| c == '&' || c.is_whitespace());
+
+        let (kind, vals) = if body.is_empty() {
+            return Err(Error::Parsing("Invalid register value".into()));
+        } else if body == "_" {
+            (ValueKind::Any, vec![])
+        } else if body == "readable" {
+            (ValueKind::Readable, vec![])
+        } else if body == "writeable" || body == "writable" {
+            (ValueKind::Writeable, vec![])
+        } else if body.starts_with('\'') {
+            let bytes = parse_string_literal(&body[1..])?;
+            let (endian, word_size) = word_format();
+            if bytes.len() <= word_size {
+                let mut word = vec![0_u8; word_size];
+                word[..bytes.len()].copy_from_slice(&bytes);
+                let w = read_integer(&word, endian, word_size).expect("word is word-sized");
+                (ValueKind::Exact, vec![w])
+            } else if deref == 0 {
+                return Err(Error::Parsing(format!(
+                    "Strings longer than {} bytes must be dereferenced: {}",
+                    word_size, s
+                )));
+            } else {
+                (ValueKind::Bytes(bytes), vec![])
             }
-        }
-        parse_rv(s.chars(), 0)
+        } else if body.starts_with('!') {
+            (ValueKind::NotEqual, vec![parse_number(&body[1..])?])
+        } else if let Some(i) = body.find("..") {
+            let (lo, hi) = (parse_number(&body[..i])?, parse_number(&body[i + 2..])?);
+            if lo >= hi {
+                return Err(Error::Parsing(format!("Empty range: {}", body)));
+            }
+            (ValueKind::Range, vec![lo, hi])
+        } else if let Some(i) = body.find('&') {
+            let mask = parse_number(&body[..i])?;
+            let bits = parse_number(&body[i + 1..])?;
+            (ValueKind::Mask, vec![mask, bits & mask])
+        } else {
+            (ValueKind::Exact, vec![parse_number(body)?])
+        };
+        Ok(RegisterValue { vals, deref, kind })
     }
 }
 
@@ -268,6 +427,48 @@ fn least_word_distance(w1: u64, w2s: &[u64]) -> f64 {
         .unwrap() as f64
 }
 
+/// The distance from `w` to the nearest address with the permissions `perm`.
+fn pointer_distance(w: u64, perm: Perms) -> f64 {
+    let memory = get_static_memory_image();
+    memory
+        .segments()
+        .iter()
+        .filter(|seg| seg.perm.intersects(perm))
+        .map(|seg| {
+            if w < seg.aligned_start() {
+                weighted_ham(w, seg.aligned_start())
+            } else if w >= seg.aligned_end() {
+                weighted_ham(w, seg.aligned_end() - 1)
+            } else {
+                0.0
+            }
+        })
+        .fold(std::f64::MAX, |a, b| a.min(b))
+}
+
+fn read_byte(addr: u64, memory: Option<&SparseData>) -> Option<u8> {
+    memory.and_then(|m| m.byte_at(addr)).or_else(|| {
+        loader::try_to_get_static_memory_image()
+            .and_then(|image| image.try_dereference(addr, None))
+            .and_then(|bytes| bytes.first().copied())
+    })
+}
+
+/// The number of bits by which the bytes at `ptr` differ from `target`,
+/// counting unreadable bytes as entirely wrong.
+fn bytes_distance(ptr: u64, target: &[u8], memory: Option<&SparseData>) -> f64 {
+    target
+        .iter()
+        .enumerate()
+        .map(
+            |(i, t)| match read_byte(ptr.wrapping_add(i as u64), memory) {
+                Some(b) => (b ^ t).count_ones() as f64,
+                None => 8.0,
+            },
+        )
+        .sum()
+}
+
 fn max_word_distance() -> f64 {
     get_static_memory_image().word_size as f64 * 8.0
 }
@@ -275,6 +476,16 @@ fn max_word_distance() -> f64 {
 // TODO: write some integration tests for this. there's a LOT of room for error!
 impl RegisterPattern {
     pub fn distance_from_register_state(&self, register_state: &RegisterState) -> f64 {
+        self.distance_from_register_state_with_memory(register_state, None)
+    }
+
+    /// Like `distance_from_register_state`, but consults the memory written during
+    /// execution when matching values that point to strings.
+    pub fn distance_from_register_state_with_memory(
+        &self,
+        register_state: &RegisterState,
+        memory: Option<&SparseData>,
+    ) -> f64 {
         const WRONG_REG_PENALTY: f64 = 5.0;
 
         let summed_dist = self
@@ -285,7 +496,7 @@ impl RegisterPattern {
                     .keys()
                     .map(|r| {
                         let mut d = register_state
-                            .distance_from_register_val(r, r_val)
+                            .distance_from_register_val(r, r_val, memory)
                             .expect("Failed to get distance from register val");
                         log::debug!("[{}] summed_dist_for_reg({}, {:x?}) = {}", reg, r, r_val, d);
                         if r != reg {
@@ -313,7 +524,7 @@ impl RegisterPattern {
             .iter()
             .filter_map(|(reg, r_val)| {
                 let d = register_state
-                    .distance_from_register_val(reg, r_val)
+                    .distance_from_register_val(reg, r_val, None)
                     .expect("Failed to get distance from register val");
                 if d > 0.0 {
                     register_state.0.get(reg).map(|v| (reg, v))
@@ -363,6 +574,10 @@ pub struct RegisterFeature {
 
 impl RegisterFeature {
     fn decompose_reg_val(register: &str, reg_val: &RegisterValue, reg_feats: &mut Vec<Self>) {
+        // Only exact values can be broken down into the nybbles they must contain.
+        if reg_val.kind != ValueKind::Exact {
+            return;
+        }
         let word_size = get_static_memory_image().word_size;
 
         for i in 0..(word_size * 2) {
@@ -455,7 +670,12 @@ impl RegisterState {
         map
     }
 
-    fn distance_from_register_val(&self, reg: &str, r_val: &RegisterValue) -> Result<f64, Error> {
+    fn distance_from_register_val(
+        &self,
+        reg: &str,
+        r_val: &RegisterValue,
+        memory: Option<&SparseData>,
+    ) -> Result<f64, Error> {
         fn pos_distance(pos: usize, target: usize) -> f64 {
             let pos_dist_scale: f64 = 4.0 * get_static_memory_image().word_size as f64;
             let dist = pos as i32 - target as i32;
@@ -478,21 +698,22 @@ impl RegisterState {
 
         log::debug!("want {:x?}", r_val);
         if let Some(vals) = self.0.get(reg) {
-            let distance = if r_val.deref == 0 {
+            let position = r_val.chain_position();
+            let distance = if position == 0 {
                 // Immediate values
-                least_word_distance(vals[0], &r_val.vals)
+                r_val.word_distance(vals[0], memory)
             } else {
                 // dereferenced values
                 vals.iter()
                     .enumerate()
                     .map(|(i, val)| {
+                        let d = r_val.word_distance(*val, memory);
                         if is_mutable(i, &vals) {
-                            least_word_distance(*val, &r_val.vals)
-                        } else if r_val.vals.iter().any(|v| v == val) {
-                            0.0 + pos_distance(i, r_val.deref)
+                            d
+                        } else if d == 0.0 {
+                            0.0 + pos_distance(i, position)
                         } else {
-                            2.0 * least_word_distance(*val, &r_val.vals)
-                                + pos_distance(i, r_val.deref)
+                            2.0 * d + pos_distance(i, position)
                         }
                     })
                     .fold1(|a, b| a.min(b))
@@ -593,6 +814,7 @@ mod test {
                 RegisterValue {
                     vals: vec![0xdead_beef],
                     deref: 0,
+                    kind: ValueKind::Exact,
                 },
             ),
             (
@@ -600,6 +822,7 @@ mod test {
                 RegisterValue {
                     vals: vec![0xbeef],
                     deref: 1,
+                    kind: ValueKind::Exact,
                 },
             ),
             (
@@ -607,6 +830,7 @@ mod test {
                 RegisterValue {
                     vals: vec![0],
                     deref: 2,
+                    kind: ValueKind::Exact,
                 },
             ),
             (
@@ -614,6 +838,7 @@ mod test {
                 RegisterValue {
                     vals: vec![1234],
                     deref: 4,
+                    kind: ValueKind::Exact,
                 },
             ),
         ];
@@ -637,11 +862,13 @@ mod test {
             "RAX".to_string() => RegisterValue {
                 vals: vec![0xbeef],
                 deref: 1,
+                kind: ValueKind::Exact,
             },
 
             "RBX".to_string() => RegisterValue {
                 vals: vec![3],
                 deref: 2,
+                kind: ValueKind::Exact,
             },
         });
 
@@ -657,11 +884,13 @@ mod test {
             "RAX".to_string() => RegisterValue {
                 vals: vec![0xbeef],
                 deref: 1,
+                kind: ValueKind::Exact,
             },
 
             "RBX".to_string() => RegisterValue {
                 vals: vec![3],
                 deref: 2,
+                kind: ValueKind::Exact,
             },
         });
 
@@ -706,7 +935,9 @@ mod test {
                 &RegisterValue {
                     vals: vec![0xbeef],
                     deref: 1,
+                    kind: ValueKind::Exact,
                 },
+                None,
             )
             .unwrap();
         assert!(res < std::f64::EPSILON, "Match failed");
@@ -717,7 +948,9 @@ mod test {
                 &RegisterValue {
                     vals: vec![3],
                     deref: 2,
+                    kind: ValueKind::Exact,
                 },
+                None,
             )
             .unwrap();
         assert!(res < std::f64::EPSILON, "Match failed");
@@ -728,7 +961,9 @@ mod test {
                 &RegisterValue {
                     vals: vec![0x1000_beef],
                     deref: 1,
+                    kind: ValueKind::Exact,
                 },
+                None,
             )
             .unwrap();
         assert!(res - 1.0 < std::f64::EPSILON, "Match failed");
@@ -739,7 +974,9 @@ mod test {
                 &RegisterValue {
                     vals: vec![0x1000_beef],
                     deref: 0,
+                    kind: ValueKind::Exact,
                 },
+                None,
             )
             .unwrap();
         assert!(res - 2.0 < std::f64::EPSILON, "Match failed");
@@ -755,10 +992,113 @@ mod test {
                 &RegisterValue {
                     vals: vec![9],
                     deref: 3,
+                    kind: ValueKind::Exact,
                 },
+                None,
             )
             .unwrap();
         println!("res = {}", res);
         assert!(res - (1.0 + 3.0) < std::f64::EPSILON);
     }
+
+    #[test]
+    fn test_extended_register_value_parser() {
+        let rvs = vec![
+            (
+                "0x1000..0x2000",
+                RegisterValue {
+                    vals: vec![0x1000, 0x2000],
+                    deref: 0,
+                    kind: ValueKind::Range,
+                },
+            ),
+            (
+                "0x00ff & 0x41",
+                RegisterValue {
+                    vals: vec![0xff, 0x41],
+                    deref: 0,
+                    kind: ValueKind::Mask,
+                },
+            ),
+            (
+                "!0",
+                RegisterValue {
+                    vals: vec![0],
+                    deref: 0,
+                    kind: ValueKind::NotEqual,
+                },
+            ),
+            (
+                "_",
+                RegisterValue {
+                    vals: vec![],
+                    deref: 0,
+                    kind: ValueKind::Any,
+                },
+            ),
+            (
+                "writeable",
+                RegisterValue {
+                    vals: vec![],
+                    deref: 0,
+                    kind: ValueKind::Writeable,
+                },
+            ),
+            (
+                "'AB'",
+                RegisterValue {
+                    vals: vec![0x4241],
+                    deref: 0,
+                    kind: ValueKind::Exact,
+                },
+            ),
+            (
+                "&'/bin/bash\\0'",
+                RegisterValue {
+                    vals: vec![],
+                    deref: 1,
+                    kind: ValueKind::Bytes(b"/bin/bash\0".to_vec()),
+                },
+            ),
+        ];
+
+        for (s, reg_val) in rvs.into_iter() {
+            let rv: RegisterValue = s.parse().expect("Failed to parse");
+            assert_eq!(rv, reg_val);
+        }
+
+        for s in &["'/bin/bash\\0'", "0x2000..0x1000", "'trailing' junk", "&"] {
+            assert!(
+                s.parse::<RegisterValue>().is_err(),
+                "{} should not parse",
+                s
+            );
+        }
+    }
+
+    #[test]
+    fn test_extended_register_value_distance() {
+        let register_state = RegisterState(hashmap! {
+            "RAX".to_string() => vec![0x1800],
+        });
+        let within: RegisterValue = "0x1000..0x2000".parse().unwrap();
+        let below: RegisterValue = "0x1900..0x2000".parse().unwrap();
+        let masked: RegisterValue = "0xff00 & 0x1800".parse().unwrap();
+        let excluded: RegisterValue = "!0x1800".parse().unwrap();
+        let neighbour_excluded: RegisterValue = "!0x1801".parse().unwrap();
+        let anything: RegisterValue = "_".parse().unwrap();
+
+        let d = |rv: &RegisterValue| {
+            register_state
+                .distance_from_register_val("RAX", rv, None)
+                .unwrap()
+        };
+        assert!(d(&within) < std::f64::EPSILON);
+        assert!(d(&below) > 0.0);
+        assert!(d(&masked) < std::f64::EPSILON);
+        assert!(d(&excluded) > 0.0);
+        // a value merely close to the excluded one is not penalised
+        assert!(d(&neighbour_excluded) < std::f64::EPSILON);
+        assert!(d(&anything) < std::f64::EPSILON);
+    }
 }
@@ -0,0 +1,259 @@
+use serde::{Deserialize, Serialize};
+
+use crate::emulator::profiler::Boundary;
+use crate::emulator::register_pattern::{RegisterPattern, RegisterPatternConfig};
+use crate::error::Error;
+
+/// Parse the stage specification file. Like the register pattern file, this
+/// consists of TOML chunks, separated by lines of `---`. Each chunk describes
+/// one stage, and the stages are to be reached in the order given. For example:
+///
+/// ```toml
+/// kind = "memory"
+/// string = "/bin/sh\u0000"
+/// ---
+/// kind = "register"
+/// [registers]
+/// EBX = "&'/bin/sh\\0'"
+/// ---
+/// kind = "syscall"
+/// [registers]
+/// EAX = "0xb"
+/// ```
+pub fn parse_stage_file(path: &str) -> Result<Vec<Stage>, Error> {
+    let data = std::fs::read_to_string(path)?;
+    let res = parse_stages(&data);
+    log::info!("Parsed stages: {:#?}", res);
+    res
+}
+
+pub fn parse_stages(data: &str) -> Result<Vec<Stage>, Error> {
+    data.split("\n---")
+        .filter(|chunk| !chunk.trim().is_empty())
+        .map(|chunk| {
+            let stage_conf: StageConfig = toml::from_str(chunk)?;
+            stage_conf.into_stage()
+        })
+        .collect::<Result<Vec<Stage>, Error>>()
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum StageConfig {
+    Register {
+        registers: RegisterPatternConfig,
+    },
+    Memory {
+        #[serde(default)]
+        bytes: Vec<u8>,
+        string: Option<String>,
+    },
+    Syscall {
+        registers: Option<RegisterPatternConfig>,
+    },
+}
+
+impl StageConfig {
+    fn into_stage(self) -> Result<Stage, Error> {
+        match self {
+            StageConfig::Register { registers } => Ok(Stage::Register(registers.into())),
+            StageConfig::Memory { mut bytes, string } => {
+                if let Some(string) = string {
+                    bytes.extend_from_slice(string.as_bytes());
+                }
+                if bytes.is_empty() {
+                    Err(Error::Parsing(
+                        "A memory stage needs either `bytes` or a `string`".into(),
+                    ))
+                } else {
+                    Ok(Stage::Memory(bytes))
+                }
+            }
+            StageConfig::Syscall { registers } => Ok(Stage::Syscall(registers.map(|r| r.into()))),
+        }
+    }
+}
+
+/// A sub-goal, to be satisfied at some boundary in the course of execution.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Stage {
+    /// The registers match the pattern.
+    Register(RegisterPattern),
+    /// The bytes have been written somewhere in memory.
+    Memory(Vec<u8>),
+    /// A system call is reached, with the registers matching the pattern, if given.
+    Syscall(Option<RegisterPattern>),
+}
+
+/// The cost of reaching the right register state at a boundary that isn't a system call.
+const NOT_SYSCALL_PENALTY: f64 = 1.0;
+
+impl Stage {
+    /// How far the machine state at `boundary` is from satisfying the stage.
+    /// A distance of zero means that the stage is satisfied.
+    pub fn distance(&self, boundary: &Boundary) -> f64 {
+        match self {
+            Stage::Register(pattern) => pattern.distance_from_register_state_with_memory(
+                &boundary.registers,
+                boundary.memory.as_ref(),
+            ),
+            Stage::Memory(bytes) => {
+                let found = boundary
+                    .memory
+                    .as_ref()
+                    .and_then(|m| m.longest_prefix(bytes))
+                    .map(|(_addr, len)| len)
+                    .unwrap_or(0);
+                (bytes.len() - found) as f64
+            }
+            Stage::Syscall(pattern) => {
+                let register_distance = pattern
+                    .as_ref()
+                    .map(|p| {
+                        p.distance_from_register_state_with_memory(
+                            &boundary.registers,
+                            boundary.memory.as_ref(),
+                        )
+                    })
+                    .unwrap_or(0.0);
+                if boundary.syscall {
+                    register_distance
+                } else {
+                    register_distance + NOT_SYSCALL_PENALTY
+                }
+            }
+        }
+    }
+
+    pub fn register_pattern(&self) -> Option<&RegisterPattern> {
+        match self {
+            Stage::Register(pattern) => Some(pattern),
+            Stage::Syscall(pattern) => pattern.as_ref(),
+            Stage::Memory(_) => None,
+        }
+    }
+
+    pub fn needs_memory(&self) -> bool {
+        matches!(self, Stage::Memory(_))
+    }
+}
+
+/// How far one run got through the stages.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct StageProgress {
+    /// The index of the boundary at which each completed stage was satisfied.
+    pub reached_at: Vec<usize>,
+    /// The least distance, over the boundaries that followed the last
+    /// completed stage, from satisfying the next stage (or zero, if every
+    /// stage was completed).
+    pub error: f64,
+}
+
+impl StageProgress {
+    pub fn completed(&self) -> usize {
+        self.reached_at.len()
+    }
+}
+
+/// Walks through the boundaries in order, advancing through the stages as each
+/// one is satisfied. Several stages may be satisfied at the same boundary.
+pub fn progress_through_stages(stages: &[Stage], boundaries: &[Boundary]) -> StageProgress {
+    let mut reached_at = vec![];
+    let mut error = std::f64::MAX;
+    for (i, boundary) in boundaries.iter().enumerate() {
+        while reached_at.len() < stages.len() {
+            let d = stages[reached_at.len()].distance(boundary);
+            if d > 0.0 {
+                error = error.min(d);
+                break;
+            }
+            reached_at.push(i);
+            // a fresh stage, so forget the error accumulated on the last
+            error = std::f64::MAX;
+        }
+        if reached_at.len() == stages.len() {
+            break;
+        }
+    }
+    let error = if reached_at.len() == stages.len() {
+        0.0
+    } else if error == std::f64::MAX {
+        // There were no boundaries at which to measure the next stage.
+        NO_BOUNDARY_ERROR
+    } else {
+        error
+    };
+    StageProgress { reached_at, error }
+}
+
+/// The error assigned to a stage when there were no boundaries after the
+/// previous stage was completed.
+const NO_BOUNDARY_ERROR: f64 = 1000.0;
+
+#[cfg(test)]
+mod test {
+    use crate::emulator::profiler::{MemLogEntry, SparseData};
+    use crate::emulator::register_pattern::{RegisterState, RegisterValue};
+    use crate::hashmap;
+
+    use super::*;
+
+    fn boundary(rax: u64, written: &[u8]) -> Boundary {
+        let log = written
+            .iter()
+            .enumerate()
+            .map(|(i, b)| MemLogEntry {
+                program_counter: 0,
+                address: 0x1000 + i as u64,
+                num_bytes_written: 1,
+                value: *b as u64,
+            })
+            .collect::<Vec<MemLogEntry>>();
+        Boundary {
+            registers: RegisterState(hashmap! { "RAX".to_string() => vec![rax] }),
+            memory: Some(SparseData::from(log)),
+            syscall: false,
+        }
+    }
+
+    #[test]
+    fn test_progress_through_stages() {
+        let stages = vec![
+            Stage::Memory(b"sh".to_vec()),
+            Stage::Register(RegisterPattern(hashmap! {
+                "RAX".to_string() => RegisterValue::from(0x1000)
+            })),
+        ];
+
+        let progress = progress_through_stages(&stages, &[boundary(0, b"s")]);
+        assert_eq!(progress.completed(), 0);
+        assert!((progress.error - 1.0).abs() < std::f64::EPSILON);
+
+        let boundaries = vec![
+            boundary(0, b""),
+            boundary(0, b"sh"),
+            boundary(0x1001, b"sh"),
+        ];
+        let progress = progress_through_stages(&stages, &boundaries);
+        assert_eq!(progress.reached_at, vec![1]);
+        assert!(progress.error > 0.0);
+
+        let boundaries = vec![boundary(0x1000, b""), boundary(0, b"sh")];
+        let progress = progress_through_stages(&stages, &boundaries);
+        assert_eq!(
+            progress.reached_at,
+            vec![1],
+            "stages must be reached in order"
+        );
+
+        let boundaries = vec![boundary(0, b"sh"), boundary(0x1000, b"sh")];
+        let progress = progress_through_stages(&stages, &boundaries);
+        assert_eq!(progress.reached_at, vec![0, 1]);
+        assert_eq!(progress.error, 0.0);
+
+        // several stages may be satisfied at once
+        let boundaries = vec![boundary(0, b""), boundary(0x1000, b"sh")];
+        let progress = progress_through_stages(&stages, &boundaries);
+        assert_eq!(progress.reached_at, vec![1, 1]);
+    }
+}
@@ -0,0 +1,360 @@
+//! Self-adaptive mutation.
+//!
+//! When `self_adaptation` is configured, each creature carries its own
+//! mutation rate and Levy flight exponent, which its offspring inherit, as
+//! the geometric mean of their parents', and perturb by a log-normal factor,
+//! as in the evolution strategies.
+//!
+//! Mutation operators are chosen in proportion to the credit they have
+//! earned on each island: an operator earns credit when an offspring it
+//! helped to produce is fitter than its fittest parent. Older successes and
+//! trials decay, so that the weights follow the changing needs of the run.
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::hash::{Hash, Hasher};
+use std::sync::Mutex;
+
+use rand::distributions::{Distribution, WeightedIndex};
+use rand::Rng;
+use rand_distr::StandardNormal;
+use serde::{Deserialize, Serialize};
+
+use crate::configure::Config;
+use crate::evolution::Mutation;
+use crate::observer::{log_record, LogRecord};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct MutationParams {
+    pub rate: f64,
+    pub exponent: f64,
+}
+
+impl Hash for MutationParams {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.rate.to_bits().hash(state);
+        self.exponent.to_bits().hash(state);
+    }
+}
+
+impl MutationParams {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            rate: config.mutation_rate,
+            exponent: config.mutation_exponent,
+        }
+    }
+
+    /// The parameters of an offspring of parents with the given parameters,
+    /// where `None` stands for the global parameters. Returns `None` if
+    /// self-adaptation is disabled.
+    pub fn inherit<R: Rng>(parents: &[Option<Self>], config: &Config, rng: &mut R) -> Option<Self> {
+        let adaptation = config.self_adaptation.as_ref()?;
+        let n = parents.len().max(1) as f64;
+        let (log_rate, log_exponent) = parents
+            .iter()
+            .map(|p| p.unwrap_or_else(|| Self::from_config(config)))
+            .fold((0.0, 0.0), |(r, e), p| {
+                (r + p.rate.ln(), e + p.exponent.ln())
+            });
+        let perturb = |rng: &mut R| {
+            let z: f64 = StandardNormal.sample(rng);
+            (adaptation.learning_rate * z).exp()
+        };
+        let rate = (log_rate / n).exp() * perturb(rng);
+        let exponent = (log_exponent / n).exp() * perturb(rng);
+        Some(Self {
+            rate: rate.max(adaptation.min_rate).min(adaptation.max_rate),
+            exponent: exponent
+                .max(adaptation.min_exponent)
+                .min(adaptation.max_exponent),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct Credit {
+    successes: f64,
+    trials: f64,
+}
+
+impl Credit {
+    /// The estimated chance of success, floored so that no operator is ever
+    /// abandoned.
+    fn weight(&self, floor: f64) -> f64 {
+        floor + (1.0 - floor) * (self.successes + 1.0) / (self.trials + 2.0)
+    }
+}
+
+type CreditTable = BTreeMap<(usize, &'static str), BTreeMap<String, Credit>>;
+
+/// The credit of each operator, by island and mutation type.
+static CREDITS: Mutex<CreditTable> = Mutex::new(BTreeMap::new());
+
+fn family<M>() -> &'static str {
+    std::any::type_name::<M>()
+}
+
+fn operator_name<M: Debug>(operator: &M) -> String {
+    format!("{:?}", operator)
+}
+
+/// Chooses one of the mutation type's operators in proportion to the
+/// credit each has earned on this island. Returns `None` if operator credit
+/// is disabled, in which case the caller should choose as it otherwise
+/// would.
+pub fn choose_operator<M: Mutation + Debug + Clone, R: Rng>(
+    config: &Config,
+    rng: &mut R,
+) -> Option<M> {
+    let adaptation = config.self_adaptation.as_ref()?;
+    let operators = M::operators();
+    if operators.is_empty() {
+        return None;
+    }
+    let weights = {
+        let credits = CREDITS.lock().expect("poisoned operator credits");
+        let table = credits.get(&(config.island_id, family::<M>()));
+        operators
+            .iter()
+            .map(|op| {
+                table
+                    .and_then(|t| t.get(&operator_name(op)))
+                    .cloned()
+                    .unwrap_or_default()
+                    .weight(adaptation.min_operator_weight)
+            })
+            .collect::<Vec<f64>>()
+    };
+    let dist = WeightedIndex::new(&weights).ok()?;
+    Some(operators[dist.sample(rng)].clone())
+}
+
+/// Credits each operator that contributed to an offspring, which did or did
+/// not improve on its parents.
+pub fn credit<M: Debug>(config: &Config, mutations: &[Option<M>], improved: bool) {
+    let adaptation = match config.self_adaptation.as_ref() {
+        Some(a) => a,
+        None => return,
+    };
+    let mut names = mutations
+        .iter()
+        .flatten()
+        .map(operator_name)
+        .collect::<Vec<String>>();
+    if names.is_empty() {
+        return;
+    }
+    names.sort();
+    names.dedup();
+    let mut credits = CREDITS.lock().expect("poisoned operator credits");
+    let table = credits
+        .entry((config.island_id, family::<M>()))
+        .or_insert_with(BTreeMap::new);
+    for credit in table.values_mut() {
+        credit.successes *= adaptation.credit_decay;
+        credit.trials *= adaptation.credit_decay;
+    }
+    for name in names {
+        let credit = table.entry(name).or_default();
+        credit.trials += 1.0;
+        if improved {
+            credit.successes += 1.0;
+        }
+    }
+}
+
+#[derive(Debug)]
+struct OperatorRecord {
+    epoch: usize,
+    family: &'static str,
+    operator: String,
+    weight: f64,
+    successes: f64,
+    trials: f64,
+}
+
+impl LogRecord for OperatorRecord {
+    fn header(&self) -> String {
+        "epoch,family,operator,weight,successes,trials".to_string()
+    }
+
+    fn row(&self) -> String {
+        format!(
+            "{},{},{},{},{},{}",
+            self.epoch, self.family, self.operator, self.weight, self.successes, self.trials
+        )
+    }
+}
+
+/// Logs the credit and weight of every operator used on the island to
+/// `operators_statistics.csv`.
+pub fn log_operator_weights(config: &Config) {
+    let adaptation = match config.self_adaptation.as_ref() {
+        Some(a) => a,
+        None => return,
+    };
+    let records = {
+        let credits = CREDITS.lock().expect("poisoned operator credits");
+        credits
+            .range((config.island_id, "")..)
+            .take_while(|((island, _), _)| *island == config.island_id)
+            .flat_map(|(&(_, family), table)| {
+                table.iter().map(move |(operator, credit)| OperatorRecord {
+                    epoch: crate::get_epoch_counter(),
+                    family,
+                    operator: operator.clone(),
+                    weight: credit.weight(adaptation.min_operator_weight),
+                    successes: credit.successes,
+                    trials: credit.trials,
+                })
+            })
+            .collect::<Vec<OperatorRecord>>()
+    };
+    for record in records {
+        log_record(record, "operators", config);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use crate::configure::SelfAdaptationConfig;
+    use crate::evolution::LinearChromosome;
+    use crate::roper::bare::WordMutation;
+
+    use super::*;
+
+    /// Each test uses its own island, since the credit table is shared.
+    fn config(island_id: usize) -> Config {
+        let mut config = Config::default();
+        config.island_id = island_id;
+        config.mutation_rate = 0.1;
+        config.mutation_exponent = 2.0;
+        config.self_adaptation = Some(SelfAdaptationConfig {
+            learning_rate: 0.0,
+            min_rate: 0.01,
+            max_rate: 0.5,
+            min_exponent: 1.0,
+            max_exponent: 4.0,
+            credit_decay: 1.0,
+            min_operator_weight: 0.01,
+        });
+        config
+    }
+
+    fn credit_of(island_id: usize, operator: WordMutation) -> Option<(f64, f64)> {
+        let credits = CREDITS.lock().unwrap();
+        credits
+            .get(&(island_id, family::<WordMutation>()))
+            .and_then(|t| t.get(&operator_name(&operator)))
+            .map(|c| (c.successes, c.trials))
+    }
+
+    #[test]
+    fn test_inherit() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut config = config(9000);
+        let params = |rate, exponent| Some(MutationParams { rate, exponent });
+
+        // with no perturbation, the geometric mean of the parents'
+        let child =
+            MutationParams::inherit(&[params(0.04, 1.0), params(0.16, 4.0)], &config, &mut rng)
+                .expect("self-adaptation is enabled");
+        assert!((child.rate - 0.08).abs() < 1e-9);
+        assert!((child.exponent - 2.0).abs() < 1e-9);
+
+        // parents without parameters of their own stand for the global ones
+        let child = MutationParams::inherit(&[None], &config, &mut rng).unwrap();
+        assert!((child.rate - config.mutation_rate).abs() < 1e-9);
+        assert!((child.exponent - config.mutation_exponent).abs() < 1e-9);
+
+        // clamped to the configured bounds
+        let child = MutationParams::inherit(&[params(0.9, 0.1)], &config, &mut rng).unwrap();
+        assert_eq!(
+            child,
+            MutationParams {
+                rate: 0.5,
+                exponent: 1.0
+            }
+        );
+
+        config.self_adaptation = None;
+        assert_eq!(
+            MutationParams::inherit(&[params(0.04, 1.0)], &config, &mut rng),
+            None
+        );
+    }
+
+    #[test]
+    fn test_choose_operator() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let config = config(9001);
+        for _ in 0..50 {
+            for op in WordMutation::operators() {
+                let improved = matches!(op, WordMutation::BitFlip);
+                credit(&config, &[Some(op)], improved);
+            }
+        }
+        let flips = (0..1000)
+            .filter_map(|_| choose_operator::<WordMutation, _>(&config, &mut rng))
+            .filter(|op| matches!(op, WordMutation::BitFlip))
+            .count();
+        assert!(flips > 800, "BitFlip chosen only {} times", flips);
+
+        let mut disabled = config.clone();
+        disabled.self_adaptation = None;
+        assert!(choose_operator::<WordMutation, _>(&disabled, &mut rng).is_none());
+    }
+
+    #[test]
+    fn test_credit_operators() {
+        let config = config(9002);
+        let mut chromosome: LinearChromosome<u64, WordMutation> = LinearChromosome {
+            chromosome: vec![1, 2, 3],
+            mutations: vec![
+                Some(WordMutation::BitFlip),
+                None,
+                Some(WordMutation::BitFlip),
+            ],
+            parentage: vec![0; 3],
+            parent_names: vec![],
+            name: "test".to_string(),
+            generation: 0,
+            gene_ages: vec![0; 3],
+            mutation_params: None,
+            parent_fitness: Some(1.0),
+        };
+        chromosome.credit_operators(Some(0.5), &config);
+        // each operator is credited once per offspring
+        assert_eq!(credit_of(9002, WordMutation::BitFlip), Some((1.0, 1.0)));
+        assert_eq!(credit_of(9002, WordMutation::AddressAdd), None);
+        assert_eq!(chromosome.parent_fitness, None);
+
+        // and each offspring only once
+        chromosome.credit_operators(Some(0.5), &config);
+        assert_eq!(credit_of(9002, WordMutation::BitFlip), Some((1.0, 1.0)));
+
+        chromosome.parent_fitness = Some(0.1);
+        chromosome.credit_operators(Some(0.5), &config);
+        assert_eq!(credit_of(9002, WordMutation::BitFlip), Some((1.0, 2.0)));
+    }
+
+    #[test]
+    fn test_credit_weight() {
+        let fresh = Credit::default();
+        assert!((fresh.weight(0.0) - 0.5).abs() < 1e-9);
+        let good = Credit {
+            successes: 8.0,
+            trials: 8.0,
+        };
+        let bad = Credit {
+            successes: 0.0,
+            trials: 8.0,
+        };
+        assert!(good.weight(0.1) > fresh.weight(0.1));
+        assert!(bad.weight(0.1) < fresh.weight(0.1));
+        assert!(bad.weight(0.1) > 0.1);
+    }
+}
@@ -0,0 +1,427 @@
+//! The age-layered population structure of Gregory Hornby, "ALPS: The
+//! Age-Layered Population Structure for Reducing the Problem of Premature
+//! Convergence", GECCO 2006.
+//!
+//! The island's population is divided into layers, each with a limit on the
+//! age of the creatures it holds, where a creature's age is its
+//! `generation`. Tournaments are held within each layer, on its own
+//! `TrivialGeography`. A survivor that has outgrown its layer moves up to
+//! the next, where it takes the place of the weakest of a sample of the
+//! residents, if it is fitter than them, and an extra offspring is bred to
+//! take its place below. Every `alps.injection_interval` tournaments, the
+//! members of the bottom layer are offered to the layer above in the same
+//! way, and the bottom layer is replaced with fresh random creatures, so
+//! that new genetic material keeps entering the run without having to
+//! compete at once with the old.
+use std::iter;
+use std::sync::Arc;
+
+use rand::Rng;
+
+use crate::configure::Config;
+use crate::evolution::engine::Engine;
+use crate::evolution::population::migration::{self, priority_fitness};
+use crate::evolution::population::pier::Pier;
+use crate::evolution::population::trivial_geography::TrivialGeography;
+use crate::evolution::{Genome, Phenome};
+use crate::fitness::sort_by_dominance;
+use crate::observer::{log_record, LogRecord, Observer};
+use crate::ontogenesis::Develop;
+use crate::util::random::hash_seed_rng;
+
+pub struct Alps<E: Develop<P>, P: Phenome + 'static> {
+    pub layers: Vec<TrivialGeography<P>>,
+    pub config: Config,
+    pub iteration: usize,
+    pub observer: Observer<P>,
+    pub evaluator: E,
+    pub pier: Arc<Pier<P>>,
+}
+
+#[derive(Debug)]
+struct LayerRecord {
+    epoch: usize,
+    layer: usize,
+    size: usize,
+    mean_age: f64,
+    max_age: usize,
+    best: Option<f64>,
+}
+
+impl LogRecord for LayerRecord {
+    fn header(&self) -> String {
+        "epoch,layer,size,mean_age,max_age,best".to_string()
+    }
+
+    fn row(&self) -> String {
+        format!(
+            "{},{},{},{},{},{}",
+            self.epoch,
+            self.layer,
+            self.size,
+            self.mean_age,
+            self.max_age,
+            self.best.map(|f| f.to_string()).unwrap_or_default()
+        )
+    }
+}
+
+impl<E: Develop<P>, P: Phenome + Genome + 'static> Alps<E, P> {
+    pub fn new(config: &Config, observer: Observer<P>, evaluator: E, pier: Arc<Pier<P>>) -> Self {
+        let config = config.clone();
+        let layers = (0..config.alps.num_layers.max(1))
+            .map(|layer| Self::random_layer(&config, layer, 0))
+            .collect();
+
+        Self {
+            layers,
+            config,
+            iteration: 0,
+            observer,
+            evaluator,
+            pier,
+        }
+    }
+
+    fn layer_size(config: &Config) -> usize {
+        (config.pop_size / config.alps.num_layers.max(1)).max(config.tournament.tournament_size + 1)
+    }
+
+    fn random_layer(config: &Config, layer: usize, iteration: usize) -> TrivialGeography<P> {
+        let mut population: TrivialGeography<P> = (0..Self::layer_size(config))
+            .map(|i| P::random(config, (layer, iteration, i)))
+            .collect();
+        population.set_radius(config.tournament.geographic_radius);
+        population
+    }
+
+    /// Holds a tournament in every layer. The combatants of every layer are
+    /// developed together.
+    pub fn evolve(self) -> Self {
+        let Self {
+            mut layers,
+            config,
+            mut iteration,
+            observer,
+            mut evaluator,
+            pier,
+        } = self;
+
+        let mut rng = hash_seed_rng(&(iteration as u64 ^ config.random_seed));
+
+        let brackets: Vec<Vec<P>> = layers
+            .iter_mut()
+            .map(|layer| {
+                if layer.len() > config.tournament.tournament_size {
+                    layer.choose_combatants(config.tournament.tournament_size, &mut rng)
+                } else {
+                    vec![]
+                }
+            })
+            .collect();
+        let bracket_sizes = brackets.iter().map(Vec::len).collect::<Vec<usize>>();
+
+        let mut developed = evaluator
+            .development_pipeline(brackets.into_iter().flatten())
+            .into_iter();
+
+        for (layer, size) in bracket_sizes.into_iter().enumerate() {
+            if size == 0 {
+                continue;
+            }
+            let mut combatants = developed
+                .by_ref()
+                .take(size)
+                .map(|p| evaluator.apply_fitness_function(p))
+                .map(|e| {
+                    observer.observe(e.clone());
+                    e
+                })
+                .collect::<Vec<P>>();
+
+            sort_by_dominance(&mut combatants, |a, b| {
+                a.fitness().partial_cmp(&b.fitness())
+            });
+
+            Self::conclude_tournament(&mut layers, layer, combatants, &config, &pier, &mut rng);
+
+            iteration += 1;
+            if config.island_id == 0
+                && iteration % (config.pop_size / config.tournament.num_offspring).max(1) == 0
+            {
+                crate::increment_epoch_counter();
+            }
+            if config.alps.injection_interval > 0 && iteration % config.alps.injection_interval == 0
+            {
+                Self::log_layers(&layers, &config);
+                Self::inject(&mut layers, &config, iteration, &mut rng);
+            }
+        }
+
+        Self {
+            layers,
+            config,
+            iteration,
+            observer,
+            evaluator,
+            pier,
+        }
+    }
+
+    /// Replaces the bottom layer with fresh random creatures, once its
+    /// members have been offered to the layer above.
+    fn inject<R: Rng>(
+        layers: &mut [TrivialGeography<P>],
+        config: &Config,
+        iteration: usize,
+        rng: &mut R,
+    ) {
+        log::debug!(
+            "Island {}: injecting random creatures into the bottom layer",
+            config.island_id
+        );
+        let retired = std::mem::replace(&mut layers[0], Self::random_layer(config, 0, iteration));
+        if layers.len() > 1 {
+            for creature in retired.iter().cloned() {
+                Self::promote(layers, 1, creature, config, rng);
+            }
+        }
+    }
+
+    /// Culls the losers of a tournament held in the given layer, sorted
+    /// from best to worst, and returns the survivors, along with their
+    /// offspring, to the population. Survivors too old for the layer move
+    /// up to the next, and an extra offspring is bred for each, so that the
+    /// layer keeps its size.
+    fn conclude_tournament<R: Rng>(
+        layers: &mut [TrivialGeography<P>],
+        layer: usize,
+        mut combatants: Vec<P>,
+        config: &Config,
+        pier: &Pier<P>,
+        rng: &mut R,
+    ) {
+        for _ in 0..config.tournament.num_offspring {
+            let _ = combatants.pop();
+        }
+
+        let mut survivors = combatants;
+        migration::migrate(&mut survivors, pier, config, rng);
+
+        let age_limit = config.alps.age_limit(layer);
+        let too_old = |p: &P| age_limit.map_or(false, |limit| p.generation() > limit);
+        let num_offspring =
+            config.tournament.num_offspring + survivors.iter().filter(|p| too_old(p)).count();
+
+        let parents = survivors
+            .iter_mut()
+            .take(config.tournament.num_parents)
+            .map(|p| {
+                p.incr_num_offspring(num_offspring);
+                &*p
+            })
+            .collect::<Vec<&P>>();
+
+        let offspring: Vec<P> = iter::repeat(())
+            .take(num_offspring)
+            .map(|()| Genome::mate(&parents, config))
+            .collect::<Vec<_>>();
+
+        for survivor in survivors.into_iter() {
+            if too_old(&survivor) {
+                Self::promote(layers, layer + 1, survivor, config, rng);
+            } else {
+                layers[layer].insert(survivor).unwrap()
+            }
+        }
+        for child in offspring.into_iter() {
+            layers[layer].insert(child).unwrap()
+        }
+    }
+
+    /// Moves the creature into the given layer, in place of the weakest of a
+    /// sample of its residents, if it is fitter than that resident.
+    /// Otherwise, the creature dies.
+    fn promote<R: Rng>(
+        layers: &mut [TrivialGeography<P>],
+        layer: usize,
+        creature: P,
+        config: &Config,
+        rng: &mut R,
+    ) {
+        let destination = &mut layers[layer];
+        if destination.len() <= config.tournament.tournament_size {
+            destination.insert(creature).unwrap();
+            return;
+        }
+        let mut sample = destination.choose_combatants(config.tournament.tournament_size, rng);
+        let weakest = sample
+            .iter()
+            .enumerate()
+            .map(|(i, p)| (i, priority_fitness(p, config)))
+            .fold(
+                (0, f64::NEG_INFINITY),
+                |(wi, w), (i, f)| {
+                    if f > w {
+                        (i, f)
+                    } else {
+                        (wi, w)
+                    }
+                },
+            );
+        if priority_fitness(&creature, config) < weakest.1 {
+            log::debug!(
+                "{} moves up to layer {}, replacing {}",
+                creature.name(),
+                layer,
+                sample[weakest.0].name()
+            );
+            sample[weakest.0] = creature;
+        }
+        for p in sample.into_iter() {
+            destination.insert(p).unwrap()
+        }
+    }
+
+    fn log_layers(layers: &[TrivialGeography<P>], config: &Config) {
+        for (layer, population) in layers.iter().enumerate() {
+            let ages = population
+                .iter()
+                .map(Genome::generation)
+                .collect::<Vec<usize>>();
+            let record = LayerRecord {
+                epoch: crate::get_epoch_counter(),
+                layer,
+                size: ages.len(),
+                mean_age: ages.iter().sum::<usize>() as f64 / ages.len().max(1) as f64,
+                max_age: ages.iter().copied().max().unwrap_or(0),
+                best: population
+                    .iter()
+                    .filter_map(|p| p.scalar_fitness(config.fitness.priority()))
+                    .fold(None, |best: Option<f64>, f| {
+                        Some(best.map_or(f, |b| b.min(f)))
+                    }),
+            };
+            log_record(record, "alps", config);
+        }
+    }
+}
+
+impl<E: Develop<P>, P: Phenome + Genome + 'static> Engine<E, P> for Alps<E, P> {
+    fn init(config: &Config, observer: Observer<P>, evaluator: E, pier: Arc<Pier<P>>) -> Self {
+        Self::new(config, observer, evaluator, pier)
+    }
+
+    fn evolve(self) -> Self {
+        Alps::evolve(self)
+    }
+
+    fn snapshot(&self) -> Vec<P> {
+        self.layers
+            .iter()
+            .flat_map(TrivialGeography::iter)
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::configure::{AgingScheme, AlpsConfig};
+    use crate::examples::hello_world::Genotype;
+
+    use super::*;
+
+    /// Develops nothing, so that the layers can be driven by hand.
+    struct Inert;
+
+    impl Develop<Genotype> for Inert {
+        fn develop(&self, ob: Genotype) -> Genotype {
+            ob
+        }
+
+        fn apply_fitness_function(&mut self, ob: Genotype) -> Genotype {
+            ob
+        }
+
+        fn development_pipeline<I: 'static + Iterator<Item = Genotype> + Send>(
+            &self,
+            inbound: I,
+        ) -> Vec<Genotype> {
+            inbound.collect()
+        }
+    }
+
+    type TestAlps = Alps<Inert, Genotype>;
+
+    /// The age limits of six layers, in multiples of the age gap.
+    fn multiples(aging_scheme: AgingScheme) -> Vec<Option<usize>> {
+        let config = AlpsConfig {
+            num_layers: 6,
+            age_gap: 10,
+            aging_scheme,
+            ..Default::default()
+        };
+        (0..6)
+            .map(|layer| config.age_limit(layer).map(|l| l / 10))
+            .collect()
+    }
+
+    #[test]
+    fn test_age_limit() {
+        assert_eq!(
+            multiples(AgingScheme::Linear),
+            vec![Some(1), Some(2), Some(3), Some(4), Some(5), None]
+        );
+        assert_eq!(
+            multiples(AgingScheme::Polynomial),
+            vec![Some(1), Some(2), Some(4), Some(9), Some(16), None]
+        );
+        assert_eq!(
+            multiples(AgingScheme::Fibonacci),
+            vec![Some(1), Some(2), Some(3), Some(5), Some(8), None]
+        );
+        assert_eq!(
+            multiples(AgingScheme::Exponential),
+            vec![Some(1), Some(2), Some(4), Some(8), Some(16), None]
+        );
+    }
+
+    #[test]
+    fn test_layer_sizes_are_preserved() {
+        let mut config = Config::default();
+        config.pop_size = 30;
+        config.max_init_len = 10;
+        config.tournament.tournament_size = 4;
+        config.tournament.num_offspring = 2;
+        config.tournament.num_parents = 2;
+        // everything but the randomly generated creatures outgrows the
+        // bottom layer
+        config.alps.num_layers = 3;
+        config.alps.age_gap = 0;
+        let pier = Pier::archipelago(&[config.clone()], &[vec![]]).remove(0);
+        let mut rng = hash_seed_rng(&0);
+
+        let mut layers = (0..3)
+            .map(|layer| TestAlps::random_layer(&config, layer, 0))
+            .collect::<Vec<_>>();
+        let size = TestAlps::layer_size(&config);
+        for i in 0..50 {
+            let mut combatants = layers[0].choose_combatants(4, &mut rng);
+            for (j, c) in combatants.iter_mut().enumerate() {
+                c.set_fitness(vec![j as f64]);
+            }
+            TestAlps::conclude_tournament(&mut layers, 0, combatants, &config, &pier, &mut rng);
+            assert!(
+                layers.iter().all(|l| l.len() == size),
+                "after tournament {}",
+                i
+            );
+        }
+        assert!(layers[1].iter().any(|p| p.generation() > 0));
+
+        TestAlps::inject(&mut layers, &config, 50, &mut rng);
+        assert!(layers.iter().all(|l| l.len() == size));
+        assert!(layers[0].iter().all(|p| p.generation() == 0));
+    }
+}
@@ -0,0 +1,82 @@
+use std::sync::Arc;
+use std::thread::spawn;
+
+use rand::Rng;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::configure::Config;
+use crate::evolution::population::migration;
+use crate::evolution::population::pier::Pier;
+use crate::evolution::{Genome, Phenome};
+use crate::observer::Observer;
+use crate::ontogenesis::Develop;
+use crate::util::random::hash_seed_rng;
+
+/// A selection scheme, driving the evolution of a single island's
+/// population. Every engine can be run on any number of islands by
+/// `launch`, which connects them through a shared `Pier`.
+pub trait Engine<E: Develop<P>, P: Phenome + Genome + 'static>: Sized {
+    fn init(config: &Config, observer: Observer<P>, evaluator: E, pier: Arc<Pier<P>>) -> Self;
+
+    /// Runs a single step of the selection scheme -- a tournament, a
+    /// generation, or a proposed move, depending on the engine.
+    fn evolve(self) -> Self;
+
+    /// A copy of the creatures currently held by the engine.
+    fn snapshot(&self) -> Vec<P>;
+}
+
+/// Runs the engine `G` on `config.num_islands` islands, each in its own
+/// thread, until `keep_going()` returns false. Every island receives its
+/// own copy of the config, with its island id, data directory and random
+/// seed set, from which `prepare` builds the island's observer and
+/// evaluator. The islands' piers are connected as `config.migration`
+/// dictates. Returns the final population of each island.
+///
+/// When this process is one island of a cluster, only that island is run
+/// here, and its neighbours are reached through the coordinator.
+pub fn launch<G, E, P, F>(config: &Config, prepare: F) -> Vec<Vec<P>>
+where
+    G: Engine<E, P>,
+    E: Develop<P> + Send + 'static,
+    P: Phenome + Genome + Serialize + DeserializeOwned + Send + 'static,
+    F: Fn(&Config) -> (Observer<P>, E),
+{
+    let num_islands = config.num_islands.max(1);
+    let mut rng = hash_seed_rng(&config.random_seed);
+    let configs = (0..num_islands)
+        .map(|i| {
+            let mut config = config.clone();
+            config.island_id = i;
+            config.random_seed = rng.gen::<u64>();
+            config
+        })
+        .filter(|config| {
+            crate::cluster::link().map_or(true, |link| link.island() == config.island_id)
+        })
+        .map(|mut config| {
+            config.set_data_directory();
+            config
+        })
+        .collect::<Vec<Config>>();
+    let neighbours = migration::neighbours(&config.migration, num_islands, config.random_seed);
+    let piers = Pier::archipelago(&configs, &neighbours);
+
+    let mut handles = Vec::new();
+    for (config, pier) in configs.into_iter().zip(piers.into_iter()) {
+        let (observer, evaluator) = prepare(&config);
+        let h = spawn(move || {
+            let mut world = G::init(&config, observer, evaluator, pier);
+            while crate::keep_going() {
+                world = world.evolve();
+            }
+            world.snapshot()
+        });
+        handles.push(h);
+    }
+    handles
+        .into_iter()
+        .map(|h| h.join().expect("Failed to join thread"))
+        .collect()
+}
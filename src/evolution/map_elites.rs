@@ -0,0 +1,202 @@
+use std::iter;
+use std::sync::Arc;
+
+use hashbrown::HashMap;
+use rand::Rng;
+
+use crate::configure::Config;
+use crate::evolution::{Genome, Phenome};
+use crate::increment_epoch_counter;
+use crate::observer::{dump_heatmap, Observer};
+use crate::ontogenesis::Develop;
+use crate::util::dump::dump;
+use crate::util::random::hash_seed_rng;
+
+/// Measures a creature along each of the dimensions listed in
+/// `config.map_elites.dimensions`, in the same order.
+pub type DescriptorFn<P> = Box<dyn Fn(&P, &Config) -> Vec<f64> + Sync + Send + 'static>;
+
+/// A MAP-Elites engine. The grid is divided into cells by the behavioural
+/// dimensions given in the config, and each cell holds the best creature
+/// (by the `priority` expression) yet seen to land in it. New creatures are
+/// bred from parents drawn uniformly from the occupied cells.
+pub struct MapElites<E: Develop<P>, P: Phenome + Genome + 'static> {
+    pub grid: HashMap<Vec<usize>, P>,
+    pub config: Arc<Config>,
+    pub observer: Observer<P>,
+    pub evaluator: E,
+    pub iteration: usize,
+    pub descriptor: DescriptorFn<P>,
+}
+
+/// Places the creature in the given cell of the grid, if the cell is empty or
+/// its occupant is less fit, by `fitness`, where lower is better. A creature
+/// without a fitness is never placed. Returns true if the creature was placed.
+fn place<P, F: Fn(&P) -> Option<f64>>(
+    grid: &mut HashMap<Vec<usize>, P>,
+    cell: Vec<usize>,
+    creature: P,
+    fitness: F,
+) -> bool {
+    let challenger = match fitness(&creature) {
+        Some(f) => f,
+        None => return false,
+    };
+    let replace = match grid.get(&cell).and_then(&fitness) {
+        Some(incumbent) => challenger < incumbent,
+        None => true,
+    };
+    if replace {
+        grid.insert(cell, creature);
+    }
+    replace
+}
+
+impl<E: Develop<P>, P: Phenome + Genome + 'static> MapElites<E, P> {
+    pub fn new(
+        config: &Config,
+        observer: Observer<P>,
+        evaluator: E,
+        descriptor: DescriptorFn<P>,
+    ) -> Self {
+        assert!(
+            !config.map_elites.dimensions.is_empty(),
+            "MAP-Elites needs at least one dimension"
+        );
+        Self {
+            grid: HashMap::new(),
+            config: Arc::new(config.clone()),
+            observer,
+            evaluator,
+            iteration: 0,
+            descriptor,
+        }
+    }
+
+    fn cell(&self, creature: &P) -> Vec<usize> {
+        (self.descriptor)(creature, &self.config)
+            .into_iter()
+            .zip(self.config.map_elites.dimensions.iter())
+            .map(|(value, dim)| dim.bin(value))
+            .collect()
+    }
+
+    /// Places the creature in its cell, if the cell is empty or its current
+    /// occupant is less fit. Returns true if the creature was placed.
+    fn insert(&mut self, creature: P) -> bool {
+        let priority = self.config.fitness.priority();
+        let cell = self.cell(&creature);
+        place(&mut self.grid, cell, creature, |p| {
+            p.scalar_fitness(priority)
+        })
+    }
+
+    /// The priority fitness of the elite in each occupied cell.
+    pub fn heatmap(&self) -> Vec<(Vec<usize>, f64)> {
+        let priority = self.config.fitness.priority();
+        let mut cells = self
+            .grid
+            .iter()
+            .filter_map(|(cell, elite)| elite.scalar_fitness(priority).map(|f| (cell.clone(), f)))
+            .collect::<Vec<_>>();
+        cells.sort_by(|a, b| a.0.cmp(&b.0));
+        cells
+    }
+
+    pub fn dump_archive(&self) {
+        let path = format!(
+            "{}/map_elites_archive.json.gz",
+            self.config.data_directory()
+        );
+        log::info!(
+            "Dumping MAP-Elites archive of {} elites to {}",
+            self.grid.len(),
+            path
+        );
+        let elites = self.grid.iter().collect::<Vec<(&Vec<usize>, &P)>>();
+        if let Err(e) = dump(&elites, &path) {
+            log::error!("Failed to dump MAP-Elites archive: {:?}", e);
+        }
+    }
+
+    pub fn evolve(mut self) -> Self {
+        let mut rng = hash_seed_rng(&(self.config.random_seed, self.iteration));
+
+        let batch: Vec<P> = if self.grid.is_empty() {
+            (0..self.config.pop_size)
+                .map(|i| P::random(&self.config, (self.iteration, i)))
+                .collect()
+        } else {
+            let batch_size = self
+                .config
+                .map_elites
+                .batch_size
+                .unwrap_or(self.config.pop_size);
+            let elites = self.grid.values().collect::<Vec<&P>>();
+            (0..batch_size)
+                .map(|_| {
+                    let parents = iter::repeat(())
+                        .take(self.config.tournament.num_parents.max(1))
+                        .map(|()| elites[rng.gen_range(0, elites.len())])
+                        .collect::<Vec<&P>>();
+                    Genome::mate(&parents, &self.config)
+                })
+                .collect()
+        };
+
+        let evaluated = self
+            .evaluator
+            .development_pipeline(batch.into_iter())
+            .into_iter()
+            .map(|p| self.evaluator.apply_fitness_function(p))
+            .collect::<Vec<P>>();
+
+        let mut placed = 0;
+        for creature in evaluated.into_iter() {
+            self.observer.observe(creature.clone());
+            if self.insert(creature) {
+                placed += 1;
+            }
+        }
+        log::info!(
+            "Island {}, MAP-Elites iteration {}: {} new elites, {} cells occupied",
+            self.config.island_id,
+            self.iteration,
+            placed,
+            self.grid.len()
+        );
+        dump_heatmap(&self.config, &self.heatmap());
+
+        // The epoch is global, so only the first island advances it.
+        if self.config.island_id == 0 {
+            increment_epoch_counter();
+        }
+        self.iteration += 1;
+        self
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_place() {
+        let fitness = |p: &(&str, Option<f64>)| p.1;
+        let mut grid = HashMap::new();
+        assert!(place(&mut grid, vec![0, 1], ("first", Some(2.0)), fitness));
+        // a worse or equally fit challenger leaves the elite in place
+        assert!(!place(&mut grid, vec![0, 1], ("worse", Some(3.0)), fitness));
+        assert!(!place(&mut grid, vec![0, 1], ("equal", Some(2.0)), fitness));
+        assert_eq!(grid[&vec![0, 1]].0, "first");
+        // a fitter one replaces it
+        assert!(place(&mut grid, vec![0, 1], ("better", Some(1.0)), fitness));
+        assert_eq!(grid[&vec![0, 1]].0, "better");
+        // unscored creatures are never placed, even in an empty cell
+        assert!(!place(&mut grid, vec![1, 1], ("unscored", None), fitness));
+        // and an unscored incumbent is always replaced
+        grid.insert(vec![2, 2], ("unscored", None));
+        assert!(place(&mut grid, vec![2, 2], ("scored", Some(9.0)), fitness));
+        assert_eq!(grid.len(), 2);
+    }
+}
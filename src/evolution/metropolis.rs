@@ -1,16 +1,35 @@
-use std::sync::atomic::Ordering;
+//! Simulated annealing and parallel tempering.
+//!
+//! Each island runs one or more Markov chains, or replicas, each holding a
+//! single specimen. At every step, each replica proposes an offspring of its
+//! specimen, mated with itself as `Genome::mate` would, and accepts it with the Metropolis-Hastings probability
+//! `min(1, exp(-(E' - E) / T))`, where the energy `E` is the specimen's
+//! scalar fitness and `T` the replica's temperature, which falls as the
+//! `metropolis.cooling` schedule dictates. With more than one replica, the
+//! replicas start at a ladder of temperatures, and every
+//! `metropolis.swap_interval` steps neighbouring replicas propose to swap
+//! their specimens, accepting with probability
+//! `min(1, exp((E_i - E_j) * (1/T_i - 1/T_j)))`.
+use std::sync::Arc;
 
 use rand::Rng;
 
 use crate::configure::Config;
+use crate::evolution::engine::Engine;
+use crate::evolution::population::pier::Pier;
 use crate::evolution::{Genome, Phenome};
 use crate::observer::Observer;
 use crate::ontogenesis::Develop;
 use crate::util::random::hash_seed_rng;
-use crate::EPOCH_COUNTER;
 
-pub struct Metropolis<E: Develop<P>, P: Phenome + Genome + 'static> {
+pub struct Replica<P> {
     pub specimen: P,
+    /// The temperature at which the replica's chain started.
+    pub initial_temperature: f64,
+}
+
+pub struct Metropolis<E: Develop<P>, P: Phenome + Genome + 'static> {
+    pub replicas: Vec<Replica<P>>,
     pub config: Config,
     pub iteration: usize,
     pub observer: Observer<P>,
@@ -18,12 +37,37 @@ pub struct Metropolis<E: Develop<P>, P: Phenome + Genome + 'static> {
     pub best: Option<P>,
 }
 
+/// The energy of a specimen, with unevaluated specimens counting as the
+/// worst.
+fn energy<P: Phenome>(specimen: &P, config: &Config) -> f64 {
+    specimen
+        .scalar_fitness(&config.fitness.weighting)
+        .filter(|f| !f.is_nan())
+        .unwrap_or(f64::MAX)
+}
+
+/// The Metropolis-Hastings criterion: whether to move to a state whose
+/// energy is greater than the current one's by `delta`, given a uniform
+/// sample `r` from [0, 1).
+fn accept(delta: f64, temperature: f64, r: f64) -> bool {
+    delta <= 0.0 || r < (-delta / temperature).exp()
+}
+
 impl<E: Develop<P>, P: Phenome + Genome + 'static> Metropolis<E, P> {
     pub fn new(config: &Config, observer: Observer<P>, evaluator: E) -> Self {
-        let specimen = P::random(&config, 1);
+        let replicas = config
+            .metropolis
+            .ladder()
+            .into_iter()
+            .enumerate()
+            .map(|(i, initial_temperature)| Replica {
+                specimen: P::random(config, i),
+                initial_temperature,
+            })
+            .collect();
 
         Self {
-            specimen,
+            replicas,
             config: config.clone(),
             iteration: 0,
             observer,
@@ -34,71 +78,109 @@ impl<E: Develop<P>, P: Phenome + Genome + 'static> Metropolis<E, P> {
 
     pub fn evolve(self) -> Self {
         let Self {
-            specimen,
+            replicas,
             config,
             iteration,
             observer,
-            evaluator,
-            best,
+            mut evaluator,
+            mut best,
         } = self;
 
-        EPOCH_COUNTER.fetch_add(1, Ordering::Relaxed);
+        // The epoch is global, so only the first island advances it.
+        if config.island_id == 0 {
+            crate::increment_epoch_counter();
+        }
 
-        let mut specimen = if specimen.fitness().is_none() {
-            evaluator.develop(specimen)
-        } else {
-            specimen
-        };
-        let variation = Genome::mate(&[&specimen, &specimen], &config);
-        let variation = evaluator.develop(variation);
-
-        let mut rng = hash_seed_rng(&specimen);
-        let vari_fit = variation.scalar_fitness(&config.fitness.weighting).unwrap();
-        let spec_fit = specimen.scalar_fitness(&config.fitness.weighting).unwrap();
-        let delta = if (vari_fit - spec_fit).abs() < std::f64::EPSILON {
-            0.0
+        let mut rng = hash_seed_rng(&(iteration as u64 ^ config.random_seed));
+
+        let (initial_temperatures, specimens): (Vec<f64>, Vec<P>) = replicas
+            .into_iter()
+            .map(|r| (r.initial_temperature, r.specimen))
+            .unzip();
+
+        // Each specimen is evaluated once, on the first step.
+        let specimens = if iteration == 0 {
+            specimens
+                .into_iter()
+                .map(|p| {
+                    let p = evaluator.develop(p);
+                    evaluator.apply_fitness_function(p)
+                })
+                .collect::<Vec<P>>()
         } else {
-            1.0 / (vari_fit - spec_fit)
+            specimens
         };
 
-        // if the variation is fitter, replace.
-        // otherwise, let there be a chance of replacement inversely proportionate to the
-        // difference in fitness.
-        if delta < 0.0 || ((-delta).exp()) < rng.gen_range(0.0, 1.0) {
-            //if delta < 0.0 { // pure hillclimbing
-            specimen = variation;
-            log::info!(
-                "[{}] best: {:?}. specimen: {}, variation: {} (delta {}), switching",
-                iteration,
-                best.as_ref()
-                    .and_then(|p| p.scalar_fitness(&config.fitness.weighting)),
-                spec_fit,
-                vari_fit,
-                delta
-            );
-        };
-        observer.observe(specimen.clone());
+        // Every replica's proposal is an offspring of its specimen.
+        let variations = specimens
+            .iter()
+            .map(|specimen| {
+                let variation = Genome::mate(&[specimen, specimen], &config);
+                let variation = evaluator.develop(variation);
+                evaluator.apply_fitness_function(variation)
+            })
+            .collect::<Vec<P>>();
+
+        let mut replicas = specimens
+            .into_iter()
+            .zip(variations.into_iter())
+            .zip(initial_temperatures.into_iter())
+            .map(|((specimen, variation), initial_temperature)| {
+                let temperature = config
+                    .metropolis
+                    .temperature(initial_temperature, iteration);
+                let spec_fit = energy(&specimen, &config);
+                let vari_fit = energy(&variation, &config);
+                let r = rng.gen_range(0.0, 1.0);
+                let specimen = if accept(vari_fit - spec_fit, temperature, r) {
+                    log::debug!(
+                        "[{}] island {}, T = {}: specimen: {}, variation: {}, switching",
+                        iteration,
+                        config.island_id,
+                        temperature,
+                        spec_fit,
+                        vari_fit,
+                    );
+                    variation
+                } else {
+                    specimen
+                };
+                Replica {
+                    specimen,
+                    initial_temperature,
+                }
+            })
+            .collect::<Vec<Replica<P>>>();
 
-        let mut updated_best = false;
-        let best = match best {
-            Some(b)
-                if specimen.scalar_fitness(&config.fitness.weighting).unwrap()
-                    < b.scalar_fitness(&config.fitness.weighting).unwrap() =>
+        if replicas.len() > 1
+            && config.metropolis.swap_interval > 0
+            && iteration % config.metropolis.swap_interval == 0
+        {
+            Self::propose_swaps(&mut replicas, &config, iteration, &mut rng);
+        }
+
+        for replica in replicas.iter() {
+            let specimen = &replica.specimen;
+            observer.observe(specimen.clone());
+            if best
+                .as_ref()
+                .map_or(true, |b| energy(specimen, &config) < energy(b, &config))
             {
-                updated_best = true;
-                Some(specimen.clone())
+                log::info!("new best: {:?}", specimen);
+                best = Some(specimen.clone());
             }
-            None => {
-                updated_best = true;
-                Some(specimen.clone())
+            if specimen.is_goal_reached(&config) {
+                log::info!(
+                    "Island {}: goal reached by {}",
+                    config.island_id,
+                    specimen.name()
+                );
+                crate::stop_everything(config.island_id, true);
             }
-            _ => best,
-        };
-        if updated_best {
-            log::info!("new best: {:?}", best.as_ref().unwrap());
         }
+
         Self {
-            specimen,
+            replicas,
             config,
             iteration: iteration + 1,
             observer,
@@ -106,4 +188,85 @@ impl<E: Develop<P>, P: Phenome + Genome + 'static> Metropolis<E, P> {
             best,
         }
     }
+
+    /// Proposes a swap of specimens between each pair of neighbouring
+    /// replicas on the temperature ladder, starting alternately from the
+    /// coldest and the second coldest.
+    fn propose_swaps<R: Rng>(
+        replicas: &mut [Replica<P>],
+        config: &Config,
+        iteration: usize,
+        rng: &mut R,
+    ) {
+        let start = (iteration / config.metropolis.swap_interval.max(1)) % 2;
+        for i in (start..replicas.len() - 1).step_by(2) {
+            let t_i = config
+                .metropolis
+                .temperature(replicas[i].initial_temperature, iteration);
+            let t_j = config
+                .metropolis
+                .temperature(replicas[i + 1].initial_temperature, iteration);
+            let e_i = energy(&replicas[i].specimen, config);
+            let e_j = energy(&replicas[i + 1].specimen, config);
+            // Swapping is the move from (e_i, e_j) to (e_j, e_i), whose
+            // "delta" at unit temperature is as follows.
+            let delta = (e_j - e_i) * (1.0 / t_i - 1.0 / t_j);
+            if accept(delta, 1.0, rng.gen_range(0.0, 1.0)) {
+                log::debug!(
+                    "island {}: swapping specimens of replicas at T = {} and T = {}",
+                    config.island_id,
+                    t_i,
+                    t_j
+                );
+                let (cold, hot) = replicas.split_at_mut(i + 1);
+                std::mem::swap(&mut cold[i].specimen, &mut hot[0].specimen);
+            }
+        }
+    }
+}
+
+impl<E: Develop<P>, P: Phenome + Genome + 'static> Engine<E, P> for Metropolis<E, P> {
+    fn init(config: &Config, observer: Observer<P>, evaluator: E, _pier: Arc<Pier<P>>) -> Self {
+        Self::new(config, observer, evaluator)
+    }
+
+    fn evolve(self) -> Self {
+        Metropolis::evolve(self)
+    }
+
+    fn snapshot(&self) -> Vec<P> {
+        self.replicas.iter().map(|r| r.specimen.clone()).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::configure::{CoolingSchedule, MetropolisConfig};
+
+    use super::*;
+
+    #[test]
+    fn test_cooling() {
+        let mut config = MetropolisConfig {
+            cooling_rate: 0.5,
+            min_temperature: 0.1,
+            replicas: 3,
+            ..Default::default()
+        };
+        assert_eq!(config.ladder(), vec![1.0, 2.0, 4.0]);
+        assert_eq!(config.temperature(1.0, 1), 0.5);
+        assert_eq!(config.temperature(1.0, 10), 0.1);
+        config.cooling = CoolingSchedule::Linear;
+        assert_eq!(config.temperature(2.0, 2), 1.0);
+        config.cooling = CoolingSchedule::Constant;
+        assert_eq!(config.temperature(2.0, 100), 2.0);
+    }
+
+    #[test]
+    fn test_accept() {
+        assert!(accept(-1.0, 1e-3, 0.999));
+        assert!(!accept(1.0, 1e-3, 0.001));
+        // at a high temperature, nearly everything goes
+        assert!(accept(1.0, 1e3, 0.99));
+    }
 }
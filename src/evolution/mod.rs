@@ -1,19 +1,25 @@
 use std::fmt;
 use std::fmt::Debug;
-use std::hash::Hash;
+use std::hash::{Hash, Hasher};
+use std::ops::Range;
 
 use rand::{thread_rng, Rng};
 use serde::de::DeserializeOwned;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
-use crate::configure::Config;
+use crate::configure::{Config, CrossoverAlgorithm};
+use crate::evolution::adaptation::MutationParams;
 use crate::fitness::FitnessScore;
 use crate::util;
 use crate::util::count_min_sketch::Sketch;
 use crate::util::levy_flight::levy_decision;
 use crate::util::random::{hash_seed_rng, Prng};
 
-//pub mod lexicase;
+pub mod adaptation;
+pub mod alps;
+pub mod engine;
+pub mod lexicase;
+pub mod map_elites;
 pub mod metropolis;
 pub mod pareto_roulette;
 pub mod population;
@@ -24,7 +30,29 @@ pub trait Mutation {
 
     fn mutate_point(allele: &mut Self::Allele, config: &Config) -> Self;
 
+    /// Every operator, for operator credit. Types that return none are
+    /// left to choose their operators as they see fit.
+    fn operators() -> Vec<Self>
+    where
+        Self: Sized,
+    {
+        vec![]
+    }
+
     fn mutate(chromosome: &mut [Self::Allele], config: &Config) -> Vec<Option<Self>>
+    where
+        Self: Sized,
+    {
+        Self::mutate_with_exponent(chromosome, config.mutation_exponent, config)
+    }
+
+    /// Mutates the chromosome with the given Levy flight exponent, rather
+    /// than the global `mutation_exponent`.
+    fn mutate_with_exponent(
+        chromosome: &mut [Self::Allele],
+        exponent: f64,
+        config: &Config,
+    ) -> Vec<Option<Self>>
     where
         Self: Sized,
     {
@@ -32,7 +60,7 @@ pub trait Mutation {
         let len = chromosome.len();
         (0..len)
             .map(|i| {
-                if levy_decision(&mut rng, len, config.mutation_exponent) {
+                if levy_decision(&mut rng, len, exponent) {
                     Some(Self::mutate_point(&mut chromosome[i], &config))
                 } else {
                     None
@@ -43,7 +71,8 @@ pub trait Mutation {
 }
 
 //@formatter:off
-#[derive(Clone, Hash, Serialize)]
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(bound(deserialize = ""))]
 //@formatter:on
 pub struct LinearChromosome<
     A: Debug + Clone + Hash + Serialize + DeserializeOwned + Sized,
@@ -55,12 +84,42 @@ pub struct LinearChromosome<
     pub parent_names: Vec<String>,
     pub name: String,
     pub generation: usize,
+    /// The number of inheritances each gene has passed through since it was
+    /// last executed, mutated, or created. Missing entries count as zero.
+    #[serde(default)]
+    pub gene_ages: Vec<usize>,
+    /// The chromosome's own mutation parameters, under self-adaptation.
+    #[serde(default)]
+    pub mutation_params: Option<MutationParams>,
+    /// The priority fitness of the fittest parent, until the offspring's
+    /// mutation operators have been credited.
+    #[serde(default)]
+    pub parent_fitness: Option<f64>,
+}
+
+// The parent fitness is left out of the hash, being bookkeeping rather than
+// part of the genome.
+impl<A, M> Hash for LinearChromosome<A, M>
+where
+    A: Debug + Clone + Hash + Serialize + DeserializeOwned,
+    M: Debug + Clone + Hash + Serialize + DeserializeOwned + Mutation<Allele = A>,
+{
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.chromosome.hash(state);
+        self.mutations.hash(state);
+        self.parentage.hash(state);
+        self.parent_names.hash(state);
+        self.name.hash(state);
+        self.generation.hash(state);
+        self.gene_ages.hash(state);
+        self.mutation_params.hash(state);
+    }
 }
 
 // TODO: Define a mutation method on the mutation enum type
 
 impl<
-        A: Debug + Clone + Hash + Serialize + DeserializeOwned,
+        A: Debug + Clone + Hash + PartialEq + Serialize + DeserializeOwned,
         M: Debug + Clone + Hash + Serialize + DeserializeOwned + Mutation<Allele = A>,
     > LinearChromosome<A, M>
 {
@@ -69,27 +128,111 @@ impl<
     }
 
     pub fn crossover(parents: &[&Self], config: &Config) -> Self {
+        Self::crossover_with_execution(parents, &[], config)
+    }
+
+    /// Like `crossover`, where `executed[p][i]` tells whether the allele at
+    /// locus `i` of parent `p` was executed as a gadget, for the
+    /// `Homologous` and `GadgetBoundary` algorithms. Parents with no entry
+    /// are treated as unprofiled.
+    pub fn crossover_with_execution(
+        parents: &[&Self],
+        executed: &[Vec<bool>],
+        config: &Config,
+    ) -> Self {
+        let mut rng = thread_rng();
+        let mut offspring = Self::recombine(parents, executed, config);
+        offspring.mutation_params = MutationParams::inherit(
+            &parents
+                .iter()
+                .map(|p| p.mutation_params)
+                .collect::<Vec<_>>(),
+            config,
+            &mut rng,
+        );
+        offspring.parent_fitness = None;
+        offspring
+    }
+
+    fn recombine(parents: &[&Self], executed: &[Vec<bool>], config: &Config) -> Self {
         let min_mate_len = parents.iter().map(|p| p.len()).min().unwrap();
         let lambda = min_mate_len as f64 / config.crossover_period;
         let mut rng = thread_rng();
         if rng.gen_bool(config.crossover_rate) {
-            match config.crossover_algorithm.as_ref() {
-                "one_point" => Self::one_point_crossover(&parents, config),
-                "alternating" => {
+            match config.crossover_algorithm {
+                CrossoverAlgorithm::OnePoint => Self::one_point_crossover(&parents, config),
+                CrossoverAlgorithm::TwoPoint => Self::two_point_crossover(parents, config),
+                CrossoverAlgorithm::Uniform => Self::uniform_crossover(parents, config),
+                CrossoverAlgorithm::Alternating => {
                     let distribution =
                         rand_distr::Exp::new(lambda).expect("Failed to create random distribution");
                     Self::alternating_crossover(&distribution, parents, config)
                 }
-                _ => unimplemented!("bad algorithm name"),
+                CrossoverAlgorithm::Homologous => {
+                    Self::homologous_crossover(parents, executed, config)
+                }
+                CrossoverAlgorithm::GadgetBoundary => {
+                    Self::gadget_boundary_crossover(parents, executed, config)
+                }
             }
         } else {
             parents[rng.gen::<usize>() % parents.len()].cloned_offspring()
         }
     }
 
+    /// The age an allele inherited from the given locus will have in the
+    /// offspring.
+    fn inherited_age(&self, i: usize) -> usize {
+        self.gene_ages.get(i).copied().unwrap_or(0) + 1
+    }
+
+    /// Resets the age of the genes at the given loci, which were consumed
+    /// when the chromosome was executed.
+    pub fn exercise(&mut self, loci: Range<usize>) {
+        let len = self.chromosome.len();
+        self.gene_ages.resize(len, 0);
+        for age in self.gene_ages[loci.start.min(len)..loci.end.min(len)].iter_mut() {
+            *age = 0;
+        }
+    }
+
+    /// Deletes every gene older than `ttl`, or, if `fresh` is given, replaces
+    /// it with a new allele. At least one gene is always kept. Returns the
+    /// number of genes expired.
+    pub fn expire<F: FnMut() -> A>(&mut self, ttl: usize, mut fresh: Option<F>) -> usize {
+        self.gene_ages.resize(self.chromosome.len(), 0);
+        self.mutations.resize(self.chromosome.len(), None);
+        self.parentage.resize(self.chromosome.len(), 0);
+        let mut expired = 0;
+        let mut i = 0;
+        while i < self.chromosome.len() {
+            if self.gene_ages[i] <= ttl {
+                i += 1;
+                continue;
+            }
+            if let Some(ref mut fresh) = fresh {
+                self.chromosome[i] = fresh();
+                self.gene_ages[i] = 0;
+                self.mutations[i] = None;
+                i += 1;
+            } else if self.chromosome.len() > 1 {
+                self.chromosome.remove(i);
+                self.gene_ages.remove(i);
+                self.mutations.remove(i);
+                self.parentage.remove(i);
+            } else {
+                break;
+            }
+            expired += 1;
+        }
+        expired
+    }
+
     pub fn cloned_offspring(&self) -> Self {
         let mut offspring = self.clone();
         offspring.generation += 1;
+        offspring.mutations = vec![None; offspring.chromosome.len()];
+        offspring.gene_ages = (0..self.len()).map(|i| self.inherited_age(i)).collect();
         offspring.parentage = vec![0; offspring.chromosome.len()];
         offspring.parent_names = vec![self.name.clone()];
         offspring.name = util::name::random(4, &self);
@@ -106,6 +249,7 @@ impl<
         let splice_m = rng.gen_range(0, mother.len());
         let mut chromosome = Vec::new();
         let mut parentage = Vec::new();
+        let mut gene_ages = Vec::new();
         let mut counter = 0;
         // let there be some chance of the first allele being dropped.
         // because it's unlikely this will happen otherwise. the first allele
@@ -118,6 +262,7 @@ impl<
         for i in start..splice_f {
             chromosome.push(father.chromosome[i].clone());
             parentage.push(father_idx);
+            gene_ages.push(father.inherited_age(i));
             counter += 1;
             if counter >= config.max_length {
                 break;
@@ -126,6 +271,7 @@ impl<
         for i in splice_m..mother.len() {
             chromosome.push(mother.chromosome[i].clone());
             parentage.push(mother_idx);
+            gene_ages.push(mother.inherited_age(i));
             counter += 1;
             if counter >= config.max_length {
                 break;
@@ -145,9 +291,155 @@ impl<
                 .collect::<Vec<String>>(),
             name,
             generation,
+            gene_ages,
+            mutation_params: None,
+            parent_fitness: None,
         }
     }
 
+    /// Assembles an offspring from segments of its parents' chromosomes,
+    /// each given as the index of a parent and a range of its loci. If no
+    /// allele is taken at all, the offspring is a clone of the first
+    /// segment's parent.
+    fn splice(parents: &[&Self], segments: &[(usize, Range<usize>)], config: &Config) -> Self {
+        let mut chromosome = Vec::new();
+        let mut parentage = Vec::new();
+        let mut gene_ages = Vec::new();
+        'segments: for (src, range) in segments.iter() {
+            for i in range.clone() {
+                if chromosome.len() >= config.max_length {
+                    break 'segments;
+                }
+                chromosome.push(parents[*src].chromosome[i].clone());
+                parentage.push(*src);
+                gene_ages.push(parents[*src].inherited_age(i));
+            }
+        }
+        if chromosome.is_empty() {
+            return parents[segments.first().map_or(0, |s| s.0)].cloned_offspring();
+        }
+
+        let name = util::name::random(4, &chromosome);
+        let len = chromosome.len();
+        Self {
+            chromosome,
+            mutations: vec![None; len],
+            parentage,
+            parent_names: parents
+                .iter()
+                .map(|p| p.name.clone())
+                .collect::<Vec<String>>(),
+            name,
+            generation: parents.iter().map(|p| p.generation).max().unwrap_or(0) + 1,
+            gene_ages,
+            mutation_params: None,
+            parent_fitness: None,
+        }
+    }
+
+    /// Chooses a mother at random, and the next parent as the father.
+    fn choose_mates<R: Rng>(parents: &[&Self], rng: &mut R) -> (usize, usize) {
+        let mother_idx = rng.gen_range(0, parents.len());
+        (mother_idx, (mother_idx + 1) % parents.len())
+    }
+
+    /// The offspring takes a segment of the father in place of a segment of
+    /// the mother.
+    fn two_point_crossover(parents: &[&Self], config: &Config) -> Self {
+        let mut rng = thread_rng();
+        let (mother_idx, father_idx) = Self::choose_mates(parents, &mut rng);
+        let mut cuts = |len: usize| {
+            let a = rng.gen_range(0, len + 1);
+            let b = rng.gen_range(0, len + 1);
+            (a.min(b), a.max(b))
+        };
+        let (m1, m2) = cuts(parents[mother_idx].len());
+        let (f1, f2) = cuts(parents[father_idx].len());
+        Self::splice(
+            parents,
+            &[
+                (mother_idx, 0..m1),
+                (father_idx, f1..f2),
+                (mother_idx, m2..parents[mother_idx].len()),
+            ],
+            config,
+        )
+    }
+
+    /// The offspring has the length of a randomly chosen parent, and takes
+    /// each locus from any parent long enough to have it.
+    fn uniform_crossover(parents: &[&Self], config: &Config) -> Self {
+        let mut rng = thread_rng();
+        let len = parents[rng.gen_range(0, parents.len())].len();
+        let segments = (0..len)
+            .map(|i| {
+                let donors = (0..parents.len())
+                    .filter(|p| i < parents[*p].len())
+                    .collect::<Vec<usize>>();
+                (donors[rng.gen_range(0, donors.len())], i..i + 1)
+            })
+            .collect::<Vec<_>>();
+        Self::splice(parents, &segments, config)
+    }
+
+    /// The offspring takes the mother's alleles up to a random cut, and the
+    /// father's from the homologous locus on.
+    fn homologous_crossover(parents: &[&Self], executed: &[Vec<bool>], config: &Config) -> Self {
+        let mut rng = thread_rng();
+        let (mother_idx, father_idx) = Self::choose_mates(parents, &mut rng);
+        let mother = parents[mother_idx];
+        let father = parents[father_idx];
+        let splice_m = rng.gen_range(0, mother.len());
+        let splice_f = homologous_locus(
+            &mother.chromosome,
+            splice_m,
+            &father.chromosome,
+            executed.get(mother_idx).map_or(&[][..], Vec::as_slice),
+            executed.get(father_idx).map_or(&[][..], Vec::as_slice),
+        );
+        Self::splice(
+            parents,
+            &[
+                (mother_idx, 0..splice_m),
+                (father_idx, splice_f..father.len()),
+            ],
+            config,
+        )
+    }
+
+    /// One-point crossover, where each parent is cut just before an allele
+    /// that was executed as a gadget, if it has any.
+    fn gadget_boundary_crossover(
+        parents: &[&Self],
+        executed: &[Vec<bool>],
+        config: &Config,
+    ) -> Self {
+        let mut rng = thread_rng();
+        let (mother_idx, father_idx) = Self::choose_mates(parents, &mut rng);
+        let mut cut = |idx: usize| {
+            let len = parents[idx].len();
+            let boundaries: Vec<usize> = executed
+                .get(idx)
+                .map(|e| (0..len).filter(|i| e.get(*i) == Some(&true)).collect())
+                .unwrap_or_else(Vec::new);
+            if boundaries.is_empty() {
+                rng.gen_range(0, len)
+            } else {
+                boundaries[rng.gen_range(0, boundaries.len())]
+            }
+        };
+        let splice_f = cut(father_idx);
+        let splice_m = cut(mother_idx);
+        Self::splice(
+            parents,
+            &[
+                (father_idx, 0..splice_f),
+                (mother_idx, splice_m..parents[mother_idx].len()),
+            ],
+            config,
+        )
+    }
+
     fn alternating_crossover<D: rand_distr::Distribution<f64>>(
         distribution: &D,
         parents: &[&Self],
@@ -155,6 +447,7 @@ impl<
     ) -> Self {
         let mut chromosome = Vec::new();
         let mut parentage = Vec::new();
+        let mut gene_ages = Vec::new();
         let mut rng = hash_seed_rng(&parents[0].chromosome);
         let mut ptrs = vec![0_usize; parents.len()];
         let switch = |rng: &mut Prng| rng.gen_range(0, parents.len());
@@ -176,7 +469,8 @@ impl<
             let take_to = ptrs[src] + sample(&mut rng);
             let len = parents[src].len();
             for i in take_from..take_to {
-                chromosome.push(parents[src].chromosome[i % len].clone())
+                chromosome.push(parents[src].chromosome[i % len].clone());
+                gene_ages.push(parents[src].inherited_age(i % len));
             }
 
             for _ in 0..(take_to - take_from) {
@@ -205,14 +499,78 @@ impl<
                 .collect::<Vec<String>>(),
             name,
             generation: parents.iter().map(|p| p.generation).max().unwrap_or(0) + 1,
+            gene_ages,
+            mutation_params: None,
+            parent_fitness: None,
         }
     }
 
+    /// The chance that the offspring is mutated at all.
+    pub fn mutation_rate(&self, config: &Config) -> f64 {
+        self.mutation_params
+            .map_or(config.mutation_rate, |p| p.rate)
+    }
+
     pub fn mutate(&mut self, config: &Config) {
         // maybe check a uniform mutation rate to see if any pointwise mutations happen at all.
-        let mutations = M::mutate(&mut self.chromosome, config);
+        let exponent = self
+            .mutation_params
+            .map_or(config.mutation_exponent, |p| p.exponent);
+        let mutations = M::mutate_with_exponent(&mut self.chromosome, exponent, config);
+        // a mutated gene is a new gene
+        self.gene_ages.resize(self.chromosome.len(), 0);
+        for (age, mutation) in self.gene_ages.iter_mut().zip(mutations.iter()) {
+            if mutation.is_some() {
+                *age = 0;
+            }
+        }
         self.mutations = mutations;
     }
+
+    /// Once the offspring has been evaluated, credits the operators that
+    /// mutated it with whether it improved on its fittest parent. Each
+    /// offspring is credited only once.
+    pub fn credit_operators(&mut self, fitness: Option<f64>, config: &Config) {
+        if let (Some(fitness), Some(parent_fitness)) = (fitness, self.parent_fitness.take()) {
+            adaptation::credit(config, &self.mutations, fitness < parent_fitness);
+        }
+    }
+}
+
+/// The locus of `other` homologous to locus `i` of `one`: the nearest locus
+/// holding the same allele, or failing that, the locus of the gadget executed
+/// at the same position in the chain, or failing that, the locus at the same
+/// relative position.
+fn homologous_locus<A: PartialEq>(
+    one: &[A],
+    i: usize,
+    other: &[A],
+    one_executed: &[bool],
+    other_executed: &[bool],
+) -> usize {
+    if let Some(j) = other
+        .iter()
+        .enumerate()
+        .filter(|(_, a)| **a == one[i])
+        .map(|(j, _)| j)
+        .min_by_key(|j| (*j as isize - i as isize).abs())
+    {
+        return j;
+    }
+    if one_executed.get(i) == Some(&true) {
+        let position = one_executed[..i].iter().filter(|e| **e).count();
+        if let Some(j) = other_executed
+            .iter()
+            .take(other.len())
+            .enumerate()
+            .filter(|(_, e)| **e)
+            .map(|(j, _)| j)
+            .nth(position)
+        {
+            return j;
+        }
+    }
+    i * other.len() / one.len().max(1)
 }
 
 impl<A, M> fmt::Debug for LinearChromosome<A, M>
@@ -321,12 +679,18 @@ pub trait Genome: Hash {
         // the mutate method should check the mutation rate or exponent and
         // make the mutation decisions internally
         let mut rng = hash_seed_rng(&parents);
-        if rng.gen_range(0.0, 1.0) < config.mutation_rate {
+        if rng.gen_range(0.0, 1.0) < child.mutation_rate(config) {
             child.mutate(&config);
         }
         child
     }
 
+    /// The chance that an offspring is mutated. Creatures that carry their
+    /// own mutation parameters override this.
+    fn mutation_rate(&self, config: &Config) -> f64 {
+        config.mutation_rate
+    }
+
     fn digrams(&self) -> Box<dyn Iterator<Item = (Self::Allele, Self::Allele)> + '_> {
         if self.chromosome().len() == 1 {
             // FIXME: i don't like this edge case.
@@ -421,3 +785,185 @@ pub trait Phenome: Clone + Debug + Send + Serialize + Hash {
         unimplemented!("implement as needed")
     }
 }
+
+#[cfg(test)]
+mod test {
+    use crate::roper::bare::WordMutation;
+
+    use super::*;
+
+    #[test]
+    fn test_homologous_locus() {
+        let one = [1, 2, 3, 4, 5, 6];
+        let other = [9, 3, 8, 7, 3, 5];
+        // aligned by address, to the nearest match
+        assert_eq!(homologous_locus(&one, 2, &other, &[], &[]), 1);
+        assert_eq!(homologous_locus(&one, 4, &other, &[], &[]), 5);
+        // aligned by execution: 4 is the second gadget executed in one, and
+        // 8 the second in other
+        let one_executed = [false, true, false, true, false, false];
+        let other_executed = [true, false, true, false, false, false];
+        assert_eq!(
+            homologous_locus(&one, 3, &other, &one_executed, &other_executed),
+            2
+        );
+        // aligned by relative position
+        assert_eq!(homologous_locus(&one, 3, &other[..3], &[], &[]), 1);
+    }
+
+    fn aged(words: &[u64], gene_ages: &[usize]) -> LinearChromosome<u64, WordMutation> {
+        LinearChromosome {
+            chromosome: words.to_vec(),
+            mutations: vec![None; words.len()],
+            parentage: vec![0; words.len()],
+            parent_names: vec![],
+            name: "test".to_string(),
+            generation: 0,
+            gene_ages: gene_ages.to_vec(),
+            mutation_params: None,
+            parent_fitness: None,
+        }
+    }
+
+    #[test]
+    fn test_expire() {
+        let mut c = aged(&[1, 2, 3, 4], &[0, 5, 1, 7]);
+        assert_eq!(c.expire(3, None::<fn() -> u64>), 2);
+        assert_eq!(c.chromosome, vec![1, 3]);
+        assert_eq!(c.gene_ages, vec![0, 1]);
+        assert_eq!((c.mutations.len(), c.parentage.len()), (2, 2));
+
+        // the last gene is kept, however old
+        let mut c = aged(&[1, 2], &[4, 4]);
+        assert_eq!(c.expire(3, None::<fn() -> u64>), 1);
+        assert_eq!(c.chromosome, vec![2]);
+
+        let mut c = aged(&[1, 2, 3, 4], &[0, 5, 1, 7]);
+        assert_eq!(c.expire(3, Some(|| 99)), 2);
+        assert_eq!(c.chromosome, vec![1, 99, 3, 99]);
+        assert_eq!(c.gene_ages, vec![0, 0, 1, 0]);
+    }
+
+    #[test]
+    fn test_gene_age_inheritance() {
+        // missing ages count as zero
+        let mother = aged(&[1, 2, 3], &[0, 4]);
+        assert_eq!(mother.cloned_offspring().gene_ages, vec![1, 5, 1]);
+
+        let father = aged(&[7, 8, 9], &[2, 0, 6]);
+        let mut config = Config::default();
+        config.max_length = 100;
+        let mut child =
+            LinearChromosome::splice(&[&mother, &father], &[(0, 0..2), (1, 1..3)], &config);
+        assert_eq!(child.chromosome, vec![1, 2, 8, 9]);
+        assert_eq!(child.gene_ages, vec![1, 5, 1, 7]);
+
+        // running the payload consumes a prefix of it
+        child.exercise(0..3);
+        assert_eq!(child.gene_ages, vec![0, 0, 0, 7]);
+        child.exercise(2..10);
+        assert_eq!(child.gene_ages, vec![0, 0, 0, 0]);
+    }
+
+    fn parents() -> (
+        LinearChromosome<u64, WordMutation>,
+        LinearChromosome<u64, WordMutation>,
+    ) {
+        (aged(&[1, 2, 3, 4, 5, 6], &[]), aged(&[11, 12, 13, 14], &[]))
+    }
+
+    fn crossover_config() -> Config {
+        let mut config = Config::default();
+        config.max_length = 100;
+        config
+    }
+
+    #[test]
+    fn test_two_point_crossover() {
+        let (a, b) = parents();
+        let config = crossover_config();
+        // the offspring is one parent with a segment replaced by a segment
+        // of the other
+        let is_two_point = |child: &[u64], m: &[u64], f: &[u64]| {
+            (0..=m.len()).any(|m1| {
+                (m1..=m.len()).any(|m2| {
+                    (0..=f.len()).any(|f1| {
+                        (f1..=f.len()).any(|f2| {
+                            let spliced = m[..m1]
+                                .iter()
+                                .chain(f[f1..f2].iter())
+                                .chain(m[m2..].iter())
+                                .copied()
+                                .collect::<Vec<u64>>();
+                            spliced == child
+                        })
+                    })
+                })
+            })
+        };
+        for _ in 0..1000 {
+            let child = LinearChromosome::two_point_crossover(&[&a, &b], &config);
+            assert!(
+                is_two_point(&child.chromosome, &a.chromosome, &b.chromosome)
+                    || is_two_point(&child.chromosome, &b.chromosome, &a.chromosome),
+                "{:?} is not a two-point crossover",
+                child.chromosome
+            );
+            assert_eq!(child.parentage.len(), child.chromosome.len());
+        }
+    }
+
+    #[test]
+    fn test_uniform_crossover() {
+        let (a, b) = parents();
+        let config = crossover_config();
+        let mut mixed = false;
+        for _ in 0..1000 {
+            let child = LinearChromosome::uniform_crossover(&[&a, &b], &config);
+            assert!(child.len() == a.len() || child.len() == b.len());
+            // each locus comes from the same locus of a parent
+            for (i, (allele, parent)) in child
+                .chromosome
+                .iter()
+                .zip(child.parentage.iter())
+                .enumerate()
+            {
+                assert_eq!(*allele, [&a, &b][*parent].chromosome[i]);
+            }
+            mixed |= child.parentage.contains(&0) && child.parentage.contains(&1);
+        }
+        assert!(mixed);
+    }
+
+    #[test]
+    fn test_gadget_boundary_crossover() {
+        let (a, b) = parents();
+        let config = crossover_config();
+        let executed = vec![
+            vec![false, true, false, false, true, false],
+            vec![false, false, true, false],
+        ];
+        let cuts = |p: usize| {
+            (0..executed[p].len())
+                .filter(|i| executed[p][*i])
+                .collect::<Vec<usize>>()
+        };
+        let chromosomes = [&a.chromosome, &b.chromosome];
+        for _ in 0..1000 {
+            let child = LinearChromosome::gadget_boundary_crossover(&[&a, &b], &executed, &config);
+            // the father's alleles before one of his gadgets, and then the
+            // mother's, from one of hers on
+            let found = [(0, 1), (1, 0)].iter().any(|(f, m)| {
+                cuts(*f).iter().any(|cf| {
+                    cuts(*m).iter().any(|cm| {
+                        chromosomes[*f][..*cf]
+                            .iter()
+                            .chain(chromosomes[*m][*cm..].iter())
+                            .eq(child.chromosome.iter())
+                    })
+                })
+            });
+            assert!(found, "{:?} wasn't cut at gadgets", child.chromosome);
+        }
+    }
+}
@@ -7,6 +7,9 @@ use rand_distr::Distribution;
 use non_dominated_sort::{non_dominated_sort, DominanceOrd};
 
 use crate::configure::Config;
+use crate::evolution::engine::Engine;
+use crate::evolution::population::migration;
+use crate::evolution::population::pier::Pier;
 use crate::evolution::{Genome, Phenome};
 use crate::increment_epoch_counter;
 use crate::observer::Observer;
@@ -20,13 +23,18 @@ pub struct Roulette<E: Develop<P>, P: Phenome + Genome + 'static, D: DominanceOr
     pub evaluator: E,
     pub iteration: usize,
     pub dominance_order: D,
+    pub pier: Arc<Pier<P>>,
 }
 
 impl<E: Develop<P>, P: Phenome + Genome + 'static, D: DominanceOrd<P>> Roulette<E, P, D> {
-    pub fn new(config: &Config, observer: Observer<P>, evaluator: E, dominance_order: D) -> Self {
-        let population = (0..config.pop_size)
-            .map(|i| P::random(&config, i))
-            .collect();
+    pub fn new(
+        config: &Config,
+        observer: Observer<P>,
+        evaluator: E,
+        dominance_order: D,
+        pier: Arc<Pier<P>>,
+    ) -> Self {
+        let population = (0..config.pop_size).map(|i| P::random(config, i)).collect();
 
         Self {
             population,
@@ -35,6 +43,7 @@ impl<E: Develop<P>, P: Phenome + Genome + 'static, D: DominanceOrd<P>> Roulette<
             observer,
             evaluator,
             dominance_order,
+            pier,
         }
     }
 
@@ -46,6 +55,7 @@ impl<E: Develop<P>, P: Phenome + Genome + 'static, D: DominanceOrd<P>> Roulette<
             config,
             iteration,
             dominance_order,
+            pier,
         } = self;
 
         let mut rng = hash_seed_rng(&population);
@@ -55,6 +65,7 @@ impl<E: Develop<P>, P: Phenome + Genome + 'static, D: DominanceOrd<P>> Roulette<
             .into_iter()
             .map(|p| evaluator.apply_fitness_function(p))
             .collect::<Vec<P>>();
+        migration::migrate(&mut population, &pier, &config, &mut rng);
         // we're going to need to clone the population to send to the observer, anyway
         // so we might as well do that now. this lets us get around certain awkward
         // lifetime constraints imposed on us by the `Front` struct.
@@ -128,7 +139,10 @@ impl<E: Develop<P>, P: Phenome + Genome + 'static, D: DominanceOrd<P>> Roulette<
             new_population.push(child)
         }
 
-        increment_epoch_counter();
+        // The epoch is global, so only the first island advances it.
+        if config.island_id == 0 {
+            increment_epoch_counter();
+        }
 
         Self {
             population: new_population,
@@ -137,6 +151,23 @@ impl<E: Develop<P>, P: Phenome + Genome + 'static, D: DominanceOrd<P>> Roulette<
             evaluator,
             iteration: iteration + 1,
             dominance_order,
+            pier,
         }
     }
 }
+
+impl<E: Develop<P>, P: Phenome + Genome + 'static, D: DominanceOrd<P> + Default> Engine<E, P>
+    for Roulette<E, P, D>
+{
+    fn init(config: &Config, observer: Observer<P>, evaluator: E, pier: Arc<Pier<P>>) -> Self {
+        Self::new(config, observer, evaluator, D::default(), pier)
+    }
+
+    fn evolve(self) -> Self {
+        Roulette::evolve(self)
+    }
+
+    fn snapshot(&self) -> Vec<P> {
+        self.population.clone()
+    }
+}
@@ -0,0 +1,207 @@
+//! Adjusts the radius of a `TrivialGeography` in response to the genetic
+//! diversity of the creatures drawn into its tournaments. When diversity
+//! falls below the target, the radius shrinks, so that successful lineages
+//! spread more slowly across the deme. When it rises above the target, the
+//! radius widens, and selection pressure with it.
+use std::hash::{Hash, Hasher};
+
+use hashbrown::HashSet;
+
+use crate::configure::AdaptiveRadiusConfig;
+use crate::evolution::Genome;
+
+/// The ratio of distinct digrams to all digrams in the creatures' genomes,
+/// from near 0, when every genome is made of the same digram, to 1, when no
+/// digram is repeated.
+pub fn digram_diversity<'a, P: Genome + 'a, I: Iterator<Item = &'a P>>(creatures: I) -> f64 {
+    let mut count = DigramCount::default();
+    for creature in creatures {
+        count.record(creature);
+    }
+    count.diversity()
+}
+
+#[derive(Default)]
+struct DigramCount {
+    distinct: HashSet<u64>,
+    total: usize,
+}
+
+impl DigramCount {
+    fn record<P: Genome>(&mut self, creature: &P) {
+        for digram in creature.digrams() {
+            let mut hasher = fnv::FnvHasher::default();
+            digram.hash(&mut hasher);
+            self.distinct.insert(hasher.finish());
+            self.total += 1;
+        }
+    }
+
+    fn diversity(&self) -> f64 {
+        if self.total == 0 {
+            1.0
+        } else {
+            self.distinct.len() as f64 / self.total as f64
+        }
+    }
+}
+
+pub struct AdaptiveRadius {
+    config: AdaptiveRadiusConfig,
+    count: DigramCount,
+    tournaments: usize,
+    diversity: f64,
+}
+
+impl AdaptiveRadius {
+    /// The radius never falls below `min_radius`, nor to or below the
+    /// tournament size.
+    pub fn new(config: &AdaptiveRadiusConfig, tournament_size: usize) -> Self {
+        let mut config = config.clone();
+        config.min_radius = config.min_radius.max(tournament_size + 1);
+        config.max_radius = config.max_radius.max(config.min_radius);
+        Self {
+            config,
+            count: DigramCount::default(),
+            tournaments: 0,
+            diversity: 1.0,
+        }
+    }
+
+    /// The diversity measured over the last complete window.
+    pub fn diversity(&self) -> f64 {
+        self.diversity
+    }
+
+    /// Records the genomes of a tournament's combatants.
+    pub fn record<P: Genome>(&mut self, combatants: &[P]) {
+        for combatant in combatants {
+            self.count.record(combatant);
+        }
+        self.tournaments += 1;
+    }
+
+    /// Once a window of tournaments has been recorded, returns the new
+    /// radius, and starts a new window.
+    pub fn adjust(&mut self, radius: usize) -> Option<usize> {
+        if self.tournaments < self.config.window.max(1) {
+            return None;
+        }
+        self.diversity = std::mem::take(&mut self.count).diversity();
+        self.tournaments = 0;
+
+        let radius = if self.diversity < self.config.target_diversity - self.config.tolerance {
+            radius.saturating_sub(self.config.step)
+        } else if self.diversity > self.config.target_diversity + self.config.tolerance {
+            radius + self.config.step
+        } else {
+            radius
+        };
+        Some(
+            radius
+                .max(self.config.min_radius)
+                .min(self.config.max_radius),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::configure::Config;
+
+    use super::*;
+
+    fn adaptive_radius(tournament_size: usize) -> AdaptiveRadius {
+        let config = AdaptiveRadiusConfig {
+            min_radius: 10,
+            max_radius: 50,
+            target_diversity: 0.5,
+            tolerance: 0.1,
+            window: 2,
+            step: 5,
+        };
+        AdaptiveRadius::new(&config, tournament_size)
+    }
+
+    #[derive(Hash)]
+    struct Words(Vec<u64>);
+
+    impl Genome for Words {
+        type Allele = u64;
+
+        fn chromosome(&self) -> &[u64] {
+            &self.0
+        }
+
+        fn chromosome_mut(&mut self) -> &mut [u64] {
+            &mut self.0
+        }
+
+        fn native_island(&self) -> usize {
+            0
+        }
+
+        fn random<H: Hash>(_config: &Config, _salt: H) -> Self {
+            unimplemented!()
+        }
+
+        fn crossover(_parents: &[&Self], _config: &Config) -> Self {
+            unimplemented!()
+        }
+
+        fn mutate(&mut self, _config: &Config) {
+            unimplemented!()
+        }
+
+        fn incr_num_offspring(&mut self, _n: usize) {}
+
+        fn generation(&self) -> usize {
+            0
+        }
+
+        fn num_offspring(&self) -> usize {
+            0
+        }
+    }
+
+    /// Records a complete window of two tournaments, among whose
+    /// combatants' 10 digrams `distinct` are distinct, and adjusts the radius.
+    fn adjust(adaptive: &mut AdaptiveRadius, distinct: u64, radius: usize) -> Option<usize> {
+        let combatants = (0..10)
+            .map(|i| Words(vec![i.min(distinct - 1), 0]))
+            .collect::<Vec<_>>();
+        adaptive.record(&combatants[..5]);
+        adaptive.record(&combatants[5..]);
+        adaptive.adjust(radius)
+    }
+
+    #[test]
+    fn test_adjust() {
+        let mut adaptive = adaptive_radius(3);
+        adaptive.record(&[Words(vec![1, 2, 3]), Words(vec![1, 2, 3])]);
+        assert_eq!(adaptive.adjust(30), None);
+        adaptive.record(&[Words(vec![4, 5, 6])]);
+        // 4 of the window's 6 digrams are distinct
+        assert_eq!(adaptive.adjust(30), Some(35));
+        assert!((adaptive.diversity() - 4.0 / 6.0).abs() < std::f64::EPSILON);
+        assert!(
+            (digram_diversity([Words(vec![1, 2, 3]), Words(vec![1, 2, 3])].iter()) - 0.5).abs()
+                < std::f64::EPSILON
+        );
+
+        assert_eq!(adjust(&mut adaptive, 2, 30), Some(25));
+        assert!((adaptive.diversity() - 0.2).abs() < std::f64::EPSILON);
+        assert_eq!(adjust(&mut adaptive, 5, 30), Some(30));
+        assert_eq!(adjust(&mut adaptive, 9, 30), Some(35));
+        // the window starts over after each adjustment
+        assert_eq!(adaptive.adjust(30), None);
+
+        assert_eq!(adjust(&mut adaptive, 2, 12), Some(10));
+        assert_eq!(adjust(&mut adaptive, 2, 3), Some(10));
+        assert_eq!(adjust(&mut adaptive, 9, 48), Some(50));
+
+        // the radius is kept above the tournament size
+        let mut adaptive = adaptive_radius(20);
+        assert_eq!(adjust(&mut adaptive, 2, 25), Some(21));
+    }
+}
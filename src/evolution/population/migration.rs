@@ -0,0 +1,196 @@
+//! Migration between islands: the topology connecting their piers, and the
+//! policies by which emigrants and the residents replaced by immigrants
+//! are chosen.
+use rand::seq::SliceRandom;
+use rand::Rng;
+
+use crate::configure::{Config, MigrationConfig, MigrationPolicy, ReplacementPolicy, Topology};
+use crate::evolution::population::pier::Pier;
+use crate::evolution::{Genome, Phenome};
+use crate::util::count_min_sketch::CountMinSketch;
+use crate::util::random::hash_seed_rng;
+
+/// Returns the neighbours of each of `num_islands` islands: the islands to
+/// which it sends its emigrants. No island is its own neighbour.
+pub fn neighbours(config: &MigrationConfig, num_islands: usize, seed: u64) -> Vec<Vec<usize>> {
+    let n = num_islands;
+    let mut graph: Vec<Vec<usize>> = match config.topology {
+        Topology::Ring => (0..n).map(|i| vec![(i + 1) % n]).collect(),
+        Topology::TorusGrid => {
+            // the most nearly square grid with n cells
+            let cols = (1..=n)
+                .take_while(|c| c * c <= n)
+                .filter(|c| n % c == 0)
+                .last()
+                .unwrap_or(1);
+            if cols == 1 && n > 3 {
+                log::warn!(
+                    "{} islands can't be laid out on a torus with more than one row, \
+                     so the torus grid is a bidirectional ring",
+                    n
+                );
+            }
+            let rows = n / cols;
+            (0..n)
+                .map(|i| {
+                    let (r, c) = (i / cols, i % cols);
+                    vec![
+                        ((r + rows - 1) % rows) * cols + c,
+                        ((r + 1) % rows) * cols + c,
+                        r * cols + (c + cols - 1) % cols,
+                        r * cols + (c + 1) % cols,
+                    ]
+                })
+                .collect()
+        }
+        Topology::FullyConnected => (0..n)
+            .map(|i| (0..n).filter(|j| *j != i).collect())
+            .collect(),
+        Topology::RandomRegular => {
+            // A circulant graph over a random ordering of the islands, in
+            // which every island has `degree` neighbours, and is the
+            // neighbour of `degree` others.
+            let degree = config.degree.min(n.saturating_sub(1));
+            let mut order = (0..n).collect::<Vec<usize>>();
+            order.shuffle(&mut hash_seed_rng(&seed));
+            let mut graph = vec![vec![]; n];
+            for (pos, island) in order.iter().enumerate() {
+                graph[*island] = (1..=degree).map(|k| order[(pos + k) % n]).collect();
+            }
+            graph
+        }
+        Topology::Star => (0..n)
+            .map(|i| if i == 0 { (1..n).collect() } else { vec![0] })
+            .collect(),
+    };
+    for (i, adjacent) in graph.iter_mut().enumerate() {
+        adjacent.sort_unstable();
+        adjacent.dedup();
+        adjacent.retain(|j| *j != i);
+    }
+    graph
+}
+
+/// Scalar fitness by the priority expression, with unevaluated creatures
+/// counting as the worst.
+pub fn priority_fitness<P: Phenome>(creature: &P, config: &Config) -> f64 {
+    creature
+        .scalar_fitness(config.fitness.priority())
+        .filter(|f| !f.is_nan())
+        .unwrap_or(f64::MAX)
+}
+
+/// Returns the index of the candidate to be sent abroad.
+pub fn choose_emigrant<P: Phenome + Genome, R: Rng>(
+    candidates: &[P],
+    config: &Config,
+    rng: &mut R,
+) -> usize {
+    match config.migration.policy {
+        MigrationPolicy::Best => index_of_min(candidates, |c| priority_fitness(c, config)),
+        MigrationPolicy::Random => rng.gen_range(0, candidates.len()),
+        MigrationPolicy::MostNovel => {
+            let mut sketch = CountMinSketch::new(config);
+            for candidate in candidates {
+                candidate.record_genetic_frequency(&mut sketch);
+            }
+            index_of_min(candidates, |c| c.query_genetic_frequency(&sketch))
+        }
+    }
+}
+
+/// Returns the index of the candidate to be replaced by an immigrant.
+pub fn choose_replacement<P: Phenome, R: Rng>(
+    candidates: &[P],
+    config: &Config,
+    rng: &mut R,
+) -> usize {
+    match config.migration.replacement {
+        ReplacementPolicy::Worst => index_of_min(candidates, |c| -priority_fitness(c, config)),
+        ReplacementPolicy::Random => rng.gen_range(0, candidates.len()),
+    }
+}
+
+fn index_of_min<P, F: Fn(&P) -> f64>(candidates: &[P], key: F) -> usize {
+    candidates
+        .iter()
+        .map(key)
+        .enumerate()
+        .fold((0, f64::INFINITY), |(best_i, best), (i, k)| {
+            if k < best {
+                (i, k)
+            } else {
+                (best_i, best)
+            }
+        })
+        .0
+}
+
+/// With probability `tournament.migration_rate`, sends a copy of one of the
+/// candidates to a neighbouring island, and lets any immigrant waiting on
+/// the pier take the place of one of them.
+pub fn migrate<P: Phenome + Genome, R: Rng>(
+    candidates: &mut [P],
+    pier: &Pier<P>,
+    config: &Config,
+    rng: &mut R,
+) {
+    if candidates.is_empty() {
+        return;
+    }
+    if rng.gen_range(0.0, 1.0) < config.tournament.migration_rate {
+        let emigrant = candidates[choose_emigrant(candidates, config, rng)].clone();
+        if pier.embark(emigrant).is_err() {
+            log::debug!("No room on the neighbouring piers, emigrant stays home");
+        }
+    }
+    if let Some(immigrant) = pier.disembark() {
+        let i = choose_replacement(candidates, config, rng);
+        log::debug!(
+            "{} has arrived on island {} from island {}, replacing {}",
+            immigrant.name(),
+            config.island_id,
+            immigrant.native_island(),
+            candidates[i].name()
+        );
+        candidates[i] = immigrant;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn graph(topology: Topology, degree: usize, n: usize) -> Vec<Vec<usize>> {
+        let config = MigrationConfig {
+            topology,
+            degree,
+            ..Default::default()
+        };
+        neighbours(&config, n, 42)
+    }
+
+    #[test]
+    fn test_neighbours() {
+        assert_eq!(graph(Topology::Ring, 0, 3), vec![vec![1], vec![2], vec![0]]);
+        assert_eq!(graph(Topology::Ring, 0, 1), vec![Vec::<usize>::new()]);
+        assert_eq!(
+            graph(Topology::Star, 0, 3),
+            vec![vec![1, 2], vec![0], vec![0]]
+        );
+        assert_eq!(
+            graph(Topology::FullyConnected, 0, 3),
+            vec![vec![1, 2], vec![0, 2], vec![0, 1]]
+        );
+        // a grid of 3 rows and 2 columns
+        let torus = graph(Topology::TorusGrid, 0, 6);
+        assert_eq!(torus[0], vec![1, 2, 4]);
+        assert_eq!(torus[4], vec![0, 2, 5]);
+
+        let regular = graph(Topology::RandomRegular, 3, 8);
+        for (i, adjacent) in regular.iter().enumerate() {
+            assert_eq!(adjacent.len(), 3);
+            assert_eq!(regular.iter().filter(|adj| adj.contains(&i)).count(), 3);
+        }
+    }
+}
@@ -1,3 +1,5 @@
+pub mod adaptive_radius;
+pub mod migration;
 pub mod pier;
 pub mod shuffling_heap;
 pub mod trivial_geography;
@@ -1,52 +1,253 @@
+use std::collections::VecDeque;
 use std::sync::atomic::{self, AtomicUsize};
+use std::sync::{Arc, Mutex};
 
-use crossbeam::queue::SegQueue;
+use hashbrown::HashMap;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
 
-pub struct Pier<P> {
+use crate::cluster::{self, Link, Message};
+use crate::configure::Config;
+use crate::evolution::Phenome;
+use crate::observer::{log_record, LogRecord};
+
+/// The queue of immigrants waiting to land on a single island. The queue is
+/// locked while its capacity is checked, so that immigrants arriving at
+/// once can't overfill it.
+struct Dock<P> {
     capacity: usize,
-    count: AtomicUsize,
-    q: SegQueue<P>,
+    q: Mutex<VecDeque<P>>,
 }
 
-impl<P> Pier<P> {
-    pub fn new(capacity: usize) -> Self {
+impl<P> Dock<P> {
+    fn new(capacity: usize) -> Self {
         Self {
             capacity,
-            count: AtomicUsize::new(0),
-            q: SegQueue::new(),
+            q: Mutex::new(VecDeque::with_capacity(capacity)),
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.q.lock().expect("poisoned dock").len()
+    }
+
+    fn push(&self, p: P) -> Result<usize, P> {
+        let mut q = self.q.lock().expect("poisoned dock");
+        if q.len() >= self.capacity {
+            return Err(p);
         }
+        q.push_back(p);
+        Ok(q.len())
+    }
+
+    fn pop(&self) -> Option<P> {
+        self.q.lock().expect("poisoned dock").pop_front()
+    }
+}
+
+/// An island's connection to the others. Emigrants are sent to the docks of
+/// the island's neighbours, in turn, and immigrants are taken from its own.
+/// When the island is one process of a cluster, its neighbours live in other
+/// processes, and migrants travel through the coordinator as JSON.
+pub struct Pier<P> {
+    config: Arc<Config>,
+    neighbours: Vec<usize>,
+    docks: Arc<HashMap<usize, Dock<P>>>,
+    next: AtomicUsize,
+    link: Option<Arc<Link>>,
+    encode: fn(&P) -> serde_json::Result<String>,
+    decode: fn(&str) -> serde_json::Result<P>,
+}
+
+#[derive(Debug)]
+struct MigrationRecord {
+    epoch: usize,
+    from: usize,
+    to: usize,
+    name: String,
+    fitness: Option<f64>,
+}
+
+impl LogRecord for MigrationRecord {
+    fn header(&self) -> String {
+        "epoch,from,to,name,fitness".to_string()
+    }
+
+    fn row(&self) -> String {
+        format!(
+            "{},{},{},{},{}",
+            self.epoch,
+            self.from,
+            self.to,
+            self.name,
+            self.fitness.map(|f| f.to_string()).unwrap_or_default()
+        )
+    }
+}
+
+impl<P: Serialize + DeserializeOwned> Pier<P> {
+    /// Builds a pier for each island, given the islands' configs and the
+    /// neighbours of each, as computed by `migration::neighbours`. Each
+    /// dock has room for one immigrant from each island that sends to it.
+    /// In a cluster, `configs` holds only the config of this process's
+    /// island, while `neighbours` still covers every island.
+    pub fn archipelago(configs: &[Config], neighbours: &[Vec<usize>]) -> Vec<Arc<Self>> {
+        let docks = configs
+            .iter()
+            .map(|config| {
+                let i = config.island_id;
+                let in_degree = neighbours.iter().filter(|adj| adj.contains(&i)).count();
+                (i, Dock::new(in_degree.max(1)))
+            })
+            .collect::<HashMap<usize, Dock<P>>>();
+        let docks = Arc::new(docks);
+        let link = cluster::link();
+        configs
+            .iter()
+            .map(|config| {
+                let adjacent = neighbours[config.island_id].clone();
+                log::info!(
+                    "Island {} sends emigrants to islands {:?}",
+                    config.island_id,
+                    adjacent
+                );
+                Arc::new(Self {
+                    config: Arc::new(config.clone()),
+                    neighbours: adjacent,
+                    docks: docks.clone(),
+                    next: AtomicUsize::new(0),
+                    link: link.clone(),
+                    encode: serde_json::to_string,
+                    decode: |s| serde_json::from_str(s),
+                })
+            })
+            .collect()
+    }
+}
+
+impl<P> Pier<P> {
+    fn dock(&self) -> &Dock<P> {
+        &self.docks[&self.config.island_id]
     }
 
+    /// The number of immigrants waiting to land on this island.
     pub fn len(&self) -> usize {
-        self.count.load(atomic::Ordering::SeqCst)
+        self.dock().len()
     }
 
-    fn incr_count(&self) -> usize {
-        self.count.fetch_add(1, atomic::Ordering::SeqCst)
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
     }
 
-    fn decr_count(&self) -> usize {
-        self.count.fetch_sub(1, atomic::Ordering::SeqCst)
+    pub fn disembark(&self) -> Option<P> {
+        let p = match self.dock().pop() {
+            Some(p) => p,
+            None => self.receive()?,
+        };
+        log::debug!(
+            "Immigrant disembarked onto island {}. {} waiting",
+            self.config.island_id,
+            self.len()
+        );
+        Some(p)
     }
 
-    pub fn embark(&self, emigrant: P) -> Result<(), P> {
-        if self.len() >= self.capacity {
-            log::debug!("Pier at capacity, returning emigrant");
-            return Err(emigrant);
+    /// Takes the next migrant to have arrived from another process.
+    fn receive(&self) -> Option<P> {
+        let json = self.link.as_ref()?.take_migrant()?;
+        match (self.decode)(&json) {
+            Ok(p) => Some(p),
+            Err(e) => {
+                log::error!("Failed to decode migrant: {:?}", e);
+                None
+            }
         }
-        self.q.push(emigrant);
-        let len = self.incr_count();
-        log::debug!("Emigrant embarked onto pier. Holding {}", len + 1);
-        Ok(())
     }
+}
 
-    pub fn disembark(&self) -> Option<P> {
-        if let Ok(p) = self.q.pop() {
-            let len = self.decr_count();
-            log::debug!("Immigrant disembarked from pier. Holding {}", len - 1);
-            Some(p)
-        } else {
-            None
+impl<P: Phenome> Pier<P> {
+    /// Sends the emigrant to the first neighbour, in rotation, with room on
+    /// its dock, logging the migration. Returns the emigrant if there is
+    /// none.
+    pub fn embark(&self, mut emigrant: P) -> Result<(), P> {
+        let n = self.neighbours.len();
+        let start = self.next.fetch_add(1, atomic::Ordering::Relaxed);
+        for k in 0..n {
+            let to = self.neighbours[(start + k) % n];
+            let record = MigrationRecord {
+                epoch: crate::get_epoch_counter(),
+                from: self.config.island_id,
+                to,
+                name: emigrant.name().to_string(),
+                fitness: emigrant.scalar_fitness(self.config.fitness.priority()),
+            };
+            let dock = match self.docks.get(&to) {
+                Some(dock) => dock,
+                None => match self.send(to, &emigrant) {
+                    Ok(()) => {
+                        log_record(record, "migration", &self.config);
+                        return Ok(());
+                    }
+                    Err(e) => {
+                        log::error!("Failed to send emigrant to island {}: {:?}", to, e);
+                        continue;
+                    }
+                },
+            };
+            match dock.push(emigrant) {
+                Ok(waiting) => {
+                    log::debug!(
+                        "Emigrant embarked for island {}, where {} are waiting",
+                        to,
+                        waiting
+                    );
+                    log_record(record, "migration", &self.config);
+                    return Ok(());
+                }
+                Err(e) => emigrant = e,
+            }
         }
+        log::debug!("Piers at capacity, returning emigrant");
+        Err(emigrant)
+    }
+
+    /// Sends the emigrant to an island in another process. Remote docks
+    /// are not bounded: the receiving island takes its immigrants in the
+    /// order they arrive.
+    fn send(&self, to: usize, emigrant: &P) -> Result<(), crate::error::Error> {
+        let link = self.link.as_ref().ok_or_else(|| {
+            crate::error::Error::Misc(format!("Island {} is not in this process", to))
+        })?;
+        link.send(&Message::Migrant {
+            from: self.config.island_id,
+            to,
+            creature: (self.encode)(emigrant)?,
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_dock_capacity() {
+        let dock = Arc::new(Dock::new(3));
+        let handles = (0..16)
+            .map(|i| {
+                let dock = dock.clone();
+                std::thread::spawn(move || dock.push(i).is_ok())
+            })
+            .collect::<Vec<_>>();
+        let accepted = handles
+            .into_iter()
+            .map(|h| h.join().unwrap())
+            .filter(|ok| *ok)
+            .count();
+        assert_eq!(accepted, 3);
+        assert_eq!(dock.len(), 3);
+        assert!(dock.pop().is_some());
+        assert_eq!(dock.push(99), Ok(3));
     }
 }
@@ -41,10 +41,19 @@ impl<P: Hash> TrivialGeography<P> {
         self.radius = radius.min(self.len())
     }
 
+    pub fn radius(&self) -> usize {
+        self.radius
+    }
+
     pub fn len(&self) -> usize {
         self.deme.len() - self.vacancies.len()
     }
 
+    /// Iterates over the occupied cells of the deme.
+    pub fn iter(&self) -> impl Iterator<Item = &P> {
+        self.deme.iter().flatten()
+    }
+
     pub fn extract(&mut self, index: usize) -> Option<P> {
         // let's try to handle empty cells gracefully
         let len = self.deme.len();
@@ -102,13 +111,35 @@ impl<P: Hash> TrivialGeography<P> {
             .collect()
     }
 
+    /// The occupied cells within the radius of a random base. If fewer than
+    /// `n` of those cells are occupied, because earlier combatants have been
+    /// taken from them and not yet returned, the range is widened until it
+    /// holds `n` occupied cells or covers the whole deme.
+    fn get_occupied_range<R: Rng>(&self, n: usize, rng: &mut R) -> Vec<usize> {
+        let len = self.deme.len();
+        let base = rng.gen_range(0, len);
+        let mut range = Vec::with_capacity(self.radius);
+        let mut span = 0;
+        while span < len && (span < self.radius || range.len() < n) {
+            let i = (base + span) % len;
+            if self.deme[i].is_some() {
+                range.push(i);
+            }
+            span += 1;
+        }
+        range
+    }
+
+    /// Takes `n` combatants from the neighbourhood of a random cell. Since
+    /// only occupied cells are drawn from, several tournaments can be drawn
+    /// before any of their combatants are returned.
     pub fn choose_combatants<R: Rng>(&mut self, n: usize, rng: &mut R) -> Vec<P> {
         debug_assert!(
             n < self.radius,
             "don't try to take more creatures than the radius allows"
         );
 
-        let range = self.get_range(rng);
+        let range = self.get_occupied_range(n, rng);
         self.choose_with_range(&range, n, rng)
     }
 
@@ -225,6 +256,55 @@ mod test {
         }
     }
 
+    fn cyclic_span(mut cells: Vec<usize>, len: usize) -> usize {
+        cells.sort_unstable();
+        let widest_gap = cells
+            .iter()
+            .zip(
+                cells
+                    .iter()
+                    .skip(1)
+                    .chain(cells.first().map(|c| c + len).iter()),
+            )
+            .map(|(a, b)| b - a)
+            .max()
+            .unwrap();
+        len - widest_gap + 1
+    }
+
+    #[test]
+    fn test_choose_pipelined_combatants() {
+        let size = 32;
+        let radius = 8;
+        for seed in 0..1000 {
+            let mut geo = TrivialGeography {
+                radius,
+                deme: (0..size).map(Option::Some).collect::<Vec<Option<usize>>>(),
+                vacancies: vec![],
+            };
+            let mut rng = hash_seed_rng(&seed);
+
+            // The first bracket is drawn from a full deme, and so from
+            // within the radius.
+            let first = geo.choose_combatants(7, &mut rng);
+            assert_eq!(first.len(), 7);
+            assert!(cyclic_span(first.clone(), size) <= radius);
+
+            // Later brackets are drawn while earlier ones are still out.
+            let mut drawn = first;
+            for _ in 0..3 {
+                let bracket = geo.choose_combatants(7, &mut rng);
+                assert_eq!(bracket.len(), 7);
+                drawn.extend(bracket);
+            }
+            let count = drawn.len();
+            drawn.sort_unstable();
+            drawn.dedup();
+            assert_eq!(drawn.len(), count, "a creature was drawn twice");
+            assert_eq!(geo.len(), size - count);
+        }
+    }
+
     #[test]
     fn test_get_range() {
         let geo = TrivialGeography {
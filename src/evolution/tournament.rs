@@ -1,4 +1,4 @@
-use std::cmp::{Ordering, PartialOrd};
+use std::cmp::PartialOrd;
 use std::iter;
 use std::sync::Arc;
 
@@ -6,9 +6,13 @@ use rand::Rng;
 use rayon::prelude::*;
 
 use crate::configure::Config;
+use crate::evolution::engine::Engine;
+use crate::evolution::population::adaptive_radius::{digram_diversity, AdaptiveRadius};
+use crate::evolution::population::migration;
 use crate::evolution::population::pier::Pier;
 use crate::evolution::population::trivial_geography::TrivialGeography;
 use crate::evolution::{Genome, Phenome};
+use crate::fitness::sort_by_dominance;
 use crate::observer::Observer;
 use crate::ontogenesis::Develop;
 use crate::util::random::hash_seed_rng;
@@ -20,6 +24,7 @@ pub struct Tournament<E: Develop<P>, P: Phenome + 'static> {
     pub observer: Observer<P>,
     pub evaluator: E,
     pub pier: Arc<Pier<P>>,
+    pub adaptive_radius: Option<AdaptiveRadius>,
 }
 
 impl<E: Develop<P>, P: Phenome + Genome + 'static> Tournament<E, P> {
@@ -39,6 +44,14 @@ impl<E: Develop<P>, P: Phenome + Genome + 'static> Tournament<E, P> {
         population.set_radius(config.tournament.geographic_radius);
         log::debug!("population initialized");
 
+        let adaptive_radius = config
+            .tournament
+            .adaptive_radius
+            .as_ref()
+            .map(|c| AdaptiveRadius::new(c, config.tournament.tournament_size));
+        observer.set_gauge("radius", population.radius() as f64);
+        observer.set_gauge("diversity", digram_diversity(population.iter()));
+
         Self {
             population,
             config,
@@ -46,9 +59,14 @@ impl<E: Develop<P>, P: Phenome + Genome + 'static> Tournament<E, P> {
             observer,
             evaluator,
             pier,
+            adaptive_radius,
         }
     }
 
+    /// Runs `tournament.pipeline_depth` tournaments. The combatants of
+    /// every tournament are developed together, so that the evaluator can
+    /// keep its emulators busy, and then each tournament is concluded in
+    /// turn.
     pub fn evolve(self) -> Self {
         // destruct the Epoch
         let Self {
@@ -56,8 +74,9 @@ impl<E: Develop<P>, P: Phenome + Genome + 'static> Tournament<E, P> {
             observer,
             mut evaluator,
             config,
-            iteration,
+            mut iteration,
             pier,
+            mut adaptive_radius,
         } = self;
         log::debug!(
             "population size in island {}: {}",
@@ -67,25 +86,79 @@ impl<E: Develop<P>, P: Phenome + Genome + 'static> Tournament<E, P> {
 
         let mut rng = hash_seed_rng(&population);
 
-        let combatants: Vec<P> =
-            population.choose_combatants(config.tournament.tournament_size, &mut rng);
+        let brackets: Vec<Vec<P>> = (0..config.tournament.pipeline_depth.max(1))
+            .map(|_| population.choose_combatants(config.tournament.tournament_size, &mut rng))
+            .collect();
+        let bracket_sizes = brackets.iter().map(Vec::len).collect::<Vec<usize>>();
+
+        let mut developed = evaluator
+            .development_pipeline(brackets.into_iter().flatten())
+            .into_iter();
+
+        for size in bracket_sizes {
+            let mut combatants = developed
+                .by_ref()
+                .take(size)
+                .map(|p| evaluator.apply_fitness_function(p))
+                .map(|e| {
+                    observer.observe(e.clone());
+                    e
+                })
+                .collect::<Vec<P>>();
+
+            if let Some(ref mut controller) = adaptive_radius {
+                controller.record(&combatants);
+                if let Some(radius) = controller.adjust(population.radius()) {
+                    if radius != population.radius() {
+                        log::debug!(
+                            "Island {}: diversity is {}, setting radius to {}",
+                            config.island_id,
+                            controller.diversity(),
+                            radius
+                        );
+                    }
+                    population.set_radius(radius);
+                    observer.set_gauge("radius", population.radius() as f64);
+                    observer.set_gauge("diversity", controller.diversity());
+                }
+            }
 
-        let mut combatants = evaluator
-            .development_pipeline(combatants.into_iter())
-            .into_iter()
-            .map(|p| evaluator.apply_fitness_function(p))
-            .map(|e| {
-                observer.observe(e.clone());
-                e
-            })
-            .collect::<Vec<P>>();
+            sort_by_dominance(&mut combatants, |a, b| {
+                a.fitness().partial_cmp(&b.fitness())
+            });
+
+            Self::conclude_tournament(
+                &mut population,
+                combatants,
+                &config,
+                iteration,
+                &pier,
+                &mut rng,
+            );
+            iteration += 1;
+        }
 
-        combatants.sort_by(|a, b| {
-            a.fitness()
-                .partial_cmp(&b.fitness())
-                .unwrap_or(Ordering::Equal)
-        });
+        Self {
+            population,
+            config,
+            iteration,
+            observer,
+            evaluator,
+            pier,
+            adaptive_radius,
+        }
+    }
 
+    /// Culls the losers of a tournament, sorted from best to worst, and
+    /// returns the survivors to the population along with their offspring.
+    fn conclude_tournament<R: Rng>(
+        population: &mut TrivialGeography<P>,
+        mut combatants: Vec<P>,
+        config: &Config,
+        iteration: usize,
+        pier: &Pier<P>,
+        rng: &mut R,
+    ) {
         // kill one off for every offspring to be produced
         for _ in 0..config.tournament.num_offspring {
             let _ = combatants.pop();
@@ -105,34 +178,10 @@ impl<E: Develop<P>, P: Phenome + Genome + 'static> Tournament<E, P> {
             log::info!(
                 "New global epoch. Island #{} epoch is {}",
                 config.island_id,
-                Self::island_epoch(iteration, &config)
+                Self::island_epoch(iteration, config)
             );
         }
-        // NOTE: migration relies on tournaments being at least 1 larger than
-        // the number of parents plus the number of children
-        if survivors.len() > config.tournament.num_parents {
-            let mut migrated = false;
-            if rng.gen_range(0.0, 1.0) < config.tournament.migration_rate {
-                log::debug!("Attempting migration...");
-                let emigrant = survivors.pop().unwrap();
-                if let Err(emigrant) = pier.embark(emigrant) {
-                    log::debug!("Pier full, returning emigrant to population");
-                    survivors.push(emigrant);
-                } else {
-                    migrated = true;
-                }
-            }
-            if !migrated {
-                if let Some(immigrant) = pier.disembark() {
-                    log::debug!(
-                        "{} has arrived from the pier of island {}",
-                        immigrant.name(),
-                        config.island_id
-                    );
-                    survivors.push(immigrant);
-                }
-            }
-        }
+        migration::migrate(&mut survivors, pier, config, rng);
 
         debug_assert!(survivors.len() >= config.tournament.num_parents);
 
@@ -147,7 +196,7 @@ impl<E: Develop<P>, P: Phenome + Genome + 'static> Tournament<E, P> {
 
         let offspring: Vec<P> = iter::repeat(())
             .take(config.tournament.num_offspring)
-            .map(|()| Genome::mate(&parents, &config))
+            .map(|()| Genome::mate(&parents, config))
             .collect::<Vec<_>>();
 
         // return everyone to the population
@@ -157,18 +206,23 @@ impl<E: Develop<P>, P: Phenome + Genome + 'static> Tournament<E, P> {
         for child in offspring.into_iter() {
             population.insert(child).unwrap()
         }
-
-        Self {
-            population,
-            config,
-            iteration: iteration + 1,
-            observer,
-            evaluator,
-            pier,
-        }
     }
 
     fn island_epoch(iteration: usize, config: &Config) -> usize {
         iteration / (config.pop_size / config.tournament.num_offspring)
     }
 }
+
+impl<E: Develop<P>, P: Phenome + Genome + 'static> Engine<E, P> for Tournament<E, P> {
+    fn init(config: &Config, observer: Observer<P>, evaluator: E, pier: Arc<Pier<P>>) -> Self {
+        Self::new(config, observer, evaluator, pier)
+    }
+
+    fn evolve(self) -> Self {
+        Tournament::evolve(self)
+    }
+
+    fn snapshot(&self) -> Vec<P> {
+        self.population.iter().cloned().collect()
+    }
+}
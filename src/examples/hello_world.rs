@@ -9,8 +9,12 @@ use rand::distributions::Alphanumeric;
 use rand::prelude::*;
 use serde::{Deserialize, Serialize};
 
-use crate::configure::Config;
-use crate::evolution::population::pier::Pier;
+use crate::configure::{Config, Selection};
+use crate::evolution::alps::Alps;
+use crate::evolution::engine;
+use crate::evolution::lexicase::Lexicase;
+use crate::evolution::metropolis::Metropolis;
+use crate::evolution::pareto_roulette::Roulette;
 use crate::evolution::{Genome, Phenome};
 use crate::observer::Window;
 use crate::util::count_min_sketch::CountMinSketch;
@@ -268,21 +272,45 @@ fn fitness_function(
     phenome
 }
 
-pub fn run(config: Config) {
+fn prepare(config: &Config) -> (Observer<Genotype>, evaluation::Evaluator) {
     let report_fn = Box::new(report);
     let fitness_fn = Box::new(fitness_function);
-    let observer = Observer::spawn(&config, report_fn);
-    let evaluator = evaluation::Evaluator::spawn(&config, fitness_fn);
-    let pier = Pier::new(4); // FIXME: don't hardcode, make this the number of islands, say
-    let mut world = Tournament::<evaluation::Evaluator, Genotype>::new(
-        &config,
-        observer,
-        evaluator,
-        Arc::new(pier),
-    );
+    let observer = Observer::spawn(config, report_fn);
+    let evaluator = evaluation::Evaluator::spawn(config, fitness_fn);
+    (observer, evaluator)
+}
 
-    while crate::keep_going() {
-        world = world.evolve();
+crate::impl_dominance_ord_for_phenome!(Genotype, GenotypeDominanceOrd);
+
+pub fn run(config: Config) {
+    match config.selection {
+        Selection::Tournament => {
+            engine::launch::<Tournament<evaluation::Evaluator, Genotype>, _, _, _>(
+                &config, prepare,
+            );
+        }
+        Selection::Roulette => {
+            engine::launch::<
+                Roulette<evaluation::Evaluator, Genotype, GenotypeDominanceOrd>,
+                _,
+                _,
+                _,
+            >(&config, prepare);
+        }
+        Selection::Metropolis => {
+            engine::launch::<Metropolis<evaluation::Evaluator, Genotype>, _, _, _>(
+                &config, prepare,
+            );
+        }
+        Selection::Lexicase => {
+            engine::launch::<Lexicase<evaluation::Evaluator, Genotype>, _, _, _>(&config, prepare);
+        }
+        Selection::Alps => {
+            engine::launch::<Alps<evaluation::Evaluator, Genotype>, _, _, _>(&config, prepare);
+        }
+        Selection::MapElites => {
+            unreachable!("MAP-Elites is rejected for this job by Config::from_path")
+        }
     }
 }
 
@@ -1,16 +1,17 @@
 use std::cmp::Ordering;
 use std::fmt::Debug;
 use std::hash::{Hash, Hasher};
-use std::sync::Arc;
 use std::{fmt, iter};
 
 use rand::Rng;
 use serde::{Deserialize, Serialize};
 
 use crate::configure::{ClassificationProblem, Config, Selection};
+use crate::evolution::alps::Alps;
+use crate::evolution::engine;
+use crate::evolution::lexicase::Lexicase;
 use crate::evolution::metropolis::Metropolis;
 use crate::evolution::pareto_roulette::Roulette;
-use crate::evolution::population::pier::Pier;
 use crate::evolution::{tournament::Tournament, Genome, Phenome};
 use crate::fitness::Weighted;
 use crate::observer::{Observer, ReportFn, Window};
@@ -278,7 +279,6 @@ pub struct Creature {
     chromosome_parentage: Vec<usize>,
     chromosome_mutation: Vec<Option<Mutation>>,
     answers: Option<Answer>,
-    #[serde(borrow)]
     pub fitness: Option<Fitness<'static>>,
     tag: u64,
     //crossover_mask: u64,
@@ -705,7 +705,7 @@ mod evaluation {
     }
 }
 
-fn prepare(mut config: Config) -> (Config, Observer<Creature>, evaluation::Evaluator) {
+fn prepare_config(mut config: Config) -> Config {
     let problems = parse_data(&config.data.path);
     assert!(problems.is_some());
     // figure out the number of return registers needed
@@ -734,51 +734,49 @@ fn prepare(mut config: Config) -> (Config, Observer<Creature>, evaluation::Evalu
     config.linear_gp.return_registers = Some(return_registers);
     config.linear_gp.num_registers = Some(num_registers);
     log::info!("Config: {:#?}", config);
+    config
+}
+
+fn prepare(config: &Config) -> (Observer<Creature>, evaluation::Evaluator) {
     let report_fn: ReportFn<_> = Box::new(report);
     let fitness_fn: FitnessFn<Creature, _, _> = Box::new(evaluation::fitness_function);
-    let observer = Observer::spawn(&config, report_fn);
-    let evaluator = evaluation::Evaluator::spawn(&config, fitness_fn);
-    (config, observer, evaluator)
+    let observer = Observer::spawn(config, report_fn);
+    let evaluator = evaluation::Evaluator::spawn(config, fitness_fn);
+    (observer, evaluator)
 }
 
 crate::impl_dominance_ord_for_phenome!(Creature, CreatureDominanceOrd);
 
 pub fn run(config: Config) {
-    //let target_fitness = config.target_fitness;
-    let selection = config.selection;
-    let (config, observer, evaluator) = prepare(config);
+    let config = prepare_config(config);
 
-    match selection {
+    match config.selection {
         Selection::Tournament => {
-            let pier = Pier::new(4); // FIXME: don't hardcode
-            let mut world = Tournament::<evaluation::Evaluator, Creature>::new(
-                &config,
-                observer,
-                evaluator,
-                Arc::new(pier),
+            engine::launch::<Tournament<evaluation::Evaluator, Creature>, _, _, _>(
+                &config, prepare,
             );
-            while crate::keep_going() {
-                world = world.evolve();
-            }
         }
         Selection::Roulette => {
-            let mut world = Roulette::<evaluation::Evaluator, Creature, CreatureDominanceOrd>::new(
-                &config,
-                observer,
-                evaluator,
-                CreatureDominanceOrd,
-            );
-            while crate::keep_going() {
-                world = world.evolve();
-            }
+            engine::launch::<
+                Roulette<evaluation::Evaluator, Creature, CreatureDominanceOrd>,
+                _,
+                _,
+                _,
+            >(&config, prepare);
         }
         Selection::Metropolis => {
-            let mut world =
-                Metropolis::<evaluation::Evaluator, Creature>::new(&config, observer, evaluator);
-            while crate::keep_going() {
-                world = world.evolve();
-            }
+            engine::launch::<Metropolis<evaluation::Evaluator, Creature>, _, _, _>(
+                &config, prepare,
+            );
+        }
+        Selection::Lexicase => {
+            engine::launch::<Lexicase<evaluation::Evaluator, Creature>, _, _, _>(&config, prepare);
+        }
+        Selection::Alps => {
+            engine::launch::<Alps<evaluation::Evaluator, Creature>, _, _, _>(&config, prepare);
+        }
+        Selection::MapElites => {
+            unreachable!("MAP-Elites is rejected for this job by Config::from_path")
         }
-        sel => unimplemented!("{:?} not implemented for {:?}", sel, config.job),
     }
 }
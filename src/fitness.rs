@@ -1,5 +1,5 @@
 use std::cmp::Ordering;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashSet};
 use std::fmt;
 use std::fmt::Debug;
 use std::hash::{Hash, Hasher};
@@ -10,6 +10,8 @@ use itertools::Itertools;
 use serde::export::Formatter;
 use serde::{Deserialize, Serialize};
 
+use crate::util::expression;
+
 pub type FitnessMap<'a> = BTreeMap<&'a str, f64>;
 
 pub trait HasScalar {
@@ -25,9 +27,19 @@ impl HasScalar for Vec<f64> {
 pub trait FitnessScore:
     Sized + PartialEq + Debug + Send + Clone + PartialOrd + Serialize + PartialOrd + HasScalar
 {
+    /// The scores on each fitness case, keyed by the name of the case, as
+    /// used by lexicase selection. Lower is better.
+    fn cases(&self) -> BTreeMap<String, f64>;
 }
 
-impl FitnessScore for Vec<f64> {}
+impl FitnessScore for Vec<f64> {
+    fn cases(&self) -> BTreeMap<String, f64> {
+        self.iter()
+            .enumerate()
+            .map(|(i, v)| (i.to_string(), *v))
+            .collect()
+    }
+}
 
 #[derive(Clone, Serialize, Deserialize)]
 pub struct Pareto<'a>(#[serde(borrow)] BTreeMap<&'a str, f64>);
@@ -97,7 +109,14 @@ pub trait MapFit {
     }
 }
 
-impl FitnessScore for Pareto<'static> {}
+impl FitnessScore for Pareto<'static> {
+    fn cases(&self) -> BTreeMap<String, f64> {
+        self.inner()
+            .iter()
+            .map(|(k, v)| (k.to_string(), *v))
+            .collect()
+    }
+}
 
 impl PartialOrd for Pareto<'static> {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
@@ -278,14 +297,106 @@ impl From<BTreeMap<&'static str, f64>> for ShuffleFit {
     }
 }
 
-impl FitnessScore for ShuffleFit {}
+impl FitnessScore for ShuffleFit {
+    fn cases(&self) -> BTreeMap<String, f64> {
+        self.inner()
+            .iter()
+            .map(|(k, v)| (k.to_string(), *v))
+            .collect()
+    }
+}
+
+/// How two fitness maps are to be compared.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum Comparison {
+    /// By the scalar value of the weighting expression.
+    Weighted,
+    /// By Pareto dominance over the objectives. Some pairs are incomparable.
+    Pareto,
+    /// By each objective in turn, moving on to the next only in case of a tie.
+    Lexicographic,
+}
 
-#[derive(Serialize, Deserialize)]
+impl Default for Comparison {
+    fn default() -> Self {
+        Self::Weighted
+    }
+}
+
+/// A map of named objectives, to be minimized. By default, these are compared
+/// by the scalar value of the weighting expression, but see `Comparison`.
+#[derive(Serialize)]
 pub struct Weighted<'a> {
     weighting: String,
-    #[serde(borrow)]
     pub scores: BTreeMap<&'a str, f64>,
     cached_scalar: Mutex<Option<f64>>,
+    #[serde(skip)]
+    cached_cases: Mutex<Option<BTreeMap<String, f64>>>,
+    #[serde(default)]
+    comparison: Comparison,
+    /// The objectives considered by Pareto and lexicographic comparisons, in
+    /// order of precedence. If empty, every score is considered, in
+    /// alphabetical order.
+    #[serde(default)]
+    objectives: Vec<String>,
+    /// The island on which the scores were earned, for the sake of the
+    /// population statistics available to expressions.
+    #[serde(default)]
+    island: usize,
+    /// Whether the weighting refers to the population statistics, in which
+    /// case its value isn't cached.
+    #[serde(skip)]
+    population_dependent: bool,
+}
+
+/// Interns a factor name, leaking each distinct name once, so that factors
+/// named at runtime can key the scores of a `Weighted`.
+pub fn intern_factor(name: &str) -> &'static str {
+    static NAMES: Mutex<Option<HashSet<&'static str>>> = Mutex::new(None);
+    let mut names = NAMES.lock().expect("poisoned factor names");
+    let names = names.get_or_insert_with(HashSet::new);
+    if let Some(existing) = names.get(name) {
+        return existing;
+    }
+    let leaked: &'static str = Box::leak(name.to_string().into_boxed_str());
+    names.insert(leaked);
+    leaked
+}
+
+/// The serialized form of a `Weighted`, with owned factor names.
+#[derive(Deserialize)]
+struct WeightedRepr {
+    weighting: String,
+    scores: BTreeMap<String, f64>,
+    #[serde(default)]
+    comparison: Comparison,
+    #[serde(default)]
+    objectives: Vec<String>,
+    #[serde(default)]
+    island: usize,
+}
+
+/// Scores can't borrow their factor names from the input if they're to
+/// outlive it, as migrants read from a socket must, so the names are
+/// interned instead.
+impl<'de> Deserialize<'de> for Weighted<'static> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let repr = WeightedRepr::deserialize(deserializer)?;
+        Ok(Self {
+            scores: repr
+                .scores
+                .iter()
+                .map(|(k, v)| (intern_factor(k), *v))
+                .collect(),
+            cached_scalar: Mutex::new(None),
+            cached_cases: Mutex::new(None),
+            comparison: repr.comparison,
+            objectives: repr.objectives,
+            island: repr.island,
+            population_dependent: expression::depends_on_population(&repr.weighting),
+            weighting: repr.weighting,
+        })
+    }
 }
 
 impl PartialEq for Weighted<'_> {
@@ -327,8 +438,13 @@ impl Clone for Weighted<'_> {
     fn clone(&self) -> Self {
         Self {
             cached_scalar: Mutex::new(None),
+            cached_cases: Mutex::new(None),
             weighting: self.weighting.clone(),
             scores: self.scores.clone(),
+            comparison: self.comparison,
+            objectives: self.objectives.clone(),
+            island: self.island,
+            population_dependent: self.population_dependent,
         }
     }
 }
@@ -339,9 +455,44 @@ impl Weighted<'static> {
             weighting: weighting.to_string(),
             scores: FitnessMap::new(),
             cached_scalar: Mutex::new(None),
+            cached_cases: Mutex::new(None),
+            comparison: Comparison::default(),
+            objectives: vec![],
+            island: 0,
+            population_dependent: expression::depends_on_population(weighting),
+        }
+    }
+
+    pub fn set_island(&mut self, island: usize) {
+        self.island = island;
+    }
+
+    pub fn set_comparison(&mut self, comparison: Comparison, objectives: &[String]) {
+        self.comparison = comparison;
+        self.objectives = objectives.to_vec();
+    }
+
+    fn objective_keys<'b>(&'b self, other: &'b Self) -> Vec<&'b str> {
+        if self.objectives.is_empty() {
+            let mut keys = self
+                .scores
+                .keys()
+                .chain(other.scores.keys())
+                .copied()
+                .collect::<Vec<&str>>();
+            keys.sort_unstable();
+            keys.dedup();
+            keys
+        } else {
+            self.objectives.iter().map(String::as_str).collect()
         }
     }
 
+    /// Missing objectives count as the worst possible score.
+    fn objective(&self, key: &str) -> f64 {
+        self.scores.get(key).copied().unwrap_or(f64::MAX)
+    }
+
     fn powf(&self, n: f64) -> Self {
         let mut res = self.clone();
         for v in res.scores.values_mut() {
@@ -378,7 +529,9 @@ impl Weighted<'static> {
             return res;
         } else {
             let res = self.scalar_with_expression(&self.weighting);
-            *cache = Some(res);
+            if !self.population_dependent {
+                *cache = Some(res);
+            }
             res
         }
     }
@@ -387,8 +540,7 @@ impl Weighted<'static> {
         if self.scores.is_empty() {
             return f64::MAX;
         }
-        let mut ns = self.scores.clone();
-        match fasteval::ez_eval(expr, &mut ns) {
+        match expression::evaluate(expr, &self.scores, self.island) {
             Err(e) => panic!(
                 "Failed to evaluate expression {:?} with scores {:?}: {:?}",
                 expr, self.scores, e
@@ -438,11 +590,101 @@ impl HasScalar for Weighted<'static> {
 
 impl PartialOrd for Weighted<'static> {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        self.scalar().partial_cmp(&other.scalar())
+        match self.comparison {
+            Comparison::Weighted => self.scalar().partial_cmp(&other.scalar()),
+            Comparison::Lexicographic => {
+                for key in self.objective_keys(other) {
+                    match self.objective(key).partial_cmp(&other.objective(key)) {
+                        Some(Ordering::Equal) => continue,
+                        ord => return ord,
+                    }
+                }
+                Some(Ordering::Equal)
+            }
+            Comparison::Pareto => {
+                let mut better = false;
+                let mut worse = false;
+                for key in self.objective_keys(other) {
+                    let (a, b) = (self.objective(key), other.objective(key));
+                    if a < b {
+                        better = true;
+                    } else if a > b {
+                        worse = true;
+                    }
+                }
+                match (better, worse) {
+                    (true, false) => Some(Ordering::Less),
+                    (false, true) => Some(Ordering::Greater),
+                    (false, false) => Some(Ordering::Equal),
+                    (true, true) => None,
+                }
+            }
+        }
     }
 }
 
-impl FitnessScore for Weighted<'static> {}
+/// Sorts `items` from best to worst, by the number of other items that compare
+/// as strictly less. Unlike sorting by `partial_cmp` directly, this gives a
+/// consistent order even when some items are incomparable, as under Pareto
+/// dominance, and it agrees with the ordinary sort when they are not.
+pub fn sort_by_dominance<T, F>(items: &mut Vec<T>, compare: F)
+where
+    F: Fn(&T, &T) -> Option<Ordering>,
+{
+    let ranks = items
+        .iter()
+        .map(|item| {
+            items
+                .iter()
+                .filter(|other| compare(other, item) == Some(Ordering::Less))
+                .count()
+        })
+        .collect::<Vec<usize>>();
+    let mut ranked = items.drain(..).zip(ranks).collect::<Vec<(T, usize)>>();
+    ranked.sort_by_key(|(_, rank)| *rank);
+    items.extend(ranked.into_iter().map(|(item, _)| item));
+}
+
+impl FitnessScore for Weighted<'static> {
+    /// If objectives are configured, these are the cases, scored as they
+    /// are under Pareto and lexicographic comparison. Otherwise, each factor
+    /// named in the weighting expression is a case, scored by how much it
+    /// adds to the weighted scalar, so that factors the expression rewards
+    /// are rewarded here too.
+    fn cases(&self) -> BTreeMap<String, f64> {
+        if !self.objectives.is_empty() {
+            return self
+                .objectives
+                .iter()
+                .map(|k| (k.clone(), self.objective(k)))
+                .collect();
+        }
+        let mut cache = self.cached_cases.lock().expect("poisoned");
+        if let Some(cases) = cache.as_ref() {
+            return cases.clone();
+        }
+        // The scalar is computed once, and each factor's contribution is
+        // found by zeroing that factor in a single working copy of the scores.
+        let scalar = self.scalar();
+        let mut without = self.scores.clone();
+        let mut cases = BTreeMap::new();
+        for name in expression::variables(&self.weighting) {
+            let (factor, score) = match self.scores.get_key_value(name.as_str()) {
+                Some((factor, score)) => (*factor, *score),
+                None => continue,
+            };
+            without.insert(factor, 0.0);
+            let contribution = expression::evaluate(&self.weighting, &without, self.island)
+                .map_or(f64::MAX, |rest| scalar - rest);
+            without.insert(factor, score);
+            cases.insert(name, contribution);
+        }
+        if !self.population_dependent {
+            *cache = Some(cases.clone());
+        }
+        cases
+    }
+}
 
 impl MapFit for Weighted<'static> {
     fn inner_mut(&mut self) -> &mut BTreeMap<&'static str, f64> {
@@ -541,6 +783,66 @@ mod test {
         assert_eq!(s_foo, 0.7071067811865476);
         assert_eq!(s_bar, 0.7071067811865476);
     }
+    #[test]
+    fn test_weighted_round_trip() {
+        let mut w = Weighted::new("foo + bar");
+        w.insert("foo", 1.0);
+        w.insert("bar", 2.0);
+        w.set_island(3);
+        let json = serde_json::to_string(&w).unwrap();
+        let w2: Weighted<'static> = serde_json::from_str(&json).unwrap();
+        assert_eq!(w, w2);
+        assert_eq!(w2.island, 3);
+        assert_eq!(w2.scalar(), 3.0);
+    }
+
+    #[test]
+    fn test_weighted_comparisons() {
+        let mut w1 = Weighted::new("foo + bar");
+        w1.insert("foo", 1.0);
+        w1.insert("bar", 3.0);
+        let mut w2 = Weighted::new("foo + bar");
+        w2.insert("foo", 2.0);
+        w2.insert("bar", 1.0);
+        assert_eq!(w1.partial_cmp(&w2), Some(Ordering::Greater));
+
+        w1.set_comparison(Comparison::Lexicographic, &["foo".to_string()]);
+        assert_eq!(w1.partial_cmp(&w2), Some(Ordering::Less));
+
+        w1.set_comparison(Comparison::Pareto, &[]);
+        w2.set_comparison(Comparison::Pareto, &[]);
+        assert_eq!(w1.partial_cmp(&w2), None);
+        let mut w3 = w2.clone();
+        w3.insert("bar", 0.5);
+        assert_eq!(w3.partial_cmp(&w2), Some(Ordering::Less));
+
+        let mut ws = vec![w1.clone(), w2.clone(), w3.clone()];
+        sort_by_dominance(&mut ws, |a, b| a.partial_cmp(b));
+        // w1 and w3 are both non-dominated, while w3 dominates w2.
+        assert_eq!(ws[2], w2);
+    }
+
+    #[test]
+    fn test_weighted_cases() {
+        let mut w = Weighted::new("register_error + 10 * crash_count - gadgets_executed");
+        w.insert("register_error", 2.0);
+        w.insert("crash_count", 1.0);
+        w.insert("gadgets_executed", 5.0);
+        w.insert("unweighted", 1.0);
+        let cases = w.cases();
+        assert_eq!(cases.len(), 3);
+        assert_eq!(cases["register_error"], 2.0);
+        assert_eq!(cases["crash_count"], 10.0);
+        // more gadgets executed is better, and so scores lower
+        assert_eq!(cases["gadgets_executed"], -5.0);
+
+        w.set_comparison(Comparison::Weighted, &["unweighted".to_string()]);
+        assert_eq!(
+            w.cases().into_iter().collect::<Vec<_>>(),
+            vec![("unweighted".to_string(), 1.0)]
+        );
+    }
+
     // #[test]
     // fn test_find_minima() {
     //     fn random_pareto() -> Pareto<'static> {
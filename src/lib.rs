@@ -7,6 +7,7 @@ use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use configure::Config;
 
+pub mod cluster;
 pub mod configure;
 #[allow(dead_code)] // FIXME
 mod disassembler;
@@ -81,6 +82,11 @@ pub fn keep_going() -> bool {
 pub fn stop_everything(island: usize, champion: bool) {
     let prior = KEEP_GOING.swap(false, atomic::Ordering::Relaxed);
     if prior {
+        // In a cluster, the coordinator passes the order along to the other
+        // islands.
+        if let Some(link) = cluster::link() {
+            link.send(&cluster::Message::Stop { island, champion });
+        }
         if champion {
             let msg = format!("Island {} has produced a champion!", island);
             let mut msg = ansi_colors::ColouredStr::new(&msg);
@@ -102,8 +108,14 @@ pub fn get_epoch_counter() -> usize {
     EPOCH_COUNTER.load(atomic::Ordering::Relaxed)
 }
 
+/// In a cluster, the epoch counter is kept by the coordinator, which
+/// broadcasts each new value.
 pub fn increment_epoch_counter() {
-    EPOCH_COUNTER.fetch_add(1, atomic::Ordering::Relaxed);
+    if let Some(link) = cluster::link() {
+        link.send(&cluster::Message::IncrementEpoch);
+    } else {
+        EPOCH_COUNTER.fetch_add(1, atomic::Ordering::Relaxed);
+    }
 }
 
 pub fn limit_threads(threads: usize, config: &mut Config) {
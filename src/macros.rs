@@ -1,7 +1,7 @@
 #[macro_export]
 macro_rules! impl_dominance_ord_for_phenome {
     ($phenome:ty, $ord:ident) => {
-        #[derive(Clone, Debug, Copy)]
+        #[derive(Clone, Debug, Copy, Default)]
         pub struct $ord;
 
         impl ::non_dominated_sort::DominanceOrd<$phenome> for $ord {
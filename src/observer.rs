@@ -1,20 +1,22 @@
 // A Logger needs to asynchronously gather and periodically
 // record information on the evolutionary process.
 
+use std::collections::BTreeMap;
 use std::fmt::Debug;
 use std::fs;
 use std::fs::OpenOptions;
 use std::io::BufWriter;
 use std::io::Write;
+use std::iter;
 use std::path::Path;
 use std::sync::mpsc::{channel, Receiver, Sender};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::thread::{spawn, JoinHandle};
 
 use hashbrown::HashMap;
 
 use crate::configure::Config;
-use crate::evolution::{Genome, Phenome};
+use crate::evolution::{adaptation, Genome, Phenome};
 use crate::util::count_min_sketch::CountMinSketch;
 use crate::util::dump::dump;
 
@@ -43,9 +45,14 @@ fn get_log_filename(name: &str, config: &Config) -> String {
 //         .from_writer(file)
 // }
 
+/// Named values set by the engine, such as the current geographic radius,
+/// which the report function can include in its statistics.
+pub type Gauges = Arc<Mutex<BTreeMap<&'static str, f64>>>;
+
 pub struct Observer<O: Send> {
     pub handle: JoinHandle<()>,
     tx: Sender<O>,
+    gauges: Gauges,
 }
 
 pub type ReportFn<T> = Box<dyn Fn(&Window<T>, usize, &Config) -> () + Sync + Send + 'static>;
@@ -92,11 +99,12 @@ pub struct Window<O: Phenome + 'static> {
     pub champion: Option<O>,
     // priority fitness best
     pub archive: Vec<O>,
+    gauges: Gauges,
     // stat_writers: HashMap<&'static str, Arc<Mutex<csv::Writer<fs::File>>>>,
 }
 
 impl<O: Genome + Phenome + 'static> Window<O> {
-    fn new(report_fn: ReportFn<O>, config: Arc<Config>) -> Self {
+    fn new(report_fn: ReportFn<O>, config: Arc<Config>, gauges: Gauges) -> Self {
         let window_size = config.observer.window_size;
         let report_every = if let Some(n) = config.observer.report_every {
             n
@@ -130,10 +138,16 @@ impl<O: Genome + Phenome + 'static> Window<O> {
             best: None,
             champion: None,
             archive: vec![],
+            gauges,
             // stat_writers,
         }
     }
 
+    /// The latest value of each gauge set through the observer.
+    pub fn gauges(&self) -> BTreeMap<&'static str, f64> {
+        self.gauges.lock().expect("poisoned gauges").clone()
+    }
+
     pub fn is_halting_condition_reached(&self) {
         // This is how you check for an unweighted fitness value.
         // This shows the advantage of having Weighted fitness as
@@ -267,43 +281,24 @@ impl<O: Genome + Phenome + 'static> Window<O> {
     }
 
     fn report(&self) {
+        if self.config.self_adaptation.is_some() && !self.frame.is_empty() {
+            let mean_rate = self
+                .frame
+                .iter()
+                .map(|c| c.mutation_rate(&self.config))
+                .sum::<f64>()
+                / self.frame.len() as f64;
+            self.gauges
+                .lock()
+                .expect("poisoned gauges")
+                .insert("mutation_rate", mean_rate);
+        }
         (self.report_fn)(&self, self.counter, &self.config);
+        adaptation::log_operator_weights(&self.config);
     }
 
     pub fn log_record<S: LogRecord + Debug>(&self, record: S, name: &str) {
-        log::debug!(
-            "Island {}, logging to {}: {:#?}",
-            self.config.island_id,
-            name,
-            record
-        );
-        let filename = get_log_filename(name, &self.config);
-        // check to see if file exists yet
-        let msg = if !Path::exists((&filename).as_ref()) {
-            log::debug!("Creating header for {}", filename);
-            format!("{}\n{}\n", record.header(), record.row())
-        } else {
-            format!("{}\n", record.row())
-        };
-        let fd = OpenOptions::new()
-            .append(true)
-            .create(true)
-            .open(&filename)
-            .expect("Failed to open log file");
-        let mut w = BufWriter::new(fd);
-        write!(w, "{}", msg).expect("Failed to log row");
-
-        // self.stat_writers[name]
-        //     .lock()
-        //     .expect("poisoned lock on window's logger")
-        //     .serialize(record)
-        //     .map_err(|e| log::error!("Error logging record: {:?}", e))
-        //     .expect("Failed to log record!");
-        // self.stat_writers[name]
-        //     .lock()
-        //     .expect("poisoned lock on window's logger")
-        //     .flush()
-        //     .expect("Failed to flush");
+        log_record(record, name, &self.config)
     }
 
     pub fn dump_population(&self) {
@@ -365,18 +360,27 @@ impl<O: 'static + Phenome + Genome> Observer<O> {
         self.tx.send(ob).expect("tx failure");
     }
 
+    pub fn set_gauge(&self, name: &'static str, value: f64) {
+        self.gauges
+            .lock()
+            .expect("poisoned gauges")
+            .insert(name, value);
+    }
+
     pub fn spawn(config: &Config, report_fn: ReportFn<O>) -> Observer<O> {
         let (tx, rx): (Sender<O>, Receiver<O>) = channel();
 
         let config = Arc::new(config.clone());
+        let gauges = Gauges::default();
+        let window_gauges = gauges.clone();
         let handle: JoinHandle<()> = spawn(move || {
-            let mut window: Window<O> = Window::new(report_fn, config.clone());
+            let mut window: Window<O> = Window::new(report_fn, config.clone(), window_gauges);
             for observable in rx {
                 window.insert(observable);
             }
         });
 
-        Observer { handle, tx }
+        Observer { handle, tx, gauges }
     }
 
     // pub fn stop_evolution(&mut self) {
@@ -384,6 +388,72 @@ impl<O: 'static + Phenome + Genome> Observer<O> {
     // }
 }
 
+/// Writes the occupied cells of a MAP-Elites grid, with the priority fitness
+/// of each cell's elite, to a CSV file in the data directory. The file is
+/// overwritten each time, so it always shows the latest state of the grid.
+pub fn dump_heatmap(config: &Config, cells: &[(Vec<usize>, f64)]) {
+    let path = format!("{}/map_elites_heatmap.csv", config.data_directory());
+    let dims = config.map_elites.dimensions.len();
+    let header = config
+        .map_elites
+        .dimensions
+        .iter()
+        .map(|d| format!("{:?}", d.feature))
+        .chain(iter::once("fitness".to_string()))
+        .collect::<Vec<String>>()
+        .join(",");
+    let mut msg = format!("{}\n", header);
+    for (cell, fitness) in cells {
+        debug_assert_eq!(cell.len(), dims);
+        let row = cell
+            .iter()
+            .map(|c| c.to_string())
+            .chain(iter::once(fitness.to_string()))
+            .collect::<Vec<String>>()
+            .join(",");
+        msg.push_str(&row);
+        msg.push('\n');
+    }
+    if let Err(e) = fs::write(&path, msg) {
+        log::error!("Failed to write heatmap to {}: {:?}", path, e);
+    }
+}
+
+/// Appends the record to the island's `<name>_statistics.csv`, writing the
+/// header first if the file is new.
+pub fn log_record<S: LogRecord + Debug>(record: S, name: &str, config: &Config) {
+    log::debug!(
+        "Island {}, logging to {}: {:#?}",
+        config.island_id,
+        name,
+        record
+    );
+    let filename = get_log_filename(name, config);
+    // check to see if file exists yet
+    let msg = if !Path::exists((&filename).as_ref()) {
+        log::debug!("Creating header for {}", filename);
+        format!("{}\n{}\n", record.header(), record.row())
+    } else {
+        format!("{}\n", record.row())
+    };
+    let fd = OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(&filename)
+        .expect("Failed to open log file");
+    let mut w = BufWriter::new(fd);
+    write!(w, "{}", msg).expect("Failed to log row");
+
+    if let Some(link) = crate::cluster::link() {
+        link.send(&crate::cluster::Message::Record {
+            island: config.island_id,
+            name: name.to_string(),
+            header: record.header(),
+            row: record.row(),
+        });
+    }
+}
+
 pub trait LogRecord {
     fn header(&self) -> String;
     fn row(&self) -> String;
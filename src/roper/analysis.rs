@@ -1,3 +1,5 @@
+use std::collections::BTreeMap;
+
 use hashbrown::HashSet;
 use itertools::Itertools;
 use serde::Serialize;
@@ -9,6 +11,7 @@ use crate::evolution::{Genome, Phenome};
 use crate::fitness::{average_weighted, stddev_weighted, Weighted};
 use crate::get_epoch_counter;
 use crate::observer::{LogRecord, Window};
+use crate::util::expression::{publish_population_stats, PopulationStats};
 
 #[derive(Serialize, Clone, Debug)]
 pub struct StatRecord {
@@ -23,6 +26,9 @@ pub struct StatRecord {
     pub emulation_time: f64,
     pub fitness: Weighted<'static>,
     pub stdev_fitness: Option<Weighted<'static>>,
+    /// The engine's gauges, such as the geographic radius, at the time of
+    /// the report.
+    pub gauges: BTreeMap<&'static str, f64>,
 }
 
 impl LogRecord for StatRecord {
@@ -40,6 +46,10 @@ impl LogRecord for StatRecord {
                 s.push_str(&format!(",stdev_{}", factor));
             }
         }
+        for gauge in self.gauges.keys() {
+            s.push_str(",");
+            s.push_str(gauge);
+        }
         s
     }
 
@@ -72,6 +82,58 @@ impl LogRecord for StatRecord {
                 s.push_str(&format!(",{}", stdev_values[i]));
             }
         }
+        for value in self.gauges.values() {
+            s.push_str(&format!(",{}", value));
+        }
+        s
+    }
+}
+
+/// The number of creatures in the observation window native to each island.
+/// The columns are fixed by the number of islands, so that every row matches
+/// the header; creatures from islands beyond that number, if any, are
+/// counted as `other`.
+#[derive(Clone, Debug)]
+pub struct IndigeneityRecord {
+    pub epoch: usize,
+    pub natives: Vec<usize>,
+    pub other: usize,
+}
+
+impl IndigeneityRecord {
+    fn from_window<C: Genome + Phenome + 'static>(window: &Window<C>, num_islands: usize) -> Self {
+        let mut natives = vec![0; num_islands];
+        let mut other = 0;
+        for creature in window.frame.iter() {
+            match natives.get_mut(creature.native_island()) {
+                Some(count) => *count += 1,
+                None => other += 1,
+            }
+        }
+        Self {
+            epoch: get_epoch_counter(),
+            natives,
+            other,
+        }
+    }
+}
+
+impl LogRecord for IndigeneityRecord {
+    fn header(&self) -> String {
+        let mut s = "epoch".to_string();
+        for island in 0..self.natives.len() {
+            s.push_str(&format!(",island_{}", island));
+        }
+        s.push_str(",other");
+        s
+    }
+
+    fn row(&self) -> String {
+        let mut s = self.epoch.to_string();
+        for count in self.natives.iter() {
+            s.push_str(&format!(",{}", count));
+        }
+        s.push_str(&format!(",{}", self.other));
         s
     }
 }
@@ -116,6 +178,7 @@ impl StatRecord {
                 .expect("Missing fitness in specimen")
                 .clone(),
             stdev_fitness: None,
+            gauges: BTreeMap::new(),
         }
     }
 
@@ -203,6 +266,7 @@ impl StatRecord {
             emulation_time,
             fitness: mean_fitness,
             stdev_fitness: Some(stdev_fitness),
+            gauges: window.gauges(),
         }
     }
 }
@@ -219,6 +283,22 @@ where
         record = record,
     );
     window.log_record(record, "mean");
+    window.log_record(
+        IndigeneityRecord::from_window(window, config.num_islands.max(1)),
+        "indigeneity",
+    );
+
+    // Make the window's statistics available to the fitness expressions.
+    publish_population_stats(
+        config.island_id,
+        PopulationStats::from_scores(
+            window
+                .frame
+                .iter()
+                .filter_map(|c| c.fitness())
+                .map(|f| &f.scores),
+        ),
+    );
 
     if let Some(ref champion) = window.champion {
         let champion_record =
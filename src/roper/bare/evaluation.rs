@@ -6,6 +6,7 @@ use crate::configure::ClassificationProblem;
 use crate::emulator::register_pattern::Register;
 use crate::ontogenesis::FitnessFn;
 use crate::roper::Sketches;
+use crate::util::cache::EvaluationCache;
 use crate::{configure::Config, emulator::hatchery::Hatchery, ontogenesis::Develop, util};
 
 use super::*;
@@ -15,6 +16,7 @@ pub struct Evaluator<C: 'static + Cpu<'static>> {
     hatchery: Hatchery<C>,
     sketches: Sketches,
     fitness_fn: Box<FitnessFn<Creature, Sketches, Config>>,
+    cache: EvaluationCache<Profile, Fitness<'static>>,
 }
 
 impl<C: 'static + Cpu<'static>> Evaluator<C> {
@@ -45,11 +47,13 @@ impl<C: 'static + Cpu<'static>> Evaluator<C> {
         );
 
         let sketches = Sketches::new(&config);
+        let cache = EvaluationCache::new(&config);
         Self {
             config: Arc::new(config),
             hatchery,
             sketches,
             fitness_fn: Box::new(fitness_fn),
+            cache,
         }
     }
 }
@@ -71,52 +75,111 @@ fn classification_problem_to_register_map<C: 'static + Cpu<'static>>(
 
 // And refactor the modules a bit.
 impl<'a, C: 'static + Cpu<'static>> Develop<Creature> for Evaluator<C> {
-    fn develop(&self, mut creature: Creature) -> Creature {
-        if creature.profile.is_some() {
-            return creature;
-        }
-        // TODO: implement classification task here.
-        if let Some(ref problems) = self.config.problems {
-            for problem in problems {
-                let reg_map: HashMap<Register<C>, u64> = classification_problem_to_register_map::<C>(
-                    problem,
-                    &self.config.roper.input_registers,
-                );
-                let profile = self
-                    .hatchery
-                    .execute(creature.chromosome().to_vec(), Some(reg_map))
-                    .expect("Failed to evaluate creature");
-                creature.add_profile(profile);
-            }
+    fn develop(&self, creature: Creature) -> Creature {
+        self.development_pipeline(std::iter::once(creature))
+            .pop()
+            .expect("Lost a creature in development")
+    }
+
+    fn apply_fitness_function(&mut self, mut creature: Creature) -> Creature {
+        // A static fitness restored from the cache needn't be recomputed, but
+        // the operators that produced the creature are still credited.
+        if self.cache.caches_fitness() && creature.fitness.is_some() {
+            let fitness = creature.scalar_fitness(self.config.fitness.priority());
+            creature.chromosome.credit_operators(fitness, &self.config);
             return creature;
         }
-        // We could detach the chromosome here (Vec<u64>) and send it
-        // as the payload, instead of the entire creature, but the truth
-        // is that it doesn't really matter. Only the ownership of the
-        // creature is passed, so very little data is actually copied.
-        // Cloning the chromosome, or snipping it off and retaching it,
-        // is probably no less expensive, all things considered.
-        // However, if we start appending arguments to the payload, then
-        // we might want to do this differently.
-        let profile = self
-            .hatchery
-            .execute(creature.chromosome().to_vec(), None)
-            .expect("Failed to evaluate creature");
-        creature.add_profile(profile);
+        let mut creature = (self.fitness_fn)(creature, &mut self.sketches, self.config.clone());
+        let fitness = creature.scalar_fitness(self.config.fitness.priority());
+        creature.chromosome.credit_operators(fitness, &self.config);
+        self.cache
+            .store_fitness(creature.chromosome(), creature.fitness.clone());
         creature
     }
 
-    fn apply_fitness_function(&mut self, creature: Creature) -> Creature {
-        (self.fitness_fn)(creature, &mut self.sketches, self.config.clone())
-    }
-
     fn development_pipeline<I: 'static + Iterator<Item = Creature> + Send>(
         &self,
         inbound: I,
     ) -> Vec<Creature> {
-        inbound
+        // Creatures that have already been developed, or whose evaluations
+        // are cached, keep their places, and the rest are emulated together.
+        let mut slots: Vec<Option<Creature>> = vec![];
+        let mut pending_slots = vec![];
+        let mut pending = vec![];
+        for mut creature in inbound {
+            if creature.profile.is_some() {
+                slots.push(Some(creature));
+                continue;
+            }
+            match self.cache.restore(creature.chromosome()) {
+                Some((profile, fitness)) => {
+                    creature.set_profile(profile);
+                    if let Some(fitness) = fitness {
+                        creature.set_fitness(fitness);
+                    }
+                    slots.push(Some(creature))
+                }
+                None => {
+                    pending_slots.push(slots.len());
+                    slots.push(None);
+                    pending.push(creature);
+                }
+            }
+        }
+        let developed = self.emulate_batch(pending);
+        for (slot, creature) in pending_slots.into_iter().zip(developed.into_iter()) {
+            if let Some(ref profile) = creature.profile {
+                self.cache.store(creature.chromosome(), profile.clone());
+            }
+            slots[slot] = Some(creature);
+        }
+        slots
             .into_iter()
-            .map(|c| self.develop(c))
-            .collect::<Vec<Creature>>()
+            .map(|c| c.expect("Lost a creature in development"))
+            .collect()
+    }
+}
+
+impl<C: 'static + Cpu<'static>> Evaluator<C> {
+    /// Emulates every creature on every classification problem (or once, if
+    /// there are none) in a single batch, so that the hatchery's workers are
+    /// all kept busy.
+    fn emulate_batch(&self, mut creatures: Vec<Creature>) -> Vec<Creature> {
+        if creatures.is_empty() {
+            return creatures;
+        }
+        // TODO: implement classification task here.
+        let register_maps: Vec<Option<HashMap<Register<C>, u64>>> = match self.config.problems {
+            Some(ref problems) => problems
+                .iter()
+                .map(|problem| {
+                    Some(classification_problem_to_register_map::<C>(
+                        problem,
+                        &self.config.roper.input_registers,
+                    ))
+                })
+                .collect(),
+            None => vec![None],
+        };
+        let jobs = creatures
+            .iter()
+            .flat_map(|creature| {
+                register_maps
+                    .iter()
+                    .map(move |args| (creature.chromosome().to_vec(), args.clone()))
+            })
+            .collect::<Vec<_>>();
+        let mut profiles = self
+            .hatchery
+            .execute_batch(jobs)
+            .expect("Failed to evaluate creatures")
+            .into_iter();
+        for creature in creatures.iter_mut() {
+            for _ in 0..register_maps.len() {
+                let profile = profiles.next().expect("Missing profile");
+                creature.add_profile(profile);
+            }
+        }
+        creatures
     }
 }
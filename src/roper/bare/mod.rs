@@ -8,12 +8,12 @@ use rand::{thread_rng, Rng};
 use rand_distr::{Distribution, Standard};
 use serde::{Deserialize, Serialize};
 
-use crate::configure::Config;
+use crate::configure::{BadBytePolicy, Config, CrossoverAlgorithm, GeneExpiry};
 use crate::emulator::loader;
 use crate::emulator::loader::get_static_memory_image;
-use crate::emulator::pack::Pack;
+use crate::emulator::pack::{word_has_bad_bytes, Pack};
 use crate::emulator::profiler::{HasProfile, Profile};
-use crate::evolution::{Genome, LinearChromosome, Mutation, Phenome};
+use crate::evolution::{adaptation, Genome, LinearChromosome, Mutation, Phenome};
 use crate::roper::Fitness;
 use crate::util::architecture::{read_integer, write_integer, Perms};
 use crate::util::random::hash_seed_rng;
@@ -24,7 +24,7 @@ use crate::util::{self, architecture::Endian};
 /// scores to each member of the population.
 pub mod evaluation;
 
-#[derive(Clone, Serialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Creature {
     // pub chromosome: Vec<T>,
     // pub chromosome_parentage: Vec<usize>,
@@ -32,7 +32,6 @@ pub struct Creature {
     pub chromosome: LinearChromosome<u64, WordMutation>,
     pub tag: u64,
     pub profile: Option<Profile>,
-    #[serde(borrow)]
     pub fitness: Option<Fitness<'static>>,
     pub front: Option<usize>,
     pub num_offspring: usize,
@@ -46,6 +45,7 @@ impl HasProfile for Creature {
     }
 
     fn add_profile(&mut self, profile: Profile) {
+        self.chromosome.exercise(0..self.words_consumed(&profile));
         if let Some(ref mut p) = self.profile {
             p.absorb(profile)
         } else {
@@ -54,6 +54,7 @@ impl HasProfile for Creature {
     }
 
     fn set_profile(&mut self, profile: Profile) {
+        self.chromosome.exercise(0..self.words_consumed(&profile));
         self.profile = Some(profile)
     }
 }
@@ -231,6 +232,71 @@ fn try_to_ensure_exec(mut chromosome: Vec<u64>) -> Vec<u64> {
     }
 }
 
+impl Creature {
+    /// Whether each word of the chromosome was executed as a gadget, according
+    /// to the creature's profile. Empty if the creature has not been run.
+    ///
+    /// In each run, the executions of each gadget address are attributed to
+    /// the earliest words holding that address, so that a word repeated later
+    /// in the chromosome isn't credited with executions that happened before
+    /// the chain reached it. Where boundaries were recorded, they count the
+    /// gadgets the run passed through, and so how many words it can have
+    /// reached.
+    fn executed_words(&self, config: &Config) -> Vec<bool> {
+        let profile = match self.profile {
+            Some(ref profile) => profile,
+            None => return vec![],
+        };
+        let words = self.chromosome();
+        let mut executed = vec![false; words.len()];
+        for (run, counts) in profile.gadgets_executed.iter().enumerate() {
+            let mut remaining = counts.clone();
+            let mut reachable = if config.roper.record_boundaries() {
+                profile.boundaries.get(run).map(|boundaries| {
+                    // the first gadget is entered without crossing a boundary
+                    boundaries.iter().filter(|b| !b.syscall).count() + 1
+                })
+            } else {
+                None
+            };
+            for (i, word) in words.iter().enumerate() {
+                if reachable == Some(0) {
+                    break;
+                }
+                if let Some(n) = remaining.get_mut(word).filter(|n| **n > 0) {
+                    *n -= 1;
+                    executed[i] = true;
+                    if let Some(ref mut r) = reachable {
+                        *r -= 1;
+                    }
+                }
+            }
+        }
+        executed
+    }
+
+    /// The number of leading words of the payload consumed when it ran:
+    /// everything up to the last gadget executed, along with the data words
+    /// that follow it, up to the next address of executable code, where
+    /// its return would have taken it.
+    fn words_consumed(&self, profile: &Profile) -> usize {
+        let words = self.chromosome();
+        let last = match words.iter().rposition(|w| profile.times_executed(*w) > 0) {
+            Some(last) => last,
+            None => return 0,
+        };
+        let memory = get_static_memory_image();
+        words[last + 1..]
+            .iter()
+            .position(|w| {
+                memory
+                    .perm_of_addr(*w)
+                    .map_or(false, |p| p.intersects(Perms::EXEC))
+            })
+            .map_or(words.len(), |n| last + 1 + n)
+    }
+}
+
 impl Genome for Creature {
     type Allele = u64;
 
@@ -285,6 +351,9 @@ impl Genome for Creature {
                 parent_names: vec![],
                 name,
                 generation: 0,
+                gene_ages: vec![],
+                mutation_params: None,
+                parent_fitness: None,
             },
             tag,
             profile: None,
@@ -301,7 +370,34 @@ impl Genome for Creature {
             .iter()
             .map(|x| &x.chromosome)
             .collect::<Vec<&LinearChromosome<_, _>>>();
-        let chromosome = LinearChromosome::crossover(&parents, config);
+        let executed: Vec<Vec<bool>> = match config.crossover_algorithm {
+            CrossoverAlgorithm::Homologous | CrossoverAlgorithm::GadgetBoundary => {
+                mates.iter().map(|m| m.executed_words(config)).collect()
+            }
+            _ => vec![],
+        };
+        let mut chromosome =
+            LinearChromosome::crossover_with_execution(&parents, &executed, config);
+        chromosome.parent_fitness = mates
+            .iter()
+            .filter_map(|m| m.scalar_fitness(config.fitness.priority()))
+            .fold(None, |best: Option<f64>, f| {
+                Some(best.map_or(f, |b| b.min(f)))
+            });
+        if let Some(ttl) = config.roper.gene_ttl {
+            let expired = match config.roper.gene_expiry {
+                GeneExpiry::Delete => chromosome.expire(ttl, None::<fn() -> u64>),
+                GeneExpiry::Replace => {
+                    let soup = config.roper.soup.as_ref().expect("No soup?!");
+                    // Seeded, like crossover, so that runs can be reproduced.
+                    let mut rng = hash_seed_rng(&(config.random_seed, &chromosome.chromosome));
+                    chromosome.expire(ttl, Some(|| soup[rng.gen_range(0, soup.len())]))
+                }
+            };
+            if expired > 0 {
+                log::trace!("{} genes expired in {}", expired, chromosome.name);
+            }
+        }
         Self {
             chromosome,
             tag: thread_rng().gen::<u64>(),
@@ -318,6 +414,10 @@ impl Genome for Creature {
         self.chromosome.mutate(config)
     }
 
+    fn mutation_rate(&self, config: &Config) -> f64 {
+        self.chromosome.mutation_rate(config)
+    }
+
     fn incr_num_offspring(&mut self, n: usize) {
         self.num_offspring += n
     }
@@ -330,17 +430,33 @@ pub enum WordMutation {
     AddressAdd,
     AddressSub,
     BitFlip,
+    Repair,
 }
 
 impl Mutation for WordMutation {
     type Allele = u64;
 
-    fn mutate_point(allele: &mut Self::Allele, _config: &Config) -> Self {
+    /// `Repair` is left out, being chosen only under the `Repair` bad byte
+    /// policy.
+    fn operators() -> Vec<Self> {
+        use WordMutation::*;
+        vec![Dereference, Indirection, AddressAdd, AddressSub, BitFlip]
+    }
+
+    fn mutate_point(allele: &mut Self::Allele, config: &Config) -> Self {
         let mut rng = thread_rng();
-        let mutation = rand::random::<WordMutation>();
         let memory = get_static_memory_image();
         let endian = memory.endian;
         let word_size = memory.word_size;
+        let forbidden_bytes = config.roper.forbidden_bytes();
+        // Words containing bad bytes are always repaired, if repair is possible.
+        let mutation = if config.roper.bad_byte_policy == BadBytePolicy::Repair
+            && word_has_bad_bytes(*allele, word_size, endian, forbidden_bytes)
+        {
+            WordMutation::Repair
+        } else {
+            adaptation::choose_operator(config, &mut rng).unwrap_or_else(rand::random)
+        };
         // TODO: add a mutation that picks a random address from the soup
         match mutation {
             WordMutation::Dereference => {
@@ -372,11 +488,55 @@ impl Mutation for WordMutation {
                 let word = *allele ^ (1 << rng.gen_range(0, word_size as u64 * 8));
                 *allele = word;
             }
+            WordMutation::Repair => {
+                if let Some(word) = find_equivalent_gadget(*allele, forbidden_bytes, &mut rng) {
+                    *allele = word;
+                }
+            }
         }
         mutation
     }
 }
 
+/// The greatest number of instructions considered when matching one gadget to another.
+const MAX_GADGET_INSTS: usize = 16;
+
+/// Look for another executable address, free of bad bytes, at which the same
+/// instructions as those at `addr` can be found, up to and including the first
+/// `ret`.
+fn find_equivalent_gadget<R: Rng>(addr: u64, bad_bytes: &[u8], rng: &mut R) -> Option<u64> {
+    let memory = get_static_memory_image();
+    let available = memory.try_dereference(addr, None)?.len();
+    // 15 bytes is the longest an x86 instruction can be.
+    let size = available.min(MAX_GADGET_INSTS * 15);
+    let insts = memory.disassemble(addr, size, Some(MAX_GADGET_INSTS))?;
+    let mut gadget = Vec::new();
+    for inst in insts.iter() {
+        gadget.extend_from_slice(inst.bytes());
+        if inst
+            .mnemonic()
+            .map(|m| m.starts_with("ret"))
+            .unwrap_or(false)
+        {
+            break;
+        }
+    }
+    if gadget.is_empty() {
+        return None;
+    }
+    memory
+        .seek_all_segs_exhaustively(&gadget, None, 0)
+        .into_iter()
+        .filter(|a| {
+            memory
+                .perm_of_addr(*a)
+                .map(|p| p.intersects(Perms::EXEC))
+                .unwrap_or(false)
+        })
+        .filter(|a| !word_has_bad_bytes(*a, memory.word_size, memory.endian, bad_bytes))
+        .choose(rng)
+}
+
 impl Distribution<WordMutation> for Standard {
     fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> WordMutation {
         use WordMutation::*;
@@ -456,3 +616,62 @@ impl Phenome for Creature {
         self.profile.is_some()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use crate::emulator::profiler::Boundary;
+    use crate::emulator::register_pattern::RegisterState;
+    use crate::emulator::stages::Stage;
+
+    use super::*;
+
+    fn creature(words: &[u64], gadgets_executed: &[(u64, usize)], boundaries: usize) -> Creature {
+        let boundary = Boundary {
+            registers: RegisterState(Default::default()),
+            memory: None,
+            syscall: false,
+        };
+        let profile = Profile {
+            gadgets_executed: vec![gadgets_executed.iter().copied().collect()],
+            boundaries: vec![vec![boundary; boundaries]],
+            ..Default::default()
+        };
+        Creature {
+            chromosome: LinearChromosome {
+                chromosome: words.to_vec(),
+                mutations: vec![None; words.len()],
+                parentage: vec![],
+                parent_names: vec![],
+                name: "test".to_string(),
+                generation: 0,
+                gene_ages: vec![],
+                mutation_params: None,
+                parent_fitness: None,
+            },
+            tag: 0,
+            profile: Some(profile),
+            fitness: None,
+            front: None,
+            num_offspring: 0,
+            native_island: 0,
+            description: None,
+        }
+    }
+
+    #[test]
+    fn test_executed_words() {
+        let mut config = Config::default();
+        // 0x10 was executed once, so only its first occurrence is credited
+        let c = creature(&[0x10, 1, 0x20, 0x10, 0x30], &[(0x10, 1), (0x20, 1)], 0);
+        assert_eq!(
+            c.executed_words(&config),
+            vec![true, false, true, false, false]
+        );
+
+        // with boundaries recorded, the run reached no further than the
+        // second gadget, however often the gadgets ran
+        config.roper.parsed_stages = vec![Stage::Memory(b"sh".to_vec())];
+        let c = creature(&[0x10, 0x20, 0x10], &[(0x10, 2), (0x20, 1)], 1);
+        assert_eq!(c.executed_words(&config), vec![true, true, false]);
+    }
+}
@@ -1,26 +1,355 @@
-use std::sync::Arc;
+use std::cell::RefCell;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, RwLock};
 
 use hashbrown::HashSet;
 
-use crate::configure::Config;
+use crate::configure::{BadBytePolicy, BehaviourFeature, Config, ReachTargetConfig};
 use crate::emulator::loader::get_static_memory_image;
-use crate::emulator::profiler::HasProfile;
+use crate::emulator::profiler::{HasProfile, Profile};
+use crate::emulator::stages::Stage;
+use crate::error::Error;
 use crate::evolution::{Genome, Phenome};
-use crate::fitness::Weighted;
+use crate::fitness::{intern_factor, Weighted};
 use crate::ontogenesis::FitnessFn;
+use crate::roper::novelty::Behaviour;
 use crate::roper::Sketches;
+use crate::util::count_min_sketch::CountMinSketch;
 use crate::util::entropy::Entropy;
 
-pub fn just_novelty_ff<C>(mut creature: C, sketch: &mut Sketches, config: Arc<Config>) -> C
-where
-    C: HasProfile + Genome + Phenome<Fitness = Weighted<'static>> + Sized,
-{
-    if let Some(ref profile) = creature.profile() {
+/// The parts of the emulation profile that are only recorded on request,
+/// because they slow the emulator down.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProfileFeature {
+    /// Sets `roper.record_memory_writes`.
+    MemoryWrites,
+    /// Sets `roper.record_basic_blocks`.
+    BasicBlocks,
+}
+
+/// What a fitness function gets to see of a creature: the profile of its
+/// execution, and the frequency of its genetic material.
+///
+/// The subject records each thing in a sketch only the first time it is
+/// asked after, so that a creature scored by several fitness functions at
+/// once isn't counted several times over. The same goes for a single
+/// function: a thing that recurs within the profile is recorded once.
+pub struct Subject<'a> {
+    pub profile: &'a Profile,
+    record_genes: &'a dyn Fn(&mut CountMinSketch),
+    query_genes: &'a dyn Fn(&CountMinSketch) -> f64,
+    /// Each sketch, by address, with the hash of each thing recorded in it,
+    /// or `None` for the creature's digrams.
+    recorded: RefCell<HashSet<(usize, Option<u64>)>>,
+}
+
+impl<'a> Subject<'a> {
+    pub fn new(
+        profile: &'a Profile,
+        record_genes: &'a dyn Fn(&mut CountMinSketch),
+        query_genes: &'a dyn Fn(&CountMinSketch) -> f64,
+    ) -> Self {
+        Self {
+            profile,
+            record_genes,
+            query_genes,
+            recorded: RefCell::new(HashSet::new()),
+        }
+    }
+
+    /// Whether this is the first time the thing has been recorded in the
+    /// sketch for this subject.
+    fn first_record(&self, sketch: &CountMinSketch, thing: Option<u64>) -> bool {
+        let sketch = sketch as *const CountMinSketch as usize;
+        self.recorded.borrow_mut().insert((sketch, thing))
+    }
+
+    /// Records the thing in the sketch, if it hasn't been already, and
+    /// returns its frequency.
+    pub fn frequency<T: Hash>(&self, sketch: &mut CountMinSketch, thing: T) -> f64 {
+        let mut hasher = fnv::FnvHasher::default();
+        thing.hash(&mut hasher);
+        if self.first_record(sketch, Some(hasher.finish())) {
+            sketch.insert(&thing);
+        }
+        sketch.query(&thing)
+    }
+
+    /// Records the creature's digrams in the sketch, if they haven't been
+    /// already, and returns their frequency, scaled by the creature's length.
+    pub fn genetic_frequency(&self, sketch: &mut CountMinSketch) -> f64 {
+        if self.first_record(sketch, None) {
+            (self.record_genes)(sketch);
+        }
+        (self.query_genes)(sketch)
+    }
+}
+
+/// A task for ROPER to evolve solutions to. Implementations can be added to
+/// the registry with `register_fitness_function`, and selected by name with
+/// `fitness.function` in the config, alone or alongside others.
+pub trait FitnessFunction: Send + Sync {
+    /// The factors that `score` may emit, against which the weighting and
+    /// priority expressions are checked.
+    fn factors(&self, config: &Config) -> Vec<&'static str>;
+
+    /// The profile features that `score` relies on, given the rest of the
+    /// config. These are switched on in the config before the emulators are
+    /// started.
+    fn requirements(&self, _config: &Config) -> Vec<ProfileFeature> {
+        vec![]
+    }
+
+    /// Checks that the config provides whatever task the function needs.
+    /// This is done at startup, so that `score` can rely on it.
+    fn validate(&self, _config: &Config) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Scores the subject. Returning `None` leaves the creature unscored.
+    fn score(
+        &self,
+        subject: &Subject<'_>,
+        sketch: &mut Sketches,
+        config: &Config,
+    ) -> Option<Weighted<'static>>;
+}
+
+/// Fitness functions registered at runtime. These take precedence over the
+/// built-in functions of the same name.
+static REGISTRY: RwLock<Vec<(String, Arc<dyn FitnessFunction>)>> = RwLock::new(Vec::new());
+
+const BUILTIN_FITNESS_FUNCTIONS: [&str; 9] = [
+    "register_pattern",
+    "register_conjunction",
+    "register_entropy",
+    "code_coverage",
+    "memory_pattern",
+    "just_novelty",
+    "staged",
+    "string_pointer",
+    "reach_target",
+];
+
+/// Makes a fitness function available under the given name. This must be
+/// done before the config is read, so that its expressions can be checked
+/// against the function's factors.
+pub fn register_fitness_function<F: FitnessFunction + 'static>(name: &str, function: F) {
+    let mut registry = REGISTRY
+        .write()
+        .expect("poisoned fitness function registry");
+    registry.retain(|(n, _)| n != name);
+    registry.push((name.to_string(), Arc::new(function)));
+}
+
+pub fn lookup_fitness_function(name: &str) -> Option<Arc<dyn FitnessFunction>> {
+    let registered = REGISTRY
+        .read()
+        .expect("poisoned fitness function registry")
+        .iter()
+        .find(|(n, _)| n == name)
+        .map(|(_, f)| f.clone());
+    registered.or_else(|| builtin_fitness_function(name))
+}
+
+fn builtin_fitness_function(name: &str) -> Option<Arc<dyn FitnessFunction>> {
+    let ff: Arc<dyn FitnessFunction> = match name {
+        "register_pattern" => Arc::new(RegisterPatternFitness),
+        "register_conjunction" => Arc::new(RegisterConjunctionFitness),
+        "register_entropy" => Arc::new(RegisterEntropyFitness),
+        "code_coverage" => Arc::new(CodeCoverageFitness),
+        "memory_pattern" => Arc::new(MemoryPatternFitness),
+        "just_novelty" => Arc::new(JustNoveltyFitness),
+        "staged" => Arc::new(StagedFitness),
+        "string_pointer" => Arc::new(StringPointerFitness),
+        "reach_target" => Arc::new(ReachTargetFitness),
+        _ => return None,
+    };
+    Some(ff)
+}
+
+/// The names of every available fitness function, built-in or registered.
+pub fn fitness_function_names() -> Vec<String> {
+    let mut names = BUILTIN_FITNESS_FUNCTIONS
+        .iter()
+        .map(|s| s.to_string())
+        .collect::<Vec<String>>();
+    for (name, _) in REGISTRY
+        .read()
+        .expect("poisoned fitness function registry")
+        .iter()
+    {
+        if !names.contains(name) {
+            names.push(name.clone())
+        }
+    }
+    names
+}
+
+/// Switches on the recording of whatever profile features the configured
+/// fitness functions require.
+pub fn enable_requirements(config: &mut Config) {
+    let ff = match configured_fitness_function(config) {
+        Ok(ff) => ff,
+        Err(_) => return,
+    };
+    let mut requirements = ff.requirements(config);
+    // Novelty is assessed whichever fitness function is in use.
+    if let Some(ref novelty) = config.novelty {
+        for feature in novelty.behaviour.iter() {
+            let requirement = match feature {
+                BehaviourFeature::Registers => continue,
+                BehaviourFeature::MemoryWrites => ProfileFeature::MemoryWrites,
+                BehaviourFeature::Blocks => ProfileFeature::BasicBlocks,
+            };
+            if !requirements.contains(&requirement) {
+                requirements.push(requirement)
+            }
+        }
+    }
+    for requirement in requirements {
+        let flag = match requirement {
+            ProfileFeature::MemoryWrites => &mut config.roper.record_memory_writes,
+            ProfileFeature::BasicBlocks => &mut config.roper.record_basic_blocks,
+        };
+        if !*flag {
+            log::info!(
+                "Enabling {:?} recording, required by the fitness function {}",
+                requirement,
+                config.fitness.function
+            );
+            *flag = true;
+        }
+    }
+}
+
+/// The fitness function, or combination of fitness functions, named by
+/// `fitness.function` in the config.
+pub fn configured_fitness_function(config: &Config) -> Result<Arc<dyn FitnessFunction>, Error> {
+    let mut components = config.fitness.function.components();
+    let lookup = |name: &str| {
+        lookup_fitness_function(name).ok_or_else(|| {
+            Error::Parsing(format!(
+                "No such fitness function as {}. Try one of: {}",
+                name,
+                fitness_function_names().join(", ")
+            ))
+        })
+    };
+    match components.len() {
+        0 => Err(Error::Parsing("No fitness function given".into())),
+        1 if components[0].prefix.is_none() => lookup(&components[0].name),
+        _ => {
+            let parts = components
+                .drain(..)
+                .map(|c| lookup(&c.name).map(|ff| (c.prefix, ff)))
+                .collect::<Result<Vec<_>, Error>>()?;
+            Ok(Arc::new(CompositeFitness { parts }))
+        }
+    }
+}
+
+/// Prefixed factor names are made at runtime, but the keys of a `Weighted`
+/// must be `'static`, so each distinct name is interned.
+fn prefixed_factor(prefix: &str, factor: &str) -> &'static str {
+    intern_factor(&format!("{}_{}", prefix, factor))
+}
+
+/// The number of runs, and so of cases, in each creature's profile. Push
+/// creatures are run once per register pattern, and bare creatures once per
+/// classification problem, or once if there are none.
+fn number_of_cases(config: &Config) -> usize {
+    if config.roper.use_push {
+        config.roper.register_patterns().len()
+    } else {
+        config.problems.as_ref().map_or(1, Vec::len)
+    }
+}
+
+/// Several fitness functions, scored one after another, with their factors
+/// merged into a single `Weighted`. The parts share the subject, so that
+/// what they record in the sketches is recorded only once.
+pub struct CompositeFitness {
+    parts: Vec<(Option<String>, Arc<dyn FitnessFunction>)>,
+}
+
+impl FitnessFunction for CompositeFitness {
+    fn factors(&self, config: &Config) -> Vec<&'static str> {
+        self.parts
+            .iter()
+            .flat_map(|(prefix, ff)| {
+                ff.factors(config)
+                    .into_iter()
+                    .map(move |factor| match prefix {
+                        Some(prefix) => prefixed_factor(prefix, factor),
+                        None => factor,
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    fn requirements(&self, config: &Config) -> Vec<ProfileFeature> {
+        let mut requirements = vec![];
+        for (_, ff) in self.parts.iter() {
+            for requirement in ff.requirements(config) {
+                if !requirements.contains(&requirement) {
+                    requirements.push(requirement)
+                }
+            }
+        }
+        requirements
+    }
+
+    fn validate(&self, config: &Config) -> Result<(), Error> {
+        for (_, ff) in self.parts.iter() {
+            ff.validate(config)?;
+        }
+        Ok(())
+    }
+
+    fn score(
+        &self,
+        subject: &Subject<'_>,
+        sketch: &mut Sketches,
+        config: &Config,
+    ) -> Option<Weighted<'static>> {
+        let mut fitness: Option<Weighted<'static>> = None;
+        for (prefix, ff) in self.parts.iter() {
+            if let Some(part) = ff.score(subject, sketch, config) {
+                let merged =
+                    fitness.get_or_insert_with(|| Weighted::new(&config.fitness.weighting));
+                for (factor, score) in part.scores.into_iter() {
+                    let factor = match prefix {
+                        Some(prefix) => prefixed_factor(prefix, factor),
+                        None => factor,
+                    };
+                    merged.insert(factor, score);
+                }
+            }
+        }
+        fitness
+    }
+}
+
+pub struct JustNoveltyFitness;
+
+impl FitnessFunction for JustNoveltyFitness {
+    fn factors(&self, _config: &Config) -> Vec<&'static str> {
+        vec!["register_freq", "gadgets_executed"]
+    }
+
+    fn score(
+        &self,
+        subject: &Subject<'_>,
+        sketch: &mut Sketches,
+        config: &Config,
+    ) -> Option<Weighted<'static>> {
+        let profile = subject.profile;
         let mut scores = vec![];
         for reg_state in &profile.registers {
             for (reg, vals) in reg_state.0.iter() {
-                sketch.register_error.insert((reg, vals));
-                scores.push(sketch.register_error.query((reg, vals)));
+                scores.push(subject.frequency(&mut sketch.register_error, (reg, vals)));
             }
         }
         let register_freq = stats::mean(scores.into_iter());
@@ -28,23 +357,37 @@ where
         fitness.insert("register_freq", register_freq);
         let gadgets_executed = profile.gadgets_executed.len();
         fitness.insert("gadgets_executed", gadgets_executed as f64);
-        creature.set_fitness(fitness);
+        Some(fitness)
     }
-
-    creature
 }
 
 // TODO: I'm in the middle of the somewhat tedious process of refactoring
 // the code so that it handles batches of problems, and not single problems.
 // As it stands, I think the code is in an inconsistent state. First thing on
 // Monday morning, we'll get this sorted out.
-pub fn register_pattern_ff<C>(mut creature: C, sketch: &mut Sketches, config: Arc<Config>) -> C
-where
-    C: HasProfile + Genome + Phenome<Fitness = Weighted<'static>> + Sized,
-{
-    // measure fitness
-    // for now, let's just handle the register pattern task
-    if let Some(ref profile) = creature.profile() {
+pub struct RegisterPatternFitness;
+
+impl FitnessFunction for RegisterPatternFitness {
+    fn factors(&self, _config: &Config) -> Vec<&'static str> {
+        vec![
+            "register_error",
+            "register_freq",
+            "crash_count",
+            "ret_count",
+            "genetic_freq",
+            "constancy_penalty",
+        ]
+    }
+
+    fn score(
+        &self,
+        subject: &Subject<'_>,
+        sketch: &mut Sketches,
+        config: &Config,
+    ) -> Option<Weighted<'static>> {
+        // measure fitness
+        // for now, let's just handle the register pattern task
+        let profile = subject.profile;
         let number_of_cases = profile.registers.len();
         let mut fitness = Weighted::new(&config.fitness.weighting);
         // If the specimen doesn't report the right number of register states, then
@@ -56,11 +399,13 @@ where
                 number_of_cases,
                 config.roper.register_patterns().len()
             );
-            creature.set_fitness(fitness);
-            return creature;
+            return Some(fitness);
         }
         for (idx, pattern) in config.roper.register_patterns().iter().enumerate() {
-            let register_error = pattern.distance_from_register_state(&profile.registers[idx]);
+            let register_error = pattern.distance_from_register_state_with_memory(
+                &profile.registers[idx],
+                profile.memory_writes.get(idx),
+            );
             let mut weighted_fitness = Weighted::new(&config.fitness.weighting);
             weighted_fitness.insert_or_add("register_error", register_error);
 
@@ -69,10 +414,7 @@ where
                 pattern
                     .incorrect_register_states(&profile.registers[idx])
                     .iter()
-                    .map(|goof| {
-                        sketch.register_error.insert(goof);
-                        sketch.register_error.query(goof)
-                    }),
+                    .map(|goof| subject.frequency(&mut sketch.register_error, goof)),
             );
 
             weighted_fitness.insert_or_add("register_freq", register_freq);
@@ -81,11 +423,10 @@ where
             let crashes = profile.cpu_errors.iter().filter_map(|x| *x).count();
             weighted_fitness.insert_or_add("crash_count", crashes as f64);
 
-            let ret_count = profile.ret_counts[idx];
+            let ret_count = profile.gadget_transitions(idx, config.roper.chain_mode);
             weighted_fitness.insert_or_add("ret_count", ret_count as f64);
 
-            creature.record_genetic_frequency(&mut sketch.genetic);
-            let gen_freq = creature.query_genetic_frequency(&sketch.genetic);
+            let gen_freq = subject.genetic_frequency(&mut sketch.genetic);
             weighted_fitness.scores.insert("genetic_freq", gen_freq);
 
             fitness = weighted_fitness + fitness;
@@ -99,134 +440,202 @@ where
             (profile.registers.len() - regs.len()) as f64,
         );
         log::debug!("Setting creature fitness to {:#?}", fitness);
-        creature.set_fitness(fitness);
+        Some(fitness)
     }
-    creature
 }
 
-pub fn register_entropy_ff<C>(mut creature: C, sketch: &mut Sketches, config: Arc<Config>) -> C
-where
-    C: HasProfile + Genome + Phenome<Fitness = Weighted<'static>> + Sized,
-{
-    if let Some(ref profile) = creature.profile() {
-        if let Some(registers) = profile.registers.last() {
-            let just_regs = registers.0.values().map(|v| v[0]).collect::<Vec<u64>>();
-            let entropy = just_regs.entropy();
-            let mut weighted_fitness = Weighted::new(&config.fitness.weighting);
-            weighted_fitness.insert("register_entropy", entropy);
-            log::debug!("registers = {:x?}\n1/entropy = {}", just_regs, entropy);
+pub struct RegisterEntropyFitness;
 
-            sketch.register_error.insert(&just_regs);
-            let reg_freq = sketch.register_error.query(&just_regs);
-            weighted_fitness.insert("register_freq", reg_freq);
+impl FitnessFunction for RegisterEntropyFitness {
+    fn factors(&self, _config: &Config) -> Vec<&'static str> {
+        vec!["register_entropy", "register_freq", "gadgets_executed"]
+    }
 
-            weighted_fitness.insert("gadgets_executed", profile.gadgets_executed.len() as f64);
+    fn score(
+        &self,
+        subject: &Subject<'_>,
+        sketch: &mut Sketches,
+        config: &Config,
+    ) -> Option<Weighted<'static>> {
+        let profile = subject.profile;
+        let registers = profile.registers.last()?;
+        let just_regs = registers.0.values().map(|v| v[0]).collect::<Vec<u64>>();
+        let entropy = just_regs.entropy();
+        let mut weighted_fitness = Weighted::new(&config.fitness.weighting);
+        weighted_fitness.insert("register_entropy", entropy);
+        log::debug!("registers = {:x?}\n1/entropy = {}", just_regs, entropy);
 
-            creature.set_fitness(weighted_fitness);
-        }
+        let reg_freq = subject.frequency(&mut sketch.register_error, &just_regs);
+        weighted_fitness.insert("register_freq", reg_freq);
+
+        weighted_fitness.insert("gadgets_executed", profile.gadgets_executed.len() as f64);
+
+        Some(weighted_fitness)
     }
-    creature
 }
 
 // FIXME: This needs to be brought in line with the new profile format
 // in particular, we need to iterate through the runs in the profile.
-pub fn register_conjunction_ff<C>(mut creature: C, sketch: &mut Sketches, config: Arc<Config>) -> C
-where
-    C: HasProfile + Genome + Phenome<Fitness = Weighted<'static>> + Sized,
-{
-    if let Some(ref profile) = creature.profile() {
-        if let Some(registers) = profile.registers.last() {
-            let word_size = get_static_memory_image().word_size * 8;
-            let mut conj = registers.0.values().fold(!0_u64, |a, b| a & b[0]);
-            let mask = match word_size {
-                64 => 0x0000_0000_0000_0000,
-                32 => 0xFFFF_FFFF_0000_0000,
-                16 => 0xFFFF_FFFF_FFFF_0000,
-                _ => unreachable!("not a size"),
-            };
-            conj |= mask;
-            let score = conj.count_zeros() as f64;
-            // ignore bits outside of the register's word size
-            debug_assert!(score <= word_size as f64);
-            let mut weighted_fitness = Weighted::new(&config.fitness.weighting);
-            weighted_fitness.insert("zeroes", score);
-            weighted_fitness.insert("gadgets_executed", profile.gadgets_executed.len() as f64);
+pub struct RegisterConjunctionFitness;
 
-            sketch.register_error.insert(registers);
-            let reg_freq = sketch.register_error.query(registers);
-            weighted_fitness.insert("register_freq", reg_freq);
+impl FitnessFunction for RegisterConjunctionFitness {
+    fn factors(&self, _config: &Config) -> Vec<&'static str> {
+        vec!["zeroes", "gadgets_executed", "register_freq"]
+    }
 
-            creature.set_fitness(weighted_fitness);
-        }
+    fn score(
+        &self,
+        subject: &Subject<'_>,
+        sketch: &mut Sketches,
+        config: &Config,
+    ) -> Option<Weighted<'static>> {
+        let profile = subject.profile;
+        let registers = profile.registers.last()?;
+        let word_size = get_static_memory_image().word_size * 8;
+        let mut conj = registers.0.values().fold(!0_u64, |a, b| a & b[0]);
+        let mask = match word_size {
+            64 => 0x0000_0000_0000_0000,
+            32 => 0xFFFF_FFFF_0000_0000,
+            16 => 0xFFFF_FFFF_FFFF_0000,
+            _ => unreachable!("not a size"),
+        };
+        conj |= mask;
+        let score = conj.count_zeros() as f64;
+        // ignore bits outside of the register's word size
+        debug_assert!(score <= word_size as f64);
+        let mut weighted_fitness = Weighted::new(&config.fitness.weighting);
+        weighted_fitness.insert("zeroes", score);
+        weighted_fitness.insert("gadgets_executed", profile.gadgets_executed.len() as f64);
+
+        let reg_freq = subject.frequency(&mut sketch.register_error, registers);
+        weighted_fitness.insert("register_freq", reg_freq);
+
+        Some(weighted_fitness)
     }
-    creature
 }
 
-pub fn memory_pattern_ff<C>(mut creature: C, sketch: &mut Sketches, config: Arc<Config>) -> C
-where
-    C: HasProfile + Genome + Phenome<Fitness = Weighted<'static>> + Sized,
-{
-    static SUBPATTERN_LABELS: [&str; 5] = [
-        "subpattern_1",
-        "subpattern_2",
-        "subpattern_3",
-        "subpattern_4",
-        "subpattern_5",
-    ];
-    // TODO: iterate through the different cases here, as above
-    if let Some(profile) = creature.profile() {
-        let mut fitness = Weighted::new(&config.fitness.weighting);
+/// Counts of the memory pattern's first few prefixes, kept for the sake of
+/// existing weighting expressions. Longer patterns are better served by
+/// `prefix_error` and `substring_error`.
+static SUBPATTERN_LABELS: [&str; 5] = [
+    "subpattern_1",
+    "subpattern_2",
+    "subpattern_3",
+    "subpattern_4",
+    "subpattern_5",
+];
 
-        let pattern = config
-            .roper
-            .memory_pattern
-            .as_ref()
-            .expect("No memory pattern provided");
+pub struct MemoryPatternFitness;
 
-        for i in 1..pattern.len() + 1 {
-            let sub_pattern = &pattern[0..i];
-            let occurrences = profile
-                .memory_writes
-                .iter()
-                .map(|data| data.find_seq(sub_pattern).len())
-                .sum::<usize>() as f64;
-            fitness.insert_or_add(SUBPATTERN_LABELS[i - 1], occurrences);
+impl FitnessFunction for MemoryPatternFitness {
+    fn factors(&self, _config: &Config) -> Vec<&'static str> {
+        let mut factors = vec!["prefix_error", "substring_error"];
+        factors.extend_from_slice(&SUBPATTERN_LABELS);
+        factors.extend_from_slice(&["num_writes", "ret_count", "memory_freq", "genetic_freq"]);
+        factors
+    }
+
+    fn requirements(&self, _config: &Config) -> Vec<ProfileFeature> {
+        vec![ProfileFeature::MemoryWrites]
+    }
+
+    /// A single pattern applies to every case. Otherwise, as with the
+    /// register patterns, there should be one pattern per case.
+    fn validate(&self, config: &Config) -> Result<(), Error> {
+        let patterns = config.roper.memory_patterns().len();
+        if patterns == 0 {
+            return Err(Error::MissingKey(
+                "roper.memory_pattern or roper.memory_pattern_file, for the memory_pattern \
+                 fitness function"
+                    .into(),
+            ));
         }
+        let cases = number_of_cases(config);
+        if patterns != 1 && patterns != cases {
+            return Err(Error::Parsing(format!(
+                "There are {} memory patterns, but each creature is run on {} cases. \
+                 Give either one pattern, or one per case.",
+                patterns, cases
+            )));
+        }
+        Ok(())
+    }
 
-        sketch.memory_writes.insert(&profile.memory_writes);
-        let memory_freq = sketch.memory_writes.query(&profile.memory_writes);
+    fn score(
+        &self,
+        subject: &Subject<'_>,
+        sketch: &mut Sketches,
+        config: &Config,
+    ) -> Option<Weighted<'static>> {
+        let profile = subject.profile;
+        let number_of_cases = profile.memory_writes.len();
+        let mut fitness = Weighted::new(&config.fitness.weighting);
 
-        fitness.insert_or_add("memory_freq", memory_freq);
+        // The pattern count was checked by `validate`, so a mismatch here
+        // means that the creature's execution was cut short.
+        let patterns = config.roper.memory_patterns();
+        if patterns.len() != 1 && number_of_cases != patterns.len() {
+            log::error!(
+                "Creature has {} memory write records! Expecting {}!",
+                number_of_cases,
+                patterns.len()
+            );
+            return Some(fitness);
+        }
+        if number_of_cases == 0 {
+            return Some(fitness);
+        }
 
-        let num_mem_writes = profile
-            .memory_writes
-            .iter()
-            .map(|data| data.len())
-            .sum::<usize>() as f64;
+        for (idx, data) in profile.memory_writes.iter().enumerate() {
+            let pattern = &patterns[idx % patterns.len()];
 
-        let ret_count = profile.ret_counts[0] as f64;
+            fitness.insert_or_add("prefix_error", pattern.prefix_error(data) as f64);
+            fitness.insert_or_add("substring_error", pattern.substring_error(data) as f64);
 
-        creature.record_genetic_frequency(&mut sketch.genetic);
-        let genetic_freq = creature.query_genetic_frequency(&sketch.genetic);
+            for (i, label) in SUBPATTERN_LABELS
+                .iter()
+                .enumerate()
+                .take(pattern.bytes.len())
+            {
+                let occurrences = data.find_seq(&pattern.bytes[0..=i]).len();
+                fitness.insert_or_add(*label, occurrences as f64);
+            }
 
-        fitness.insert_or_add("num_writes", num_mem_writes);
-        fitness.insert_or_add("ret_count", ret_count);
-        fitness.insert_or_add("genetic_freq", genetic_freq);
+            fitness.insert_or_add("num_writes", data.len() as f64);
 
-        creature.set_fitness(fitness);
+            let ret_count = profile.gadget_transitions(idx, config.roper.chain_mode);
+            fitness.insert_or_add("ret_count", ret_count as f64);
+        }
+        fitness.scale_by(number_of_cases as f64);
+
+        let memory_freq = subject.frequency(&mut sketch.memory_writes, &profile.memory_writes);
+        fitness.insert("memory_freq", memory_freq);
+
+        let genetic_freq = subject.genetic_frequency(&mut sketch.genetic);
+        fitness.insert("genetic_freq", genetic_freq);
+
+        Some(fitness)
     }
-    creature
 }
 
-pub fn code_coverage_ff<C>(mut creature: C, sketch: &mut Sketches, config: Arc<Config>) -> C
-where
-    C: HasProfile + Genome + Phenome<Fitness = Weighted<'static>> + Sized,
-{
-    // if creature.fitness().is_some() {
-    //     return creature;
-    // }
+pub struct CodeCoverageFitness;
 
-    if let Some(ref profile) = creature.profile() {
+impl FitnessFunction for CodeCoverageFitness {
+    fn factors(&self, _config: &Config) -> Vec<&'static str> {
+        vec!["code_coverage", "code_freq", "ret_count"]
+    }
+
+    fn requirements(&self, _config: &Config) -> Vec<ProfileFeature> {
+        vec![ProfileFeature::BasicBlocks]
+    }
+
+    fn score(
+        &self,
+        subject: &Subject<'_>,
+        sketch: &mut Sketches,
+        config: &Config,
+    ) -> Option<Weighted<'static>> {
+        let profile = subject.profile;
         let mut addresses_visited = HashSet::new();
         // TODO: optimize this, maybe parallelize
         profile.execution_trace_iter().for_each(|path| {
@@ -238,8 +647,7 @@ where
         });
         let mut freq_score = 0.0;
         for addr in addresses_visited.iter() {
-            sketch.addresses_visited.insert(*addr);
-            freq_score += sketch.addresses_visited.query(*addr);
+            freq_score += subject.frequency(&mut sketch.addresses_visited, *addr);
         }
         let num_addr_visit = addresses_visited.len() as f64;
         let avg_freq = if num_addr_visit < 1.0 {
@@ -255,26 +663,496 @@ where
         fitness.insert("code_coverage", code_coverage);
         fitness.insert("code_freq", avg_freq);
 
-        let gadgets_executed = profile.ret_counts.iter().sum::<usize>();
+        let gadgets_executed = profile.total_gadget_transitions(config.roper.chain_mode);
         fitness.insert("ret_count", gadgets_executed as f64);
 
-        creature.set_fitness(fitness);
+        Some(fitness)
     }
+}
 
-    creature
+/// The pointer error assigned when no part of the string was written at all.
+const NO_STRING_POINTER_ERROR: f64 = 64.0;
+
+/// Scores the creature on how much of the target string it wrote to memory
+/// (`string_error`, the number of bytes missing from the longest prefix
+/// found), and on how close the target register comes to pointing at it
+/// (`pointer_error`, the log of the distance between the two addresses).
+pub struct StringPointerFitness;
+
+impl FitnessFunction for StringPointerFitness {
+    fn factors(&self, _config: &Config) -> Vec<&'static str> {
+        vec![
+            "string_error",
+            "pointer_error",
+            "ret_count",
+            "crash_count",
+            "memory_freq",
+        ]
+    }
+
+    fn requirements(&self, _config: &Config) -> Vec<ProfileFeature> {
+        vec![ProfileFeature::MemoryWrites]
+    }
+
+    fn validate(&self, config: &Config) -> Result<(), Error> {
+        if config.roper.string_pointer.is_none() {
+            return Err(Error::MissingKey(
+                "roper.string_pointer, for the string_pointer fitness function".into(),
+            ));
+        }
+        Ok(())
+    }
+
+    fn score(
+        &self,
+        subject: &Subject<'_>,
+        sketch: &mut Sketches,
+        config: &Config,
+    ) -> Option<Weighted<'static>> {
+        let task = config.roper.string_pointer.as_ref()?;
+        let target = task.string.as_bytes();
+
+        let profile = subject.profile;
+        let number_of_cases = profile.registers.len();
+        let mut fitness = Weighted::new(&config.fitness.weighting);
+        if number_of_cases == 0 {
+            return Some(fitness);
+        }
+        for (idx, registers) in profile.registers.iter().enumerate() {
+            let found = profile
+                .memory_writes
+                .get(idx)
+                .and_then(|mem| mem.longest_prefix(target));
+            let written = found.map_or(0, |(_, len)| len);
+            fitness.insert_or_add("string_error", (target.len() - written) as f64);
+
+            let reg_val = registers.0.get(&task.register).map(|v| v[0]);
+            let pointer_error = match (found, reg_val) {
+                (Some((addr, _)), Some(val)) => {
+                    let distance = if addr > val { addr - val } else { val - addr };
+                    ((distance as f64) + 1.0).log2()
+                }
+                _ => NO_STRING_POINTER_ERROR,
+            };
+            fitness.insert_or_add("pointer_error", pointer_error);
+
+            let ret_count = profile.gadget_transitions(idx, config.roper.chain_mode);
+            fitness.insert_or_add("ret_count", ret_count as f64);
+        }
+        let crashes = profile.cpu_errors.iter().filter_map(|x| *x).count();
+        fitness.insert_or_add("crash_count", crashes as f64);
+        fitness.scale_by(number_of_cases as f64);
+
+        let memory_freq = subject.frequency(&mut sketch.memory_writes, &profile.memory_writes);
+        fitness.insert("memory_freq", memory_freq);
+
+        Some(fitness)
+    }
+}
+
+/// Scores the creature on how far it gets through the ordered stages in the
+/// stage file, and on how close it came to satisfying the next one.
+pub struct StagedFitness;
+
+impl FitnessFunction for StagedFitness {
+    fn factors(&self, _config: &Config) -> Vec<&'static str> {
+        vec![
+            "stages_remaining",
+            "stage_error",
+            "ret_count",
+            "crash_count",
+            "genetic_freq",
+        ]
+    }
+
+    /// Memory stages are tested against the memory written by each
+    /// boundary, which is only logged when memory writes are recorded.
+    fn requirements(&self, config: &Config) -> Vec<ProfileFeature> {
+        if config.roper.stages().iter().any(Stage::needs_memory) {
+            vec![ProfileFeature::MemoryWrites]
+        } else {
+            vec![]
+        }
+    }
+
+    fn score(
+        &self,
+        subject: &Subject<'_>,
+        sketch: &mut Sketches,
+        config: &Config,
+    ) -> Option<Weighted<'static>> {
+        let profile = subject.profile;
+        let stages = config.roper.stages();
+        let number_of_cases = profile.stage_progress.len();
+        let mut fitness = Weighted::new(&config.fitness.weighting);
+        if stages.is_empty() || number_of_cases == 0 {
+            log::error!(
+                "Staged fitness needs both stages ({}) and boundary snapshots ({})",
+                stages.len(),
+                number_of_cases
+            );
+            return Some(fitness);
+        }
+        for (idx, progress) in profile.stage_progress.iter().enumerate() {
+            fitness.insert_or_add(
+                "stages_remaining",
+                (stages.len() - progress.completed()) as f64,
+            );
+            fitness.insert_or_add("stage_error", progress.error);
+
+            let ret_count = profile.gadget_transitions(idx, config.roper.chain_mode);
+            fitness.insert_or_add("ret_count", ret_count as f64);
+        }
+        let crashes = profile.cpu_errors.iter().filter_map(|x| *x).count();
+        fitness.insert_or_add("crash_count", crashes as f64);
+        fitness.scale_by(number_of_cases as f64);
+
+        let gen_freq = subject.genetic_frequency(&mut sketch.genetic);
+        fitness.insert("genetic_freq", gen_freq);
+
+        Some(fitness)
+    }
+}
+
+/// Scores the creature on how close its execution came, in the control flow
+/// graph, to the target address (`target_distance`), and on how often it
+/// missed the target altogether (`target_missed`). If a register pattern is
+/// given, the register state at the moment the target was reached is
+/// compared with it (`register_error`).
+pub struct ReachTargetFitness;
+
+impl FitnessFunction for ReachTargetFitness {
+    fn factors(&self, config: &Config) -> Vec<&'static str> {
+        let mut factors = vec![
+            "target_distance",
+            "target_missed",
+            "ret_count",
+            "crash_count",
+        ];
+        // The patterns themselves are only parsed once the binary is
+        // loaded, after the expressions have been checked.
+        if config.roper.register_pattern_file.is_some() {
+            factors.push("register_error");
+        }
+        factors
+    }
+
+    fn requirements(&self, _config: &Config) -> Vec<ProfileFeature> {
+        vec![ProfileFeature::BasicBlocks]
+    }
+
+    fn validate(&self, config: &Config) -> Result<(), Error> {
+        match config.roper.reach_target {
+            Some(ReachTargetConfig {
+                address: None,
+                symbol: None,
+            }) => Err(Error::MissingKey(
+                "roper.reach_target.address or roper.reach_target.symbol".into(),
+            )),
+            Some(_) => Ok(()),
+            None => Err(Error::MissingKey(
+                "roper.reach_target, for the reach_target fitness function".into(),
+            )),
+        }
+    }
+
+    fn score(
+        &self,
+        subject: &Subject<'_>,
+        _sketch: &mut Sketches,
+        config: &Config,
+    ) -> Option<Weighted<'static>> {
+        let distances = config
+            .roper
+            .target_distances()
+            .expect("The target distances should have been computed by roper::run");
+        let patterns = config.roper.register_patterns();
+
+        let profile = subject.profile;
+        let number_of_cases = profile.paths.len();
+        let mut fitness = Weighted::new(&config.fitness.weighting);
+        if number_of_cases == 0 {
+            return Some(fitness);
+        }
+        for (idx, path) in profile.paths.iter().enumerate() {
+            let reached = profile.targets_reached.get(idx).cloned().unwrap_or(false);
+            let distance = if reached {
+                0
+            } else {
+                distances.min_distance(path)
+            };
+            fitness.insert_or_add("target_distance", distance as f64);
+            fitness.insert_or_add("target_missed", if reached { 0.0 } else { 1.0 });
+
+            if !patterns.is_empty() {
+                if let Some(registers) = profile.registers.get(idx) {
+                    let pattern = &patterns[idx % patterns.len()];
+                    let register_error = pattern.distance_from_register_state_with_memory(
+                        registers,
+                        profile.memory_writes.get(idx),
+                    );
+                    fitness.insert_or_add("register_error", register_error);
+                }
+            }
+
+            let ret_count = profile.gadget_transitions(idx, config.roper.chain_mode);
+            fitness.insert_or_add("ret_count", ret_count as f64);
+        }
+        let crashes = profile.cpu_errors.iter().filter_map(|x| *x).count();
+        fitness.insert_or_add("crash_count", crashes as f64);
+        fitness.scale_by(number_of_cases as f64);
+
+        Some(fitness)
+    }
 }
 
-pub fn get_fitness_function<C>(name: &str) -> FitnessFn<C, Sketches, Config>
+/// Wraps the configured fitness function for use by the evaluator.
+pub fn get_fitness_function<C>(config: &Config) -> Result<FitnessFn<C, Sketches, Config>, Error>
 where
     C: HasProfile + Genome + Phenome<Fitness = Weighted<'static>> + Sized + 'static,
 {
-    match name {
-        "register_pattern" => Box::new(register_pattern_ff),
-        "register_conjunction" => Box::new(register_conjunction_ff),
-        "register_entropy" => Box::new(register_entropy_ff),
-        "code_coverage" => Box::new(code_coverage_ff),
-        "memory_pattern" => Box::new(memory_pattern_ff),
-        "just_novelty" => Box::new(just_novelty_ff),
-        s => unimplemented!("No such fitness function as {}", s),
+    let ff = configured_fitness_function(config)?;
+    Ok(Box::new(move |mut creature: C, sketch, config| {
+        let fitness = creature.profile().and_then(|profile| {
+            let record_genes =
+                |genetic: &mut CountMinSketch| creature.record_genetic_frequency(genetic);
+            let query_genes = |genetic: &CountMinSketch| creature.query_genetic_frequency(genetic);
+            let subject = Subject::new(profile, &record_genes, &query_genes);
+            ff.score(&subject, sketch, &config)
+        });
+        if let Some(fitness) = fitness {
+            creature.set_fitness(fitness);
+        }
+        finalize_fitness(creature, sketch, &config)
+    }))
+}
+
+/// The factors that the configured fitness function may emit, for the sake of
+/// validating the weighting and priority expressions. It is an error for two
+/// of the functions in a list to emit the same factor, unless one of them is
+/// given a prefix.
+pub fn fitness_factors(config: &Config) -> Result<Vec<&'static str>, Error> {
+    let mut factors = configured_fitness_function(config)?.factors(config);
+    if config.novelty.is_some() {
+        factors.push("novelty");
+    }
+    if config.roper.bad_byte_policy != BadBytePolicy::Substitute {
+        factors.push("bad_bytes");
+    }
+    let mut seen = HashSet::new();
+    if let Some(dup) = factors.iter().find(|f| !seen.insert(**f)) {
+        return Err(Error::Parsing(format!(
+            "The factor {} is emitted more than once by the fitness function {}. \
+             Give the functions prefixes, as in \"mem:memory_pattern\"",
+            dup, config.fitness.function
+        )));
+    }
+    Ok(factors)
+}
+
+/// Adds the factors that apply regardless of which fitness function is in use.
+fn finalize_fitness<C>(mut creature: C, sketch: &mut Sketches, config: &Config) -> C
+where
+    C: HasProfile + Genome + Phenome<Fitness = Weighted<'static>> + Sized,
+{
+    if let Some(fitness) = creature.fitness() {
+        let mut fitness = fitness.clone();
+        fitness.set_comparison(config.fitness.comparison, &config.fitness.objectives);
+        fitness.set_island(config.island_id);
+        creature.set_fitness(fitness);
+    }
+    if config.novelty.is_some() {
+        // The archive grows with every assessment, so this is done once per
+        // creature, whichever fitness function is in use.
+        let behaviour = creature
+            .profile()
+            .map(|p| Behaviour::from_profile(p, sketch.novelty.features()));
+        if let (Some(behaviour), Some(fitness)) = (behaviour, creature.fitness()) {
+            let mut fitness = fitness.clone();
+            fitness.insert("novelty", sketch.novelty.assess(creature.tag(), behaviour));
+            creature.set_fitness(fitness);
+        }
+    }
+    match config.roper.bad_byte_policy {
+        BadBytePolicy::Penalize | BadBytePolicy::Repair => {
+            // Average over the runs, so that the factor doesn't scale with the number of cases.
+            let bad_bytes = creature.profile().map(|p| {
+                let runs = p.bad_byte_counts.len().max(1);
+                p.bad_byte_counts.iter().sum::<usize>() as f64 / runs as f64
+            });
+            if let (Some(bad_bytes), Some(fitness)) = (bad_bytes, creature.fitness()) {
+                let mut fitness = fitness.clone();
+                fitness.insert("bad_bytes", bad_bytes);
+                creature.set_fitness(fitness);
+            }
+        }
+        BadBytePolicy::Substitute => {}
+    }
+    creature
+}
+
+#[cfg(test)]
+mod test {
+    use crate::configure::{FitnessFunctionList, NoveltyConfig};
+
+    use super::*;
+
+    struct Constant;
+
+    impl FitnessFunction for Constant {
+        fn factors(&self, _config: &Config) -> Vec<&'static str> {
+            vec!["constant"]
+        }
+
+        fn requirements(&self, _config: &Config) -> Vec<ProfileFeature> {
+            vec![ProfileFeature::BasicBlocks]
+        }
+
+        fn score(
+            &self,
+            _subject: &Subject<'_>,
+            _sketch: &mut Sketches,
+            config: &Config,
+        ) -> Option<Weighted<'static>> {
+            let mut fitness = Weighted::new(&config.fitness.weighting);
+            fitness.insert("constant", 1.0);
+            Some(fitness)
+        }
+    }
+
+    #[test]
+    fn test_memory_pattern_validation() {
+        use crate::configure::ClassificationProblem;
+        use crate::emulator::memory_pattern::MemoryPattern;
+
+        let mut config = Config::default();
+        assert!(MemoryPatternFitness.validate(&config).is_err());
+
+        // a single pattern applies to any number of cases
+        config.roper.parsed_memory_patterns = vec![MemoryPattern::from(vec![1, 2])];
+        assert!(MemoryPatternFitness.validate(&config).is_ok());
+
+        // otherwise there must be one per case
+        config.roper.parsed_memory_patterns = vec![
+            MemoryPattern::from(vec![1, 2]),
+            MemoryPattern::from(vec![3, 4]),
+        ];
+        assert!(MemoryPatternFitness.validate(&config).is_err());
+        let problem = ClassificationProblem {
+            input: vec![],
+            output: 0,
+            tag: 0,
+        };
+        config.problems = Some(vec![problem.clone(), problem]);
+        assert!(MemoryPatternFitness.validate(&config).is_ok());
+    }
+
+    #[test]
+    fn test_registry() {
+        register_fitness_function("constant", Constant);
+        assert!(fitness_function_names().contains(&"constant".to_string()));
+        assert!(lookup_fitness_function("no_such_function").is_none());
+
+        let mut config = Config::default();
+        config.fitness.function = "constant".into();
+        assert_eq!(fitness_factors(&config).ok(), Some(vec!["constant"]));
+        assert!(!config.roper.record_basic_blocks);
+        enable_requirements(&mut config);
+        assert!(config.roper.record_basic_blocks);
+
+        config.fitness.function = "string_pointer".into();
+        enable_requirements(&mut config);
+        assert!(config.roper.record_memory_writes);
+    }
+
+    #[test]
+    fn test_composite_factors() {
+        register_fitness_function("constant", Constant);
+        let mut config = Config::default();
+        config.fitness.function =
+            FitnessFunctionList::Many(vec!["constant".to_string(), "c:constant".to_string()]);
+        assert_eq!(
+            fitness_factors(&config).ok(),
+            Some(vec!["constant", "c_constant"])
+        );
+
+        config.fitness.function =
+            FitnessFunctionList::Many(vec!["constant".to_string(), "constant".to_string()]);
+        assert!(fitness_factors(&config).is_err());
+    }
+
+    #[test]
+    fn test_subject_records_once() {
+        let mut config = Config::default();
+        config.pop_size = 100;
+        let profile = Profile::default();
+        let record_genes = |genetic: &mut CountMinSketch| genetic.insert("genes");
+        let query_genes = |genetic: &CountMinSketch| genetic.query("genes");
+        let subject = Subject::new(&profile, &record_genes, &query_genes);
+        let mut sketch = Sketches::new(&config);
+        sketch.memory_writes.insert("someone else's writes");
+        sketch.register_error.insert("someone else's writes");
+        sketch.genetic.insert("someone else's genes");
+
+        // as when two fitness functions in a list ask after the same things,
+        // or one meets the same thing twice in a profile
+        for _ in 0..2 {
+            assert_eq!(subject.frequency(&mut sketch.memory_writes, "writes"), 0.5);
+            assert_eq!(subject.genetic_frequency(&mut sketch.genetic), 0.5);
+        }
+        // but the same thing is recorded separately in each sketch
+        assert_eq!(subject.frequency(&mut sketch.register_error, "writes"), 0.5);
+    }
+
+    #[test]
+    fn test_reach_target_validation() {
+        let mut config = Config::default();
+        config.fitness.function = "reach_target".into();
+        let ff = configured_fitness_function(&config).unwrap();
+        assert!(ff.validate(&config).is_err());
+
+        config.roper.reach_target = Some(ReachTargetConfig {
+            address: None,
+            symbol: Some("target".to_string()),
+        });
+        assert!(ff.validate(&config).is_ok());
+
+        // The register patterns aren't parsed until the binary is loaded,
+        // but the weighting may refer to them all the same.
+        config.fitness.weighting = "target_distance + (10 * target_missed) + register_error".into();
+        let factors = fitness_factors(&config).unwrap();
+        assert!(config.fitness.validate_expressions(&factors).is_err());
+        config.roper.register_pattern_file = Some("patterns.txt".to_string());
+        let factors = fitness_factors(&config).unwrap();
+        assert!(config.fitness.validate_expressions(&factors).is_ok());
+    }
+
+    #[test]
+    fn test_enable_requirements() {
+        let mut config = Config::default();
+        config.fitness.function = "reach_target".into();
+        config.novelty = Some(NoveltyConfig {
+            behaviour: vec![BehaviourFeature::Registers, BehaviourFeature::MemoryWrites],
+            ..Default::default()
+        });
+        enable_requirements(&mut config);
+        assert!(config.roper.record_basic_blocks);
+        assert!(config.roper.record_memory_writes);
+    }
+
+    #[test]
+    fn test_staged_requirements() {
+        let mut config = Config::default();
+        config.fitness.function = "staged".into();
+        config.roper.parsed_stages = vec![Stage::Syscall(None)];
+        enable_requirements(&mut config);
+        assert!(!config.roper.record_memory_writes);
+
+        config
+            .roper
+            .parsed_stages
+            .push(Stage::Memory(b"/bin/sh".to_vec()));
+        enable_requirements(&mut config);
+        assert!(config.roper.record_memory_writes);
     }
 }
@@ -2,24 +2,26 @@ use std::fs::File;
 use std::hash::Hash;
 use std::hash::Hasher;
 use std::io::{BufRead, BufReader};
-use std::sync::Arc;
-use std::thread::spawn;
 
 use non_dominated_sort::DominanceOrd;
-use rand::Rng;
 use unicorn::Cpu;
 
-use crate::configure::{Config, Selection};
+use crate::configure::{BadBytePolicy, Config, Selection};
+use crate::emulator::pack::word_has_bad_bytes;
+use crate::emulator::register_pattern::ValueKind;
 use crate::error::Error;
+use crate::evolution::alps::Alps;
+use crate::evolution::engine;
+use crate::evolution::lexicase::Lexicase;
+use crate::evolution::map_elites::MapElites;
 use crate::evolution::metropolis::Metropolis;
 use crate::evolution::pareto_roulette::Roulette;
-use crate::evolution::population::pier::Pier;
 use crate::fitness::Weighted;
 use crate::observer::Observer;
 use crate::ontogenesis::FitnessFn;
+use crate::roper::novelty::NoveltyArchive;
 use crate::util::architecture::Perms;
 use crate::util::count_min_sketch::CountMinSketch;
-use crate::util::random::hash_seed_rng;
 use crate::{
     emulator::loader,
     evolution::{tournament::Tournament, Phenome},
@@ -30,8 +32,9 @@ use crate::{
 mod analysis;
 
 /// Generic fitness functions, which can be used for either push or bare
-/// mode ROPER.
-mod fitness_functions;
+/// mode ROPER, along with the registry through which other crates can
+/// supply their own.
+pub mod fitness_functions;
 
 /// The `creature` module contains the implementation of the `Genome` and `Phenome`
 /// traits associated with `roper` mode.
@@ -40,6 +43,9 @@ pub mod bare;
 /// A ROPER-specific implementation of Spector's PUSH VM.
 pub mod push;
 
+/// Behaviour descriptors and the archive used for novelty search.
+pub mod novelty;
+
 /// load binary before calling this function
 pub fn init_soup(config: &mut Config) -> Result<(), Error> {
     let mut soup = Vec::new();
@@ -48,6 +54,7 @@ pub fn init_soup(config: &mut Config) -> Result<(), Error> {
         pattern
             .0
             .values()
+            .filter(|w| w.kind == ValueKind::Exact)
             .for_each(|w| w.vals.iter().for_each(|word| soup.push(*word)))
     }
     if let Some(gadget_file) = config.roper.gadget_file.as_ref() {
@@ -76,6 +83,19 @@ pub fn init_soup(config: &mut Config) -> Result<(), Error> {
             soup.push(addr)
         }
     }
+    if config.roper.bad_byte_policy != BadBytePolicy::Substitute {
+        // Under the other policies, bad bytes reach the payload, so there's no sense
+        // in seeding the population with them.
+        let memory = loader::get_static_memory_image();
+        let forbidden_bytes = config.roper.forbidden_bytes();
+        let before = soup.len();
+        soup.retain(|w| !word_has_bad_bytes(*w, memory.word_size, memory.endian, forbidden_bytes));
+        log::info!(
+            "Removed {} of {} words containing bad bytes from the soup",
+            before - soup.len(),
+            before
+        );
+    }
     config.roper.soup = Some(soup);
     Ok(())
 }
@@ -85,6 +105,7 @@ pub struct Sketches {
     pub memory_writes: CountMinSketch,
     pub genetic: CountMinSketch,
     pub addresses_visited: CountMinSketch,
+    pub novelty: NoveltyArchive,
 }
 
 impl Sketches {
@@ -94,6 +115,7 @@ impl Sketches {
             memory_writes: CountMinSketch::new(config),
             addresses_visited: CountMinSketch::new(config),
             genetic: CountMinSketch::new(config),
+            novelty: NoveltyArchive::new(config),
         }
     }
 }
@@ -104,7 +126,8 @@ fn prepare_bare<C: 'static + Cpu<'static>>(
     config: &Config,
 ) -> (Observer<bare::Creature>, bare::evaluation::Evaluator<C>) {
     let fitness_function: FitnessFn<bare::Creature, Sketches, Config> =
-        fitness_functions::get_fitness_function(&config.fitness.function);
+        fitness_functions::get_fitness_function(&config)
+            .expect("The fitness function should have been validated by Config::from_path");
     let observer = Observer::spawn(&config, Box::new(analysis::report_fn));
     let evaluator = bare::evaluation::Evaluator::spawn(&config, fitness_function);
     (observer, evaluator)
@@ -114,13 +137,15 @@ fn prepare_push<C: 'static + Cpu<'static>>(
     config: &Config,
 ) -> (Observer<push::Creature>, push::evaluation::Evaluator<C>) {
     let fitness_function: FitnessFn<push::Creature, Sketches, Config> =
-        fitness_functions::get_fitness_function(&config.fitness.function);
+        fitness_functions::get_fitness_function(&config)
+            .expect("The fitness function should have been validated by Config::from_path");
     let observer: Observer<push::Creature> =
         Observer::spawn(&config, Box::new(analysis::report_fn));
     let evaluator = push::evaluation::Evaluator::spawn(&config, fitness_function);
     (observer, evaluator)
 }
 
+#[derive(Default)]
 pub struct CreatureDominanceOrd;
 
 impl DominanceOrd<bare::Creature> for CreatureDominanceOrd {
@@ -143,11 +168,15 @@ impl DominanceOrd<&bare::Creature> for CreatureDominanceOrd {}
 
 impl DominanceOrd<&push::Creature> for CreatureDominanceOrd {}
 
-pub fn run(mut config: Config) {
-    let _ = loader::falcon_loader::load_from_path(&mut config, true)
-        .expect("Failed to load binary image");
-    config.roper.parse_register_patterns();
-    init_soup(&mut config).expect("Failed to initialize the soup");
+/// Loads the binary and completes the parts of the config that depend on
+/// it, then runs the job. Errors in those parts are returned before any
+/// island is launched.
+pub fn run(mut config: Config) -> Result<(), Error> {
+    let _ = loader::falcon_loader::load_from_path(&mut config, true)?;
+    config.roper.parse_stages();
+    config.roper.compute_target_distances()?;
+    fitness_functions::enable_requirements(&mut config);
+    init_soup(&mut config)?;
 
     use unicorn::Arch::*;
     match config.roper.arch {
@@ -159,124 +188,62 @@ pub fn run(mut config: Config) {
         M68K => launch::<unicorn::CpuM68K<'_>>(config),
         _ => unimplemented!("architecture unimplemented"),
     }
+    Ok(())
 }
 
-pub fn launch<C: 'static + Cpu<'static>>(config: Config) {
-    match config.selection {
-        Selection::Tournament => {
-            // TODO: Refactor this!!
-            let num_islands = config.num_islands;
-            if config.roper.use_push {
-                let pier: Arc<Pier<push::Creature>> = Arc::new(Pier::new(config.num_islands));
-                let mut handles = Vec::new();
-                let mut rng = hash_seed_rng(&config.random_seed);
-                for i in 0..num_islands {
-                    let mut config = config.clone();
-                    config.island_id = i;
-                    config.set_data_directory();
-                    config.random_seed = rng.gen::<u64>();
-                    let (observer, evaluator) = prepare_push(&config);
-                    let pier = pier.clone();
-                    let h = spawn(move || {
-                        let mut world =
-                            Tournament::<push::evaluation::Evaluator<C>, push::Creature>::new(
-                                &config, observer, evaluator, pier,
-                            );
-                        while crate::keep_going() {
-                            world = world.evolve();
-                        }
-                    });
-                    handles.push(h);
-                }
-                for h in handles.into_iter() {
-                    h.join().expect("Failed to join thread");
-                }
-            } else {
-                let pier: Arc<Pier<bare::Creature>> = Arc::new(Pier::new(config.num_islands));
-                let mut handles = Vec::new();
-                let mut rng = hash_seed_rng(&config.random_seed);
-                for i in 0..num_islands {
-                    let mut config = config.clone();
-                    config.island_id = i;
-                    config.set_data_directory();
-                    config.random_seed = rng.gen::<u64>();
-                    let (observer, evaluator) = prepare_bare(&config);
-                    let pier = pier.clone();
-                    let h = spawn(move || {
-                        let mut world =
-                            Tournament::<bare::evaluation::Evaluator<C>, bare::Creature>::new(
-                                &config, observer, evaluator, pier,
-                            );
-                        while crate::keep_going() {
-                            world = world.evolve();
-                        }
-                    });
-                    handles.push(h);
-                }
-                for h in handles.into_iter() {
-                    h.join().expect("Failed to join thread");
-                }
+/// Runs the selection scheme named in the config on the given creature
+/// type. MAP-Elites keeps a single archive, and so runs on one island.
+macro_rules! launch_engine {
+    ($config:expr, $creature:ty, $evaluator:ty, $prepare:expr) => {
+        match $config.selection {
+            Selection::Tournament => {
+                engine::launch::<Tournament<$evaluator, $creature>, _, _, _>(&$config, $prepare);
             }
-        }
-        Selection::Roulette => {
-            let (observer, evaluator) = prepare_bare(&config);
-            let mut world = Roulette::<
-                bare::evaluation::Evaluator<C>,
-                bare::Creature,
-                CreatureDominanceOrd,
-            >::new(&config, observer, evaluator, CreatureDominanceOrd);
-            while crate::keep_going() {
-                world = world.evolve();
+            Selection::Roulette => {
+                engine::launch::<Roulette<$evaluator, $creature, CreatureDominanceOrd>, _, _, _>(
+                    &$config, $prepare,
+                );
             }
-        }
-        Selection::Metropolis => {
-            let (observer, evaluator) = prepare_bare(&config);
-            let mut world = Metropolis::<bare::evaluation::Evaluator<C>, bare::Creature>::new(
-                &config, observer, evaluator,
-            );
-            while crate::keep_going() {
-                world = world.evolve();
+            Selection::Metropolis => {
+                engine::launch::<Metropolis<$evaluator, $creature>, _, _, _>(&$config, $prepare);
+            }
+            Selection::Lexicase => {
+                engine::launch::<Lexicase<$evaluator, $creature>, _, _, _>(&$config, $prepare);
+            }
+            Selection::Alps => {
+                engine::launch::<Alps<$evaluator, $creature>, _, _, _>(&$config, $prepare);
+            }
+            Selection::MapElites => {
+                let (observer, evaluator) = $prepare(&$config);
+                let mut world = MapElites::<$evaluator, $creature>::new(
+                    &$config,
+                    observer,
+                    evaluator,
+                    Box::new(novelty::elite_descriptor::<$creature>),
+                );
+                while crate::keep_going() {
+                    world = world.evolve();
+                }
+                world.dump_archive();
             }
         }
-        Selection::Lexicase => unimplemented!("Probably needs an overhaul"),
-        // Selection::Lexicase => {
-        //     let fitness_function: FitnessFn<bare::Creature, Sketches, Config> =
-        //         match config.fitness.function.as_str() {
-        //             "register_pattern" => Box::new(bare::evaluation::register_pattern_ff),
-        //             "register_conjunction" => Box::new(bare::evaluation::register_conjunction_ff),
-        //             "code_coverage" => Box::new(bare::evaluation::code_coverage_ff),
-        //             s => unimplemented!("No such fitness function as {}", s),
-        //         };
-        //     let pier: Arc<Pier<bare::Creature>> = Arc::new(Pier::new(config.num_islands));
-        //     let evaluator = bare::evaluation::BareEvaluator::spawn(&config, fitness_function);
-        //     let observer = Observer::spawn(
-        //         &config,
-        //         Box::new(bare::analysis::lexicase::report_fn),
-        //         CreatureDominanceOrd,
-        //     );
-        //     let mut cases = config
-        //         .roper
-        //         .register_pattern
-        //         .as_ref()
-        //         .map(|rp| {
-        //             let pattern: RegisterPattern = rp.into();
-        //             pattern
-        //                 .features()
-        //                 .into_iter()
-        //                 .map(lexi::Task::Reg)
-        //                 .collect::<Vec<lexi::Task>>()
-        //         })
-        //         .expect("No register pattern specified");
-        //     cases.push(lexi::Task::UniqExec(2));
-        //     cases.push(lexi::Task::UniqExec(3));
-        //     log::info!("Register Feature cases: {:#x?}", cases);
-        //     let mut world =
-        //         Lexicase::<lexi::Task, bare::evaluation::BareEvaluator<C>, bare::Creature>::new(
-        //             &config, observer, evaluator, pier, cases,
-        //         );
-        //     while crate::keep_going() {
-        //         world = world.evolve();
-        //     }
-        // }
+    };
+}
+
+pub fn launch<C: 'static + Cpu<'static>>(config: Config) {
+    if config.roper.use_push {
+        launch_engine!(
+            config,
+            push::Creature,
+            push::evaluation::Evaluator<C>,
+            prepare_push::<C>
+        )
+    } else {
+        launch_engine!(
+            config,
+            bare::Creature,
+            bare::evaluation::Evaluator<C>,
+            prepare_bare::<C>
+        )
     }
 }
@@ -0,0 +1,299 @@
+use std::collections::VecDeque;
+
+use hashbrown::{HashMap, HashSet};
+
+use crate::configure::{BehaviourFeature, Config, EliteFeature, NoveltyConfig};
+use crate::emulator::profiler::{HasProfile, Profile};
+use crate::util::architecture::word_size_in_bytes;
+
+/// A description of what a creature did, abstracted from its profile, so that
+/// creatures can be compared by behaviour rather than by genotype.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Behaviour {
+    /// The final register values of each run, ordered by register name.
+    registers: Vec<u64>,
+    /// The (address, byte) pairs written over all runs.
+    writes: HashSet<(u64, u8)>,
+    /// The entry points of the basic blocks visited over all runs.
+    blocks: HashSet<u64>,
+}
+
+impl Behaviour {
+    pub fn from_profile(profile: &Profile, features: &[BehaviourFeature]) -> Self {
+        let mut behaviour = Self::default();
+        for feature in features {
+            match feature {
+                BehaviourFeature::Registers => {
+                    for state in profile.registers.iter() {
+                        let mut regs = state.0.iter().collect::<Vec<_>>();
+                        regs.sort_by(|(a, _), (b, _)| a.cmp(b));
+                        behaviour
+                            .registers
+                            .extend(regs.into_iter().filter_map(|(_, vals)| vals.first()));
+                    }
+                }
+                BehaviourFeature::MemoryWrites => {
+                    for data in profile.memory_writes.iter() {
+                        behaviour.writes.extend(data.bytes());
+                    }
+                }
+                BehaviourFeature::Blocks => {
+                    for path in profile.paths.iter() {
+                        behaviour.blocks.extend(path.iter().map(|b| b.entry));
+                    }
+                }
+            }
+        }
+        behaviour
+    }
+
+    /// The sum of the distances between the two behaviours' features, each
+    /// of which lies between 0 and 1. Registers of `word_bits` bits are
+    /// compared by the mean proportion of differing bits, and the sets by
+    /// Jaccard distance.
+    pub fn distance(&self, other: &Self, word_bits: u32) -> f64 {
+        register_distance(&self.registers, &other.registers, word_bits)
+            + jaccard_distance(&self.writes, &other.writes)
+            + jaccard_distance(&self.blocks, &other.blocks)
+    }
+}
+
+fn register_distance(a: &[u64], b: &[u64], word_bits: u32) -> f64 {
+    let len = a.len().max(b.len());
+    if len == 0 {
+        return 0.0;
+    }
+    let mask = if word_bits >= 64 {
+        !0
+    } else {
+        (1_u64 << word_bits) - 1
+    };
+    let differing_bits = a
+        .iter()
+        .zip(b.iter())
+        .map(|(x, y)| ((x ^ y) & mask).count_ones() as f64 / word_bits as f64)
+        .sum::<f64>();
+    // registers missing from one or the other count as entirely different
+    let missing = (len - a.len().min(b.len())) as f64;
+    (differing_bits + missing) / len as f64
+}
+
+fn jaccard_distance<T: Eq + std::hash::Hash>(a: &HashSet<T>, b: &HashSet<T>) -> f64 {
+    let union = a.union(b).count();
+    if union == 0 {
+        return 0.0;
+    }
+    let intersection = a.intersection(b).count();
+    1.0 - intersection as f64 / union as f64
+}
+
+/// Keeps the behaviours that were novel when they were first seen, together
+/// with a window of the most recently evaluated behaviours, which stands in
+/// for the current population. A behaviour's novelty is its mean distance
+/// from its k nearest neighbours among the two. Behaviours are keyed by the
+/// tag of the creature that exhibited them, so that a creature found in both
+/// is counted only once, and one that is assessed again replaces its old
+/// record rather than adding to it.
+pub struct NoveltyArchive {
+    config: NoveltyConfig,
+    archive: VecDeque<(u64, Behaviour)>,
+    recent: VecDeque<(u64, Behaviour)>,
+    window_size: usize,
+    word_bits: u32,
+}
+
+impl NoveltyArchive {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            config: config.novelty.clone().unwrap_or_default(),
+            archive: VecDeque::new(),
+            recent: VecDeque::new(),
+            window_size: config.pop_size,
+            word_bits: word_size_in_bytes(config.roper.arch, config.roper.mode) as u32 * 8,
+        }
+    }
+
+    pub fn features(&self) -> &[BehaviourFeature] {
+        &self.config.behaviour
+    }
+
+    pub fn len(&self) -> usize {
+        self.archive.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.archive.is_empty()
+    }
+
+    /// The mean distance from `behaviour`, exhibited by the creature tagged
+    /// `tag`, to its k nearest neighbours among the archive and the recent
+    /// behaviours of other creatures.
+    pub fn sparseness(&self, tag: u64, behaviour: &Behaviour) -> f64 {
+        let neighbours = self
+            .archive
+            .iter()
+            .chain(self.recent.iter())
+            .filter(|(t, _)| *t != tag)
+            .map(|(t, b)| (*t, b))
+            .collect::<HashMap<u64, &Behaviour>>();
+        let mut distances = neighbours
+            .values()
+            .map(|other| behaviour.distance(other, self.word_bits))
+            .collect::<Vec<f64>>();
+        if distances.is_empty() {
+            return 0.0;
+        }
+        distances.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+        let k = self.config.k.max(1).min(distances.len());
+        distances[..k].iter().sum::<f64>() / k as f64
+    }
+
+    /// Measures the sparseness of `behaviour`, exhibited by the creature
+    /// tagged `tag`, then records it, adding it to the archive if it is
+    /// sparse enough. Returns the sparseness.
+    pub fn assess(&mut self, tag: u64, behaviour: Behaviour) -> f64 {
+        let sparseness = self.sparseness(tag, &behaviour);
+        // The very first behaviour has nothing to be compared with, but is
+        // as novel as anything could be.
+        let archived = self.archive.iter().any(|(t, _)| *t == tag);
+        if !archived && (sparseness >= self.config.archive_threshold || self.archive.is_empty()) {
+            self.archive.push_back((tag, behaviour.clone()));
+            if self.archive.len() > self.config.archive_size {
+                self.archive.pop_front();
+            }
+        }
+        self.recent.retain(|(t, _)| *t != tag);
+        self.recent.push_back((tag, behaviour));
+        if self.recent.len() > self.window_size {
+            self.recent.pop_front();
+        }
+        sparseness
+    }
+}
+
+/// Measures the creature along each of the MAP-Elites dimensions given in the
+/// config. A creature without a profile is placed at the origin.
+pub fn elite_descriptor<P: HasProfile>(creature: &P, config: &Config) -> Vec<f64> {
+    let dims = &config.map_elites.dimensions;
+    let profile = match creature.profile() {
+        Some(p) => p,
+        None => return vec![0.0; dims.len()],
+    };
+    let runs = profile.cpu_errors.len().max(1) as f64;
+    dims.iter()
+        .map(|dim| match dim.feature {
+            EliteFeature::GadgetsExecuted => {
+                profile
+                    .gadgets_executed
+                    .iter()
+                    .map(|g| g.len())
+                    .sum::<usize>() as f64
+                    / runs
+            }
+            EliteFeature::BytesWritten => {
+                profile.memory_writes.iter().map(|m| m.len()).sum::<usize>() as f64 / runs
+            }
+            EliteFeature::RegistersControlled => {
+                let patterns = config.roper.register_patterns();
+                if patterns.is_empty() {
+                    return 0.0;
+                }
+                profile
+                    .registers
+                    .iter()
+                    .enumerate()
+                    .map(|(idx, state)| {
+                        let pattern = &patterns[idx % patterns.len()];
+                        pattern.0.len() - pattern.incorrect_register_states(state).len()
+                    })
+                    .sum::<usize>() as f64
+                    / runs
+            }
+            EliteFeature::Crashed => {
+                profile.cpu_errors.iter().filter(|e| e.is_some()).count() as f64 / runs
+            }
+            EliteFeature::RetCount => {
+                profile.total_gadget_transitions(config.roper.chain_mode) as f64 / runs
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn regs(registers: Vec<u64>) -> Behaviour {
+        Behaviour {
+            registers,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_behaviour_distance() {
+        let a = regs(vec![0, 0]);
+        assert_eq!(a.distance(&a, 64), 0.0);
+        assert!((a.distance(&regs(vec![0, !0]), 64) - 0.5).abs() < std::f64::EPSILON);
+        assert!((a.distance(&regs(vec![0]), 64) - 0.5).abs() < std::f64::EPSILON);
+        // on a 32-bit machine, flipping the low 32 bits is a complete change
+        assert!((a.distance(&regs(vec![0, 0xffff_ffff]), 32) - 0.5).abs() < std::f64::EPSILON);
+
+        let mut b = a.clone();
+        b.blocks.insert(1);
+        b.blocks.insert(2);
+        let mut c = a.clone();
+        c.blocks.insert(2);
+        assert!((b.distance(&c, 64) - 0.5).abs() < std::f64::EPSILON);
+    }
+
+    #[test]
+    fn test_novelty_archive() {
+        let config = Config {
+            pop_size: 2,
+            novelty: Some(NoveltyConfig {
+                k: 1,
+                archive_threshold: 0.5,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let mut archive = NoveltyArchive::new(&config);
+        assert_eq!(archive.assess(1, regs(vec![0])), 0.0);
+        assert_eq!(archive.len(), 1);
+        // identical behaviour is not novel, and isn't archived
+        assert_eq!(archive.assess(2, regs(vec![0])), 0.0);
+        assert_eq!(archive.len(), 1);
+        // a behaviour with every bit flipped is maximally distant
+        let flipped = !0 >> (64 - archive.word_bits);
+        assert_eq!(archive.assess(3, regs(vec![flipped])), 1.0);
+        assert_eq!(archive.len(), 2);
+    }
+
+    #[test]
+    fn test_novelty_counts_each_creature_once() {
+        let config = Config {
+            pop_size: 4,
+            novelty: Some(NoveltyConfig {
+                k: 2,
+                archive_threshold: 0.5,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let mut archive = NoveltyArchive::new(&config);
+        let flipped = !0 >> (64 - archive.word_bits);
+        // The first creature is both archived and recent, but is only one
+        // neighbour, so the mean over two neighbours takes in the third.
+        archive.assess(1, regs(vec![0]));
+        archive.assess(2, regs(vec![flipped]));
+        assert_eq!(archive.sparseness(3, &regs(vec![0])), 0.5);
+
+        // Reassessing a creature replaces its record, and it isn't its own
+        // neighbour.
+        assert_eq!(archive.assess(1, regs(vec![0])), 1.0);
+        assert_eq!(archive.assess(1, regs(vec![0])), 1.0);
+        assert_eq!(archive.len(), 2);
+        assert_eq!(archive.recent.len(), 2);
+    }
+}
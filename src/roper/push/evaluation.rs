@@ -11,14 +11,18 @@ use crate::fitness::Weighted;
 use crate::ontogenesis::{Develop, FitnessFn};
 use crate::roper::push;
 use crate::roper::push::{register_pattern_to_push_args, Creature, MachineState};
-use crate::roper::Sketches;
+use crate::roper::{Fitness, Sketches};
 use crate::util;
+use crate::util::cache::EvaluationCache;
 
 pub struct Evaluator<C: Cpu<'static> + 'static> {
     config: Arc<Config>,
     hatchery: Hatchery<C>,
     sketches: Sketches,
     fitness_fn: Box<FitnessFn<push::Creature, Sketches, Config>>,
+    /// A push program's development is the payloads it produced, and their
+    /// profile.
+    cache: EvaluationCache<(Vec<Vec<u64>>, Profile), Fitness<'static>>,
 }
 
 impl<C: 'static + Cpu<'static>> Evaluator<C> {
@@ -46,11 +50,13 @@ impl<C: 'static + Cpu<'static>> Evaluator<C> {
         );
 
         let sketches = Sketches::new(&config);
+        let cache = EvaluationCache::new(&config);
         Self {
             config: Arc::new(config),
             hatchery,
             sketches,
             fitness_fn: Box::new(fitness_fn),
+            cache,
         }
     }
 }
@@ -66,69 +72,112 @@ pub fn problem_to_payload(
 }
 
 impl<C: 'static + Cpu<'static>> Develop<push::Creature> for Evaluator<C> {
-    fn develop(&self, mut creature: push::Creature) -> push::Creature {
-        // TODO: make this a bit more generic, so we don't assume we're doing a register pattern task
-        // for now, this doesn't matter -- we haven't defined any other kinds of tasks
-        if creature.fitness.is_none() {
-            let mut payloads = Vec::new();
-            // TODO: Refactor and generalize to other problem types.
-            for register_pattern in self.config.roper.register_patterns() {
-                let payload =
-                    problem_to_payload(&creature, register_pattern, self.config.push_vm.max_steps);
-                payloads.push(payload);
-            }
-
-            // TODO refactor bare roper in a similar fashion. just send the payload,
-            // not the whole creature.
-            let mut used_payloads = Vec::new();
-            for payload in payloads.into_iter() {
-                if !payload.is_empty() {
-                    let profile = self
-                        .hatchery
-                        .execute(payload.clone(), None)
-                        .expect("Failed to evaluate creature");
-                    creature.add_profile(profile);
-                    used_payloads.push(payload);
-                } else {
-                    // this will mark the profile as non-executable
-                    let profile = Profile::default();
-                    debug_assert!(!profile.executable);
-                    used_payloads.push(payload);
-                    creature.payloads = used_payloads;
-                    creature.add_profile(profile);
-                    return creature;
-                }
-            }
-            creature.payloads = used_payloads;
-            log::debug!(
-                "Finished developing creature. profile: {:#x?}",
-                creature.profile
-            );
-        }
-        creature
+    fn develop(&self, creature: push::Creature) -> push::Creature {
+        self.development_pipeline(std::iter::once(creature))
+            .pop()
+            .expect("Lost a creature in development")
     }
 
     fn apply_fitness_function(&mut self, mut creature: push::Creature) -> push::Creature {
+        // A static fitness restored from the cache needn't be recomputed, but
+        // the operators that produced the creature are still credited.
+        if self.cache.caches_fitness() && creature.fitness.is_some() {
+            let fitness = creature.scalar_fitness(self.config.fitness.priority());
+            creature.chromosome.credit_operators(fitness, &self.config);
+            return creature;
+        }
         let profile = creature
             .profile()
             .expect("Attempted to apply fitness function to undeveloped creature");
-        if !profile.executable {
+        let creature = if !profile.executable {
             let mut fitness = Weighted::new(&self.config.fitness.weighting);
             fitness.declare_failure();
             creature.set_fitness(fitness);
             creature
         } else {
-            (self.fitness_fn)(creature, &mut self.sketches, self.config.clone())
-        }
+            let mut creature = (self.fitness_fn)(creature, &mut self.sketches, self.config.clone());
+            let fitness = creature.scalar_fitness(self.config.fitness.priority());
+            creature.chromosome.credit_operators(fitness, &self.config);
+            creature
+        };
+        self.cache
+            .store_fitness(creature.chromosome(), creature.fitness.clone());
+        creature
     }
 
     fn development_pipeline<I: 'static + Iterator<Item = push::Creature> + Send>(
         &self,
         inbound: I,
     ) -> Vec<push::Creature> {
-        inbound
-            .into_iter()
-            .map(|c| self.develop(c))
-            .collect::<Vec<push::Creature>>()
+        // TODO: make this a bit more generic, so we don't assume we're doing a register pattern task
+        // for now, this doesn't matter -- we haven't defined any other kinds of tasks
+        let mut creatures = inbound.collect::<Vec<push::Creature>>();
+        let mut jobs = Vec::new();
+        // For each creature, the number of payloads to be executed, and
+        // whether it produced an empty payload, which ends its development.
+        let mut plans = Vec::new();
+        for creature in creatures.iter_mut() {
+            if creature.fitness.is_some() {
+                plans.push(None);
+                continue;
+            }
+            if let Some(((payloads, profile), fitness)) = self.cache.restore(creature.chromosome())
+            {
+                creature.payloads = payloads;
+                creature.set_profile(profile);
+                if let Some(fitness) = fitness {
+                    creature.set_fitness(fitness);
+                }
+                plans.push(None);
+                continue;
+            }
+            // TODO: Refactor and generalize to other problem types.
+            let mut used_payloads = Vec::new();
+            let mut failed = false;
+            for register_pattern in self.config.roper.register_patterns() {
+                let payload =
+                    problem_to_payload(creature, register_pattern, self.config.push_vm.max_steps);
+                if payload.is_empty() {
+                    used_payloads.push(payload);
+                    failed = true;
+                    break;
+                }
+                jobs.push((payload.clone(), None));
+                used_payloads.push(payload);
+            }
+            let executed = used_payloads.len() - failed as usize;
+            creature.payloads = used_payloads;
+            plans.push(Some((executed, failed)));
+        }
+
+        let mut profiles = self
+            .hatchery
+            .execute_batch(jobs)
+            .expect("Failed to evaluate creatures")
+            .into_iter();
+        for (creature, plan) in creatures.iter_mut().zip(plans.into_iter()) {
+            if let Some((executed, failed)) = plan {
+                for _ in 0..executed {
+                    creature.add_profile(profiles.next().expect("Missing profile"));
+                }
+                if failed {
+                    // this will mark the profile as non-executable
+                    let profile = Profile::default();
+                    debug_assert!(!profile.executable);
+                    creature.add_profile(profile);
+                }
+                if let Some(ref profile) = creature.profile {
+                    self.cache.store(
+                        creature.chromosome(),
+                        (creature.payloads.clone(), profile.clone()),
+                    );
+                }
+                log::debug!(
+                    "Finished developing creature. profile: {:#x?}",
+                    creature.profile
+                );
+            }
+        }
+        creatures
     }
 }
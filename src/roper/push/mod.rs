@@ -287,57 +287,66 @@ static NON_CONSTANT_OPS: [Op; 109] = [
     Op::Dup(Type::Code),
 ];
 
-fn random_ops<R: Rng>(rng: &mut R, config: &Config) -> Vec<Op> {
-    let mut ops = Vec::new();
-
-    let count = rng.gen_range(config.push_vm.min_len, config.push_vm.max_len);
-
+fn function_names() -> Vec<String> {
     let memory = get_static_memory_image();
     let program = memory.il_program.as_ref().unwrap();
-    let function_names: Vec<String> = program
+    program
         .functions()
         .into_iter()
         .map(il::Function::name)
-        .collect();
-
-    for _ in 0..count {
-        if rng.gen_range(0.0, 1.0) < config.push_vm.literal_rate {
-            match rng.gen_range(0, 2) {
-                0 => {
-                    // functions
-                    let f = function_names
-                        .choose(rng)
-                        .expect("Failed to choose a random function")
-                        .clone();
-                    ops.push(Op::FuncNamed(f))
-                }
-                1 => {
-                    // addresses
-                    let addr = config
-                        .roper
-                        .soup
-                        .as_ref()
-                        .expect("No soup?!")
-                        .choose(rng)
-                        .expect("Failed to choose word from soup.");
-                    ops.push(Op::WordConst(*addr))
-                }
-                // 3 => {
-                //     // float
-                //     ops.push(Op::FloatConst(rng.gen::<f64>().to_bits()))
-                // }
-                _ => unreachable!("nope"),
-            };
-        } else {
-            let op = NON_CONSTANT_OPS
+        .collect()
+}
+
+fn random_literal<R: Rng>(rng: &mut R, config: &Config, function_names: &[String]) -> Op {
+    match rng.gen_range(0, 2) {
+        0 => {
+            // functions
+            let f = function_names
                 .choose(rng)
-                .expect("Failed to choose random op")
+                .expect("Failed to choose a random function")
                 .clone();
-            ops.push(op)
+            Op::FuncNamed(f)
+        }
+        1 => {
+            // addresses
+            let addr = config
+                .roper
+                .soup
+                .as_ref()
+                .expect("No soup?!")
+                .choose(rng)
+                .expect("Failed to choose word from soup.");
+            Op::WordConst(*addr)
         }
+        // 3 => {
+        //     // float
+        //     ops.push(Op::FloatConst(rng.gen::<f64>().to_bits()))
+        // }
+        _ => unreachable!("nope"),
+    }
+}
+
+fn random_instruction<R: Rng>(rng: &mut R) -> Op {
+    NON_CONSTANT_OPS
+        .choose(rng)
+        .expect("Failed to choose random op")
+        .clone()
+}
+
+fn random_op<R: Rng>(rng: &mut R, config: &Config, function_names: &[String]) -> Op {
+    if rng.gen_range(0.0, 1.0) < config.push_vm.literal_rate {
+        random_literal(rng, config, function_names)
+    } else {
+        random_instruction(rng)
     }
+}
 
-    ops
+fn random_ops<R: Rng>(rng: &mut R, config: &Config) -> Vec<Op> {
+    let count = rng.gen_range(config.push_vm.min_len, config.push_vm.max_len);
+    let function_names = function_names();
+    (0..count)
+        .map(|_| random_op(rng, config, &function_names))
+        .collect()
 }
 
 // TODO: add data and control flow graph operations, using Falcon.
@@ -1304,7 +1313,7 @@ pub mod creature {
     use rand::thread_rng;
 
     use crate::emulator::profiler::{HasProfile, Profile};
-    use crate::evolution::{Genome, LinearChromosome, Mutation, Phenome};
+    use crate::evolution::{adaptation, Genome, LinearChromosome, Mutation, Phenome};
     use crate::roper::Fitness;
     use crate::util;
     use crate::util::random::hash_seed_rng;
@@ -1313,23 +1322,55 @@ pub mod creature {
 
     #[derive(Clone, Debug, Copy, Hash, Serialize, Deserialize)]
     pub enum OpMutation {
+        /// Replaces the op with a literal, at `push_vm.literal_rate`, or
+        /// else an instruction.
         RandomOp,
+        RandomLiteral,
+        RandomInstruction,
+        /// Nudges a word constant by up to 0xff in either direction. Any
+        /// other op is replaced at random, as by `RandomOp`.
+        WordNudge,
     }
 
     impl Mutation for OpMutation {
         type Allele = Op;
 
-        // TODO: should we have a pointer to the config here?
-        // we might want to take certain parameters into consideration, like the literal rate
+        fn operators() -> Vec<Self> {
+            use OpMutation::*;
+            vec![RandomOp, RandomLiteral, RandomInstruction, WordNudge]
+        }
+
         fn mutate_point(allele: &mut Self::Allele, config: &Config) -> Self {
             let mut rng = thread_rng();
-            let new_allele = random_ops(&mut rng, config).pop().unwrap();
-            *allele = new_allele;
-            OpMutation::RandomOp
+            let mutation =
+                adaptation::choose_operator(config, &mut rng).unwrap_or(OpMutation::RandomOp);
+            match (mutation, &*allele) {
+                (OpMutation::RandomLiteral, _) => {
+                    *allele = random_literal(&mut rng, config, &function_names());
+                    mutation
+                }
+                (OpMutation::RandomInstruction, _) => {
+                    *allele = random_instruction(&mut rng);
+                    mutation
+                }
+                (OpMutation::WordNudge, Op::WordConst(w)) => {
+                    let delta = rng.gen_range(1, 0x100);
+                    *allele = Op::WordConst(if rng.gen::<bool>() {
+                        w.wrapping_add(delta)
+                    } else {
+                        w.wrapping_sub(delta)
+                    });
+                    mutation
+                }
+                _ => {
+                    *allele = random_op(&mut rng, config, &function_names());
+                    OpMutation::RandomOp
+                }
+            }
         }
     }
 
-    #[derive(Clone, Serialize)]
+    #[derive(Clone, Serialize, Deserialize)]
     pub struct Creature {
         pub chromosome: LinearChromosome<Op, OpMutation>,
         pub tag: u64,
@@ -1342,7 +1383,6 @@ pub mod creature {
         // table held in Config would be just fine. We can always get a pointer to Config
         // in scope.
         pub profile: Option<Profile>,
-        #[serde(borrow)]
         pub fitness: Option<Fitness<'static>>,
         pub front: Option<usize>,
         pub num_offspring: usize,
@@ -1416,6 +1456,9 @@ pub mod creature {
                     parent_names: vec![],
                     name: util::name::random(4, rng.gen::<u64>()),
                     generation: 0,
+                    gene_ages: vec![],
+                    mutation_params: None,
+                    parent_fitness: None,
                 },
                 tag: rng.gen::<u64>(),
                 payloads: vec![],
@@ -1436,7 +1479,13 @@ pub mod creature {
                 .iter()
                 .map(|x| &x.chromosome)
                 .collect::<Vec<&LinearChromosome<_, _>>>();
-            let chromosome = LinearChromosome::crossover(&parents, config);
+            let mut chromosome = LinearChromosome::crossover(&parents, config);
+            chromosome.parent_fitness = mates
+                .iter()
+                .filter_map(|m| m.scalar_fitness(config.fitness.priority()))
+                .fold(None, |best: Option<f64>, f| {
+                    Some(best.map_or(f, |b| b.min(f)))
+                });
             Self {
                 chromosome,
                 tag: thread_rng().gen::<u64>(),
@@ -1454,6 +1503,10 @@ pub mod creature {
             self.chromosome.mutate(config)
         }
 
+        fn mutation_rate(&self, config: &Config) -> f64 {
+            self.chromosome.mutation_rate(config)
+        }
+
         fn incr_num_offspring(&mut self, _n: usize) {
             self.num_offspring += 1
         }
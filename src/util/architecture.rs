@@ -173,6 +173,22 @@ pub fn endian(arch: Arch, mode: Mode) -> Endian {
     }
 }
 
+/// The name of the register conventionally used as the frame pointer.
+pub fn frame_pointer_register(arch: Arch, mode: Mode) -> Option<&'static str> {
+    use Arch::*;
+    use Mode::*;
+
+    match (arch, mode) {
+        (X86, MODE_16) => Some("BP"),
+        (X86, MODE_32) => Some("EBP"),
+        (X86, MODE_64) => Some("RBP"),
+        (ARM, THUMB) => Some("R7"),
+        (ARM, _) => Some("R11"),
+        (ARM64, _) => Some("X29"),
+        (_, _) => None,
+    }
+}
+
 pub fn word_size_in_bytes(arch: Arch, mode: Mode) -> usize {
     use Arch::*;
     use Mode::*;
@@ -0,0 +1,240 @@
+use std::collections::VecDeque;
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use hashbrown::HashMap;
+
+use crate::configure::Config;
+
+/// A bounded cache that forgets its oldest entries first. It can be shared
+/// between threads, and keeps count of its hits and misses.
+pub struct Cache<K: Hash + Eq + Clone, V: Clone> {
+    capacity: usize,
+    inner: Mutex<CacheInner<K, V>>,
+    hits: AtomicUsize,
+    misses: AtomicUsize,
+}
+
+struct CacheInner<K, V> {
+    map: HashMap<K, V>,
+    order: VecDeque<K>,
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> Cache<K, V> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            inner: Mutex::new(CacheInner {
+                map: HashMap::new(),
+                order: VecDeque::new(),
+            }),
+            hits: AtomicUsize::new(0),
+            misses: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn get(&self, key: &K) -> Option<V> {
+        let res = self
+            .inner
+            .lock()
+            .expect("poisoned cache")
+            .map
+            .get(key)
+            .cloned();
+        if res.is_some() {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+        }
+        res
+    }
+
+    /// Applies `f` to the entry for `key`, if there is one, without counting
+    /// it as a lookup.
+    pub fn update<F: FnOnce(&mut V)>(&self, key: &K, f: F) {
+        if let Some(v) = self.inner.lock().expect("poisoned cache").map.get_mut(key) {
+            f(v)
+        }
+    }
+
+    pub fn insert(&self, key: K, value: V) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut inner = self.inner.lock().expect("poisoned cache");
+        if inner.map.insert(key.clone(), value).is_none() {
+            inner.order.push_back(key);
+            while inner.order.len() > self.capacity {
+                if let Some(old) = inner.order.pop_front() {
+                    inner.map.remove(&old);
+                }
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.lock().expect("poisoned cache").map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn hits(&self) -> usize {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    pub fn misses(&self) -> usize {
+        self.misses.load(Ordering::Relaxed)
+    }
+
+    pub fn hit_rate(&self) -> f64 {
+        let hits = self.hits();
+        let lookups = hits + self.misses();
+        if lookups == 0 {
+            0.0
+        } else {
+            hits as f64 / lookups as f64
+        }
+    }
+}
+
+/// What is remembered of a chromosome's evaluation: whatever its
+/// development produced, and its fitness, which is only reused if the
+/// fitness function is static (i.e., `fitness.dynamic` is false).
+#[derive(Clone)]
+struct CachedEvaluation<D, F> {
+    development: D,
+    fitness: Option<F>,
+}
+
+/// How often, in cache lookups, to log the cache's statistics.
+const REPORT_INTERVAL: usize = 10_000;
+
+/// A cache of evaluations, keyed by a hash of the chromosome, so that
+/// creatures with the same genes needn't be emulated again. Its capacity is
+/// `fitness.cache_size`, and a capacity of zero disables it.
+pub struct EvaluationCache<D: Clone, F: Clone> {
+    cache: Cache<u64, CachedEvaluation<D, F>>,
+    island_id: usize,
+    dynamic: bool,
+}
+
+fn chromosome_key<A: Hash>(chromosome: &[A]) -> u64 {
+    let mut h = fnv::FnvHasher::default();
+    chromosome.hash(&mut h);
+    h.finish()
+}
+
+impl<D: Clone, F: Clone> EvaluationCache<D, F> {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            cache: Cache::new(config.fitness.cache_size),
+            island_id: config.island_id,
+            dynamic: config.fitness.dynamic,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.cache.capacity > 0
+    }
+
+    /// Whether fitnesses are cached along with the development.
+    pub fn caches_fitness(&self) -> bool {
+        self.is_enabled() && !self.dynamic
+    }
+
+    /// Looks up the development of the chromosome, along with its fitness if
+    /// the fitness function is static.
+    pub fn restore<A: Hash>(&self, chromosome: &[A]) -> Option<(D, Option<F>)> {
+        if !self.is_enabled() {
+            return None;
+        }
+        let cached = self.cache.get(&chromosome_key(chromosome));
+        let lookups = self.cache.hits() + self.cache.misses();
+        if lookups % REPORT_INTERVAL == 0 {
+            log::info!(
+                "Island {} evaluation cache: {} entries, {} hits, {} misses ({:.3} hit rate)",
+                self.island_id,
+                self.cache.len(),
+                self.cache.hits(),
+                self.cache.misses(),
+                self.cache.hit_rate(),
+            );
+        }
+        let dynamic = self.dynamic;
+        cached.map(|c| (c.development, c.fitness.filter(|_| !dynamic)))
+    }
+
+    /// Remembers the development of the chromosome. Its fitness is added by
+    /// `store_fitness`, once it has been scored.
+    pub fn store<A: Hash>(&self, chromosome: &[A], development: D) {
+        if !self.is_enabled() {
+            return;
+        }
+        self.cache.insert(
+            chromosome_key(chromosome),
+            CachedEvaluation {
+                development,
+                fitness: None,
+            },
+        );
+    }
+
+    pub fn store_fitness<A: Hash>(&self, chromosome: &[A], fitness: Option<F>) {
+        if self.caches_fitness() {
+            self.cache
+                .update(&chromosome_key(chromosome), |c| c.fitness = fitness);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_cache() {
+        let cache = Cache::new(2);
+        cache.insert(1, "one");
+        cache.insert(2, "two");
+        assert_eq!(cache.get(&1), Some("one"));
+        cache.insert(3, "three");
+        assert_eq!(cache.len(), 2);
+        // the oldest entry is evicted first
+        assert_eq!(cache.get(&1), None);
+        assert_eq!(cache.get(&3), Some("three"));
+        cache.update(&3, |v| *v = "drei");
+        assert_eq!(cache.get(&3), Some("drei"));
+        assert_eq!(cache.hits(), 3);
+        assert_eq!(cache.misses(), 1);
+        assert!((cache.hit_rate() - 0.75).abs() < std::f64::EPSILON);
+    }
+
+    #[test]
+    fn test_evaluation_cache() {
+        let mut config = Config::default();
+        config.fitness.cache_size = 10;
+        config.fitness.dynamic = false;
+        let cache: EvaluationCache<&str, f64> = EvaluationCache::new(&config);
+        assert_eq!(cache.restore(&[1, 2, 3]), None);
+        cache.store(&[1, 2, 3], "profile");
+        assert_eq!(cache.restore(&[1, 2, 3]), Some(("profile", None)));
+        cache.store_fitness(&[1, 2, 3], Some(0.5));
+        assert_eq!(cache.restore(&[1, 2, 3]), Some(("profile", Some(0.5))));
+        assert_eq!(cache.restore(&[3, 2, 1]), None);
+
+        // a dynamic fitness is recomputed, so only the development is kept
+        config.fitness.dynamic = true;
+        let cache: EvaluationCache<&str, f64> = EvaluationCache::new(&config);
+        cache.store(&[1, 2, 3], "profile");
+        cache.store_fitness(&[1, 2, 3], Some(0.5));
+        assert_eq!(cache.restore(&[1, 2, 3]), Some(("profile", None)));
+
+        config.fitness.cache_size = 0;
+        let cache: EvaluationCache<&str, f64> = EvaluationCache::new(&config);
+        cache.store(&[1, 2, 3], "profile");
+        assert_eq!(cache.restore(&[1, 2, 3]), None);
+    }
+}
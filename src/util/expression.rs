@@ -0,0 +1,311 @@
+//! The language of the fitness `weighting` and `priority` expressions.
+//!
+//! Expressions are evaluated by fasteval, with the following additions to
+//! its built-in functions (`min`, `max`, `abs`, `log`, etc.):
+//!
+//! - every factor emitted by the fitness function, by name;
+//! - `mean_<factor>` and `stdev_<factor>`, giving the mean and standard
+//!   deviation of that factor over the island's observation window;
+//! - `island`, the island's identifier, and `generation` (or `E`), the
+//!   current epoch;
+//! - `clamp(x, lo, hi)` and `sigmoid(x)`;
+//! - `rank(<factor>)`, the proportion of the observation window with a
+//!   strictly lower score on that factor. Zero means the creature is
+//!   unbeaten on that factor.
+//!
+//! Until an island has published its population statistics, `mean_`,
+//! `stdev_` and `rank` all evaluate to zero.
+use std::collections::BTreeMap;
+use std::sync::RwLock;
+
+use crate::error::Error;
+
+const CONTEXT_VARIABLES: [&str; 3] = ["island", "generation", "E"];
+
+const FASTEVAL_FUNCTIONS: [&str; 23] = [
+    "min", "max", "abs", "log", "round", "floor", "ceil", "sign", "int", "e", "pi", "sin", "cos",
+    "tan", "asin", "acos", "atan", "sinh", "cosh", "tanh", "asinh", "acosh", "atanh",
+];
+
+const CUSTOM_FUNCTIONS: [&str; 3] = ["clamp", "sigmoid", "rank"];
+
+/// Summary statistics for each factor over an island's observation window.
+#[derive(Debug, Clone, Default)]
+pub struct PopulationStats {
+    pub mean: BTreeMap<String, f64>,
+    pub stdev: BTreeMap<String, f64>,
+    sorted: BTreeMap<String, Vec<f64>>,
+}
+
+impl PopulationStats {
+    pub fn from_scores<'a, 'b: 'a, I>(scores: I) -> Self
+    where
+        I: Iterator<Item = &'a BTreeMap<&'b str, f64>>,
+    {
+        let mut samples: BTreeMap<String, Vec<f64>> = BTreeMap::new();
+        for map in scores {
+            for (k, v) in map.iter() {
+                samples.entry(k.to_string()).or_default().push(*v);
+            }
+        }
+        let mut stats = Self::default();
+        for (k, mut vals) in samples.into_iter() {
+            let n = vals.len() as f64;
+            let mean = vals.iter().sum::<f64>() / n;
+            let var = vals.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / n;
+            vals.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+            stats.mean.insert(k.clone(), mean);
+            stats.stdev.insert(k.clone(), var.sqrt());
+            stats.sorted.insert(k, vals);
+        }
+        stats
+    }
+
+    /// The proportion of the samples of `factor` that are strictly less than `value`.
+    pub fn rank(&self, factor: &str, value: f64) -> Option<f64> {
+        self.sorted.get(factor).map(|vals| {
+            if vals.is_empty() {
+                0.0
+            } else {
+                let below = vals.iter().take_while(|v| **v < value).count();
+                below as f64 / vals.len() as f64
+            }
+        })
+    }
+}
+
+/// The latest statistics for each island, indexed by island id.
+static POPULATION_STATS: RwLock<Vec<Option<PopulationStats>>> = RwLock::new(Vec::new());
+
+pub fn publish_population_stats(island: usize, stats: PopulationStats) {
+    let mut all = POPULATION_STATS.write().expect("poisoned population stats");
+    if all.len() <= island {
+        all.resize(island + 1, None);
+    }
+    all[island] = Some(stats);
+}
+
+fn population_stat<F: FnOnce(&PopulationStats) -> Option<f64>>(island: usize, f: F) -> Option<f64> {
+    POPULATION_STATS
+        .read()
+        .expect("poisoned population stats")
+        .get(island)
+        .and_then(|s| s.as_ref())
+        .and_then(f)
+}
+
+/// Splits the expression into its identifiers, noting for each whether it is
+/// called as a function. Numeric literals, like `1e-5`, are skipped.
+fn identifiers(expr: &str) -> Vec<(String, bool)> {
+    let chars = expr.chars().collect::<Vec<char>>();
+    let mut res = vec![];
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_ascii_digit() || c == '.' {
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '.') {
+                i += 1;
+            }
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            let ident = chars[start..i].iter().collect::<String>();
+            let mut j = i;
+            while j < chars.len() && chars[j].is_whitespace() {
+                j += 1;
+            }
+            res.push((ident, j < chars.len() && chars[j] == '('));
+        } else {
+            i += 1;
+        }
+    }
+    res
+}
+
+/// The names of the variables the expression refers to, including the
+/// arguments of `rank`.
+pub fn variables(expr: &str) -> Vec<String> {
+    let mut vars = identifiers(expr)
+        .into_iter()
+        .filter(|(_, is_call)| !is_call)
+        .map(|(name, _)| name)
+        .collect::<Vec<String>>();
+    vars.sort_unstable();
+    vars.dedup();
+    vars
+}
+
+/// Whether the expression refers to the population statistics, and so may
+/// change in value while the scores stay the same.
+pub fn depends_on_population(expr: &str) -> bool {
+    identifiers(&rewrite_ranks(expr)).iter().any(|(name, _)| {
+        ["mean_", "stdev_", "rank_"]
+            .iter()
+            .any(|p| name.starts_with(p))
+    })
+}
+
+/// Rewrites each `rank(factor)` as the variable `rank_factor`, which the
+/// namespace can look up, since fasteval passes only the values of a
+/// function's arguments, and not their names.
+fn rewrite_ranks(expr: &str) -> String {
+    let mut res = String::with_capacity(expr.len());
+    let mut rest = expr;
+    while let Some(pos) = rest.find("rank") {
+        let (before, after) = rest.split_at(pos);
+        res.push_str(before);
+        let preceded_by_ident = before
+            .chars()
+            .last()
+            .map_or(false, |c| c.is_ascii_alphanumeric() || c == '_');
+        let inner = after["rank".len()..].trim_start();
+        if !preceded_by_ident && inner.starts_with('(') {
+            if let Some(close) = inner.find(')') {
+                let arg = inner[1..close].trim();
+                if !arg.is_empty() && arg.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+                    res.push_str("rank_");
+                    res.push_str(arg);
+                    rest = &inner[close + 1..];
+                    continue;
+                }
+            }
+        }
+        res.push_str("rank");
+        rest = &after["rank".len()..];
+    }
+    res.push_str(rest);
+    res
+}
+
+/// Checks that every identifier in the expression is a known function, a
+/// context variable, or one of the `factors` (possibly prefixed with
+/// `mean_`, `stdev_`, or wrapped in `rank`).
+pub fn validate(expr: &str, factors: &[&str]) -> Result<(), Error> {
+    let is_factor = |name: &str| {
+        factors.contains(&name)
+            || ["mean_", "stdev_", "rank_"]
+                .iter()
+                .any(|p| name.starts_with(p) && factors.contains(&&name[p.len()..]))
+    };
+    let mut unknown = identifiers(&rewrite_ranks(expr))
+        .into_iter()
+        .filter(|(name, is_call)| {
+            if *is_call {
+                !FASTEVAL_FUNCTIONS.contains(&name.as_str())
+                    && !CUSTOM_FUNCTIONS.contains(&name.as_str())
+            } else {
+                !CONTEXT_VARIABLES.contains(&name.as_str()) && !is_factor(name)
+            }
+        })
+        .map(|(name, is_call)| if is_call { format!("{}()", name) } else { name })
+        .collect::<Vec<String>>();
+    unknown.dedup();
+    if unknown.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::Parsing(format!(
+            "Unknown identifiers in fitness expression {:?}: {}. The factors available are: {}",
+            expr,
+            unknown.join(", "),
+            factors.join(", ")
+        )))
+    }
+}
+
+/// Evaluates the expression with the given scores, in the context of the
+/// given island.
+pub fn evaluate(expr: &str, scores: &BTreeMap<&str, f64>, island: usize) -> Result<f64, Error> {
+    let expr = rewrite_ranks(expr);
+    let mut ns = |name: &str, args: Vec<f64>| -> Option<f64> {
+        match (name, args.as_slice()) {
+            ("clamp", [x, lo, hi]) => Some(x.max(*lo).min(*hi)),
+            ("sigmoid", [x]) => Some(1.0 / (1.0 + (-x).exp())),
+            ("island", []) => Some(island as f64),
+            ("generation", []) | ("E", []) => Some(crate::get_epoch_counter() as f64),
+            (name, []) => {
+                if let Some(v) = scores.get(name) {
+                    return Some(*v);
+                }
+                // Before any statistics have been gathered, every statistic is zero,
+                // and everyone is unbeaten.
+                if let Some(factor) = name.strip_prefix("mean_") {
+                    population_stat(island, |s| s.mean.get(factor).copied()).or(Some(0.0))
+                } else if let Some(factor) = name.strip_prefix("stdev_") {
+                    population_stat(island, |s| s.stdev.get(factor).copied()).or(Some(0.0))
+                } else if let Some(factor) = name.strip_prefix("rank_") {
+                    let value = *scores.get(factor)?;
+                    population_stat(island, |s| s.rank(factor, value)).or(Some(0.0))
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        }
+    };
+    fasteval::ez_eval(&expr, &mut ns).map_err(|e| Error::Parsing(format!("{:?}", e)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_validate() {
+        let factors = ["register_error", "crash_count"];
+        assert!(validate("register_error + 10 * crash_count", &factors).is_ok());
+        assert!(validate(
+            "clamp(register_error, 0, 1e-5) + sigmoid(mean_crash_count) + rank(crash_count)",
+            &factors
+        )
+        .is_ok());
+        assert!(validate("max(register_error, E) + island", &factors).is_ok());
+
+        let err = validate("registr_error + wibble(crash_count) + rank(nope)", &factors)
+            .expect_err("should have failed");
+        let msg = format!("{:?}", err);
+        assert!(msg.contains("registr_error"));
+        assert!(msg.contains("wibble()"));
+        assert!(msg.contains("rank_nope"));
+    }
+
+    #[test]
+    fn test_rewrite_ranks() {
+        assert_eq!(rewrite_ranks("1 + rank( foo ) * 2"), "1 + rank_foo * 2");
+        assert_eq!(rewrite_ranks("frank(foo)"), "frank(foo)");
+        assert_eq!(rewrite_ranks("rank_foo"), "rank_foo");
+    }
+
+    #[test]
+    fn test_evaluate_before_stats() {
+        let scores: BTreeMap<&str, f64> = [("foo", 2.0)].iter().cloned().collect();
+        // no statistics are ever published for this island
+        let island = 1_000_000;
+        let res = evaluate(
+            "foo + mean_foo + 10 * stdev_foo + rank(foo)",
+            &scores,
+            island,
+        )
+        .expect("failed to evaluate");
+        assert_eq!(res, 2.0);
+    }
+
+    #[test]
+    fn test_depends_on_population() {
+        assert!(!depends_on_population("foo + clamp(bar, 0, 1)"));
+        assert!(depends_on_population("foo / (1 + stdev_foo)"));
+        assert!(depends_on_population("rank( foo )"));
+    }
+
+    #[test]
+    fn test_population_rank() {
+        let a: BTreeMap<&str, f64> = [("foo", 1.0)].iter().cloned().collect();
+        let b: BTreeMap<&str, f64> = [("foo", 3.0)].iter().cloned().collect();
+        let stats = PopulationStats::from_scores(vec![&a, &b].into_iter());
+        assert_eq!(stats.mean["foo"], 2.0);
+        assert_eq!(stats.stdev["foo"], 1.0);
+        assert_eq!(stats.rank("foo", 2.0), Some(0.5));
+        assert_eq!(stats.rank("foo", 1.0), Some(0.0));
+    }
+}
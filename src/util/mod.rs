@@ -1,9 +1,11 @@
 pub mod architecture;
 pub mod bitwise;
+pub mod cache;
 pub mod count_min_sketch;
 pub mod distance;
 pub mod dump;
 pub mod entropy;
+pub mod expression;
 pub mod five_letter_words;
 pub mod ldd;
 pub mod levy_flight;